@@ -0,0 +1,205 @@
+//! Utilities for A2M and A3M alignments.
+//!
+//! In A2M and A3M alignments, uppercase letters and `-` are match states, and
+//! lowercase letters and `.` are insert states. A2M pads insertions with `.`
+//! so that all rows have the same length, while A3M omits the padding. The
+//! functions in this module expand A3M rows into A2M columns and separate
+//! match-state columns from insert-state columns.
+
+use crate::error::{Error, Result};
+use crate::fasta::{SequenceData, build_sequence_data, read_fasta_records};
+use std::io::BufRead;
+
+/// Returns `true` if the byte is an insert state (a lowercase letter or `.`).
+#[inline]
+#[must_use]
+pub const fn is_insert_char(byte: u8) -> bool {
+    byte.is_ascii_lowercase() || byte == b'.'
+}
+
+/// Parses an A2M or A3M file and returns the sequence data, with insertions
+/// expanded into their own columns. Leading `#` comment lines are skipped.
+pub fn parse_a3m(
+    mut reader: impl BufRead + Send,
+    keep_sequence: &[String],
+) -> Result<SequenceData> {
+    while reader.fill_buf().map_err(Error::ReadInput)?.first() == Some(&b'#') {
+        reader
+            .read_until(b'\n', &mut Vec::new())
+            .map_err(Error::ReadInput)?;
+    }
+
+    let (headers, sequences) = read_fasta_records(reader)?;
+    let sequences = expand_insertions(&sequences, &headers)?;
+    build_sequence_data(headers, sequences, keep_sequence)
+}
+
+/// Expands A2M or A3M rows so that every insertion has its own columns,
+/// padding insertions with `.` (i.e., converts the rows to A2M). Returns an
+/// error if the rows don't have the same number of match states.
+pub fn expand_insertions(sequences: &[Vec<u8>], headers: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+    // For each row, the insertions before each match state (and after the last
+    // one) and the match states themselves.
+    let mut rows: Vec<(Vec<Vec<u8>>, Vec<u8>)> = Vec::with_capacity(sequences.len());
+    for seq in sequences {
+        let mut insertions = vec![Vec::new()];
+        let mut matches = Vec::new();
+        for &byte in seq {
+            if is_insert_char(byte) {
+                if byte != b'.'
+                    && let Some(insertion) = insertions.last_mut()
+                {
+                    insertion.push(byte);
+                }
+            } else {
+                matches.push(byte);
+                insertions.push(Vec::new());
+            }
+        }
+        rows.push((insertions, matches));
+    }
+
+    let match_count = rows.first().map_or(0, |(_, matches)| matches.len());
+    if let Some(idx) = rows
+        .iter()
+        .position(|(_, matches)| matches.len() != match_count)
+    {
+        return Err(Error::A3mParse(format!(
+            "sequence '{}' has {} match states, but the first sequence has {match_count}",
+            String::from_utf8_lossy(&headers[idx]),
+            rows[idx].1.len()
+        )));
+    }
+
+    let mut widths = vec![0usize; match_count + 1];
+    for (insertions, _) in &rows {
+        for (width, insertion) in widths.iter_mut().zip(insertions) {
+            *width = (*width).max(insertion.len());
+        }
+    }
+
+    let expanded_length = match_count + widths.iter().sum::<usize>();
+    Ok(rows
+        .into_iter()
+        .map(|(insertions, matches)| {
+            let mut row = Vec::with_capacity(expanded_length);
+            for (k, (insertion, &width)) in insertions.iter().zip(&widths).enumerate() {
+                row.extend_from_slice(insertion);
+                row.resize(row.len() + width - insertion.len(), b'.');
+                if let Some(&byte) = matches.get(k) {
+                    row.push(byte);
+                }
+            }
+            row
+        })
+        .collect())
+}
+
+/// Returns a mask of the match-state columns of an A2M alignment.
+#[must_use]
+pub fn find_match_columns(sequences: &[Vec<u8>]) -> Vec<bool> {
+    let seq_len = sequences.iter().map(Vec::len).max().unwrap_or(0);
+    let mut match_columns = vec![false; seq_len];
+    for seq in sequences {
+        for (pos, &byte) in seq.iter().enumerate() {
+            if !is_insert_char(byte) {
+                match_columns[pos] = true;
+            }
+        }
+    }
+    match_columns
+}
+
+/// Keeps only the columns for which `mask` is `true`.
+#[must_use]
+pub fn select_columns(sequences: &[Vec<u8>], mask: &[bool]) -> Vec<Vec<u8>> {
+    sequences
+        .iter()
+        .map(|seq| {
+            seq.iter()
+                .zip(mask)
+                .filter(|&(_, &keep)| keep)
+                .map(|(&byte, _)| byte)
+                .collect()
+        })
+        .collect()
+}
+
+/// Converts an A2M row to A3M by removing the `.` padding of insertions.
+#[must_use]
+pub fn to_a3m(seq: &[u8]) -> Vec<u8> {
+    seq.iter().copied().filter(|&b| b != b'.').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A3M: &str = "#A3M comment
+>q
+ACDEF
+>s1
+ACdeDEF
+>s2
+-CDEFgf
+>s3
+AC.DE-
+";
+
+    fn rows(rows: &[&str]) -> Vec<Vec<u8>> {
+        rows.iter().map(|row| row.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn expands_insertions_into_their_own_columns() {
+        let data = parse_a3m(A3M.as_bytes(), &[]).unwrap();
+        assert_eq!(data.headers.len(), 4);
+        assert_eq!(
+            data.sequences,
+            rows(&["AC..DEF..", "ACdeDEF..", "-C..DEFgf", "AC..DE-.."])
+        );
+        assert_eq!(data.longest_length, 9);
+    }
+
+    #[test]
+    fn selects_match_columns() {
+        let sequences = parse_a3m(A3M.as_bytes(), &[]).unwrap().sequences;
+        let mask = find_match_columns(&sequences);
+        assert_eq!(
+            mask,
+            [true, true, false, false, true, true, true, false, false]
+        );
+        assert_eq!(
+            select_columns(&sequences, &mask),
+            rows(&["ACDEF", "ACDEF", "-CDEF", "ACDE-"])
+        );
+    }
+
+    #[test]
+    fn round_trips_through_a3m_rows() {
+        let sequences = parse_a3m(A3M.as_bytes(), &[]).unwrap().sequences;
+        let a3m: Vec<Vec<u8>> = sequences.iter().map(|seq| to_a3m(seq)).collect();
+        assert_eq!(a3m, rows(&["ACDEF", "ACdeDEF", "-CDEFgf", "ACDE-"]));
+        let headers = rows(&["q", "s1", "s2", "s3"]);
+        assert_eq!(expand_insertions(&a3m, &headers).unwrap(), sequences);
+    }
+
+    #[test]
+    fn keeps_a2m_rows_aligned() {
+        let a2m = rows(&["AC..DE", "ACgtDE", "AC.aDE"]);
+        let headers = rows(&["a", "b", "c"]);
+        assert_eq!(
+            expand_insertions(&a2m, &headers).unwrap(),
+            rows(&["AC..DE", "ACgtDE", "ACa.DE"])
+        );
+    }
+
+    #[test]
+    fn rejects_rows_with_different_match_counts() {
+        let text = ">a\nACDE\n>b\nACDEF\n";
+        assert!(matches!(
+            parse_a3m(text.as_bytes(), &[]),
+            Err(Error::A3mParse(message)) if message.contains("'b'")
+        ));
+    }
+}
@@ -4,26 +4,103 @@
 //! alignments and the operations needed to analyze gap patterns and compute
 //! alignment metrics.
 
-use crate::bitops::{bitwise_or_assign, count_bits, set_bit};
+use crate::bitops::Bitset;
+use crate::weights::Weights;
 use std::collections::{HashMap, HashSet};
 
+/// Characters treated as gaps by default.
+pub const DEFAULT_GAP_CHARS: &[u8] = b"-.";
+
+/// Returns `true` if the byte is one of the default gap characters.
 #[inline]
 pub const fn is_gap_char(byte: u8) -> bool {
     byte == b'-' || byte == b'.'
 }
 
+/// Set of characters that are treated as gaps (or missing data) when computing
+/// the gap patterns of an alignment.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GapChars([bool; 256]);
+
+impl GapChars {
+    /// Creates a gap alphabet from the given characters.
+    #[must_use]
+    pub fn new(chars: &[u8]) -> Self {
+        let mut gap_chars = Self([false; 256]);
+        gap_chars.insert(chars);
+        gap_chars
+    }
+
+    /// Adds characters to the gap alphabet.
+    pub fn insert(&mut self, chars: &[u8]) {
+        for &byte in chars {
+            self.0[usize::from(byte)] = true;
+        }
+    }
+
+    /// Returns `true` if the byte is a gap character.
+    #[inline]
+    #[must_use]
+    pub const fn contains(&self, byte: u8) -> bool {
+        self.0[byte as usize]
+    }
+
+    /// Returns the gap characters in ascending byte order.
+    #[must_use]
+    pub fn chars(&self) -> Vec<u8> {
+        (0..=u8::MAX).filter(|&byte| self.contains(byte)).collect()
+    }
+}
+
+impl Default for GapChars {
+    fn default() -> Self {
+        Self::new(DEFAULT_GAP_CHARS)
+    }
+}
+
+impl std::fmt::Debug for GapChars {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("GapChars")
+            .field(&String::from_utf8_lossy(&self.chars()))
+            .finish()
+    }
+}
+
+impl std::fmt::Display for GapChars {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.chars()))
+    }
+}
+
+impl std::str::FromStr for GapChars {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("at least one gap character must be given".to_string());
+        }
+        if let Some(c) = s
+            .chars()
+            .find(|c| !c.is_ascii() || c.is_ascii_whitespace() || c.is_ascii_control())
+        {
+            return Err(format!("invalid gap character '{c}'"));
+        }
+        Ok(Self::new(s.as_bytes()))
+    }
+}
+
 /// Holds the current state of set data during optimization.
 #[derive(Clone)]
 pub struct SetData {
-    pub sets: Vec<Vec<u8>>,
-    pub gaps: Vec<Vec<u8>>,
+    pub sets: Vec<Bitset>,
+    pub gaps: Vec<Bitset>,
     pub translation: Vec<usize>,
     pub excluded: HashSet<usize>,
 }
 
 impl SetData {
     #[must_use]
-    pub fn new(sets: Vec<Vec<u8>>, gaps: Vec<Vec<u8>>, num_sequences: usize) -> Self {
+    pub fn new(sets: Vec<Bitset>, gaps: Vec<Bitset>, num_sequences: usize) -> Self {
         Self {
             sets,
             gaps,
@@ -40,6 +117,10 @@ pub struct AlignmentMetrics {
     pub gap_free_columns: usize,
     pub alignment_area: usize,
     pub alignment_length: usize,
+    /// Total weight of the sequences times the total weight of the gap-free
+    /// columns, in integer weight units (see [`Weights::scale`]). Equal to
+    /// `alignment_area` if neither sequences nor columns are weighted.
+    pub weighted_area: usize,
 }
 
 impl AlignmentMetrics {
@@ -55,20 +136,32 @@ impl AlignmentMetrics {
             gap_free_columns,
             alignment_area,
             alignment_length,
+            weighted_area: alignment_area,
         }
     }
+
+    /// Sets the weighted alignment area.
+    #[must_use]
+    pub const fn with_weighted_area(mut self, weighted_area: usize) -> Self {
+        self.weighted_area = weighted_area;
+        self
+    }
 }
 
 /// Creates a gap matrix from sequences where `gap_matrix[seq][col]` is `true`
 /// if sequence `seq` has a gap at column `col`.
 #[must_use]
-pub fn create_gap_matrix(sequences: &[Vec<u8>], alignment_length: usize) -> Vec<Vec<bool>> {
+pub fn create_gap_matrix(
+    sequences: &[Vec<u8>],
+    alignment_length: usize,
+    gap_chars: &GapChars,
+) -> Vec<Vec<bool>> {
     sequences
         .iter()
         .map(|seq| {
             let mut row = vec![true; alignment_length];
             for (col_idx, &byte) in seq.iter().enumerate() {
-                if !is_gap_char(byte) {
+                if !gap_chars.contains(byte) {
                     row[col_idx] = false;
                 }
             }
@@ -78,13 +171,13 @@ pub fn create_gap_matrix(sequences: &[Vec<u8>], alignment_length: usize) -> Vec<
 }
 
 /// Creates gap pattern sets from a gap matrix, grouping columns by their gap pattern
-/// and creating bit-packed representations for efficient manipulation.
+/// and creating bitset representations for efficient manipulation.
 #[must_use]
 pub fn create_sets(
     gap_matrix: &[Vec<bool>],
     keep_indices: &HashSet<usize>,
     alignment_length: usize,
-) -> (Vec<Vec<u8>>, Vec<Vec<u8>>, Vec<bool>) {
+) -> (Vec<Bitset>, Vec<Bitset>, Vec<bool>) {
     let num_seqs = gap_matrix.len();
     let mut keep_pattern = vec![false; alignment_length];
     for &keep_seq_idx in keep_indices {
@@ -95,17 +188,11 @@ pub fn create_sets(
         }
     }
 
-    let bytes_per_col = num_seqs.div_ceil(8);
-    let mut flat_sets = vec![0u8; alignment_length * bytes_per_col];
-    let mut has_gap_col = vec![false; alignment_length];
-
+    let mut column_sets = vec![Bitset::new(num_seqs); alignment_length];
     for (seq_idx, row) in gap_matrix.iter().enumerate() {
-        let byte_offset = seq_idx / 8;
-        let bit_mask = 1u8 << (seq_idx % 8);
         for (col_idx, &is_gap) in row.iter().enumerate() {
             if is_gap && !keep_pattern[col_idx] {
-                flat_sets[col_idx * bytes_per_col + byte_offset] |= bit_mask;
-                has_gap_col[col_idx] = true;
+                column_sets[col_idx].insert(seq_idx);
             }
         }
     }
@@ -113,50 +200,295 @@ pub fn create_sets(
     let mut sets = Vec::new();
     let mut gaps = Vec::new();
 
-    for (col_idx, &has_gap) in has_gap_col.iter().enumerate() {
-        if has_gap {
-            let start = col_idx * bytes_per_col;
-            let end = start + bytes_per_col;
-            sets.push(flat_sets[start..end].to_vec());
-
-            let mut gap_vec = vec![0u8; alignment_length.div_ceil(8)];
-            set_bit(&mut gap_vec, col_idx);
-            gaps.push(gap_vec);
+    for (col_idx, set) in column_sets.into_iter().enumerate() {
+        if set.any() {
+            sets.push(set);
+            gaps.push(Bitset::from_indices(alignment_length, [col_idx]));
         }
     }
 
     (sets, gaps, keep_pattern)
 }
 
+/// Number of gaps a column may contain among the retained sequences and still
+/// count towards the alignment area. By default, only gap-free columns count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GapTolerance {
+    /// At most this many gaps.
+    Count(usize),
+    /// At most this fraction of the retained sequences may have a gap.
+    Fraction(f64),
+}
+
+impl Default for GapTolerance {
+    fn default() -> Self {
+        Self::Count(0)
+    }
+}
+
+impl std::fmt::Display for GapTolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Count(1) => write!(f, "1 gap"),
+            Self::Count(count) => write!(f, "{count} gaps"),
+            Self::Fraction(fraction) => write!(f, "{fraction} of the retained sequences"),
+        }
+    }
+}
+
+impl GapTolerance {
+    /// Returns `true` if only gap-free columns count towards the area.
+    #[must_use]
+    pub fn is_strict(self) -> bool {
+        match self {
+            Self::Count(count) => count == 0,
+            Self::Fraction(fraction) => fraction <= 0.0,
+        }
+    }
+
+    /// Returns the number of gaps a column may contain if `retained` sequences
+    /// are retained. This never decreases as `retained` grows.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn allowed_gaps(self, retained: usize) -> usize {
+        match self {
+            Self::Count(count) => count,
+            Self::Fraction(fraction) => (fraction * retained as f64 + 1e-9).floor() as usize,
+        }
+    }
+
+    /// Returns `true` if a column with `gaps` gaps among `retained` sequences
+    /// counts towards the area. Columns without any residue never count.
+    #[must_use]
+    pub fn allows(self, gaps: usize, retained: usize) -> bool {
+        gaps < retained && gaps <= self.allowed_gaps(retained)
+    }
+}
+
+/// Hard limits on the number of excluded sequences, which the heuristic and
+/// the refinement never exceed. When several limits are set, the tightest one
+/// applies. By default, the number of excluded sequences isn't limited.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ExclusionLimit {
+    /// Smallest number of sequences to retain.
+    pub min_retained: Option<usize>,
+    /// Largest number of sequences to exclude.
+    pub max_excluded: Option<usize>,
+    /// Largest fraction of the sequences to exclude.
+    pub max_excluded_fraction: Option<f64>,
+}
+
+impl ExclusionLimit {
+    /// Returns the largest number of sequences that may be excluded from
+    /// `num_sequences` sequences, or `None` if the number isn't limited.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn max_excluded(&self, num_sequences: usize) -> Option<usize> {
+        [
+            self.min_retained
+                .map(|count| num_sequences.saturating_sub(count)),
+            self.max_excluded,
+            self.max_excluded_fraction
+                .map(|fraction| (fraction * num_sequences as f64 + 1e-9).floor() as usize),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+}
+
+/// A group of sequences from which at most `max_excluded` may be excluded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupLimit {
+    pub members: Bitset,
+    pub max_excluded: usize,
+}
+
+/// Hard limits on the excluded sequences, resolved for an alignment: the
+/// largest number of sequences that may be excluded in total, and from each
+/// group. By default, nothing is limited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExclusionBudget {
+    pub max_excluded: Option<usize>,
+    pub groups: Vec<GroupLimit>,
+}
+
+impl ExclusionBudget {
+    /// Returns `true` if no limit applies.
+    #[must_use]
+    pub fn is_unlimited(&self) -> bool {
+        self.max_excluded.is_none() && self.groups.is_empty()
+    }
+
+    /// Returns the budget with at most `count` excluded sequences in total.
+    #[must_use]
+    pub fn with_max_excluded(&self, count: usize) -> Self {
+        Self {
+            max_excluded: Some(self.max_excluded.map_or(count, |max| max.min(count))),
+            groups: self.groups.clone(),
+        }
+    }
+
+    /// Returns what is left of the budget once the sequences in `excluded` are
+    /// excluded.
+    #[must_use]
+    pub fn remaining(&self, excluded: &Bitset) -> Self {
+        Self {
+            max_excluded: self
+                .max_excluded
+                .map(|max| max.saturating_sub(excluded.count_ones())),
+            groups: self
+                .groups
+                .iter()
+                .map(|group| GroupLimit {
+                    members: group.members.clone(),
+                    max_excluded: group
+                        .max_excluded
+                        .saturating_sub(group.members.count_intersection(excluded)),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns `true` if the sequences in `set` may be excluded.
+    #[must_use]
+    pub fn admits(&self, set: &Bitset) -> bool {
+        self.max_excluded.is_none_or(|max| set.count_ones() <= max)
+            && self
+                .groups
+                .iter()
+                .all(|group| group.members.count_intersection(set) <= group.max_excluded)
+    }
+
+    /// Returns `true` if the sequences in the union of `a` and `b` may be
+    /// excluded.
+    #[must_use]
+    pub fn admits_union(&self, a: &Bitset, b: &Bitset) -> bool {
+        self.max_excluded.is_none_or(|max| a.count_union(b) <= max)
+            && self
+                .groups
+                .iter()
+                .all(|group| a.count_union_within(b, &group.members) <= group.max_excluded)
+    }
+}
+
+/// Distinct gap patterns of the columns of an alignment, used when columns may
+/// contain gaps (see [`GapTolerance`]). Unlike the sets returned by
+/// [`create_sets`], these also cover the columns in which kept sequences have
+/// gaps, since such columns can still count towards the area.
+#[derive(Debug, Clone)]
+pub struct ColumnPatterns {
+    /// Sequences with a gap in the columns of each pattern.
+    pub sets: Vec<Bitset>,
+    /// Number of columns with each pattern.
+    pub counts: Vec<usize>,
+    /// Total weight of the columns with each pattern.
+    pub weights: Vec<usize>,
+    /// Number of columns without gaps.
+    pub gap_free_count: usize,
+    /// Total weight of the columns without gaps.
+    pub gap_free_weight: usize,
+}
+
+impl ColumnPatterns {
+    /// Groups the columns of a gap matrix by gap pattern.
+    #[must_use]
+    pub fn new(
+        gap_matrix: &[Vec<bool>],
+        alignment_length: usize,
+        column_weights: &Weights,
+    ) -> Self {
+        let (column_sets, column_gaps, _) =
+            create_sets(gap_matrix, &HashSet::new(), alignment_length);
+        let gap_columns = column_sets.len();
+
+        let mut index: HashMap<Bitset, usize> = HashMap::new();
+        let mut sets = Vec::new();
+        let mut counts = Vec::new();
+        let mut weights = Vec::new();
+        for (set, gap) in column_sets.into_iter().zip(&column_gaps) {
+            let idx = *index.entry(set).or_insert_with_key(|set| {
+                sets.push(set.clone());
+                counts.push(0);
+                weights.push(0);
+                sets.len() - 1
+            });
+            counts[idx] += 1;
+            weights[idx] += column_weights.of_set(gap);
+        }
+
+        let gap_free_weight = column_weights.total() - weights.iter().sum::<usize>();
+        Self {
+            sets,
+            counts,
+            weights,
+            gap_free_count: alignment_length - gap_columns,
+            gap_free_weight,
+        }
+    }
+
+    /// Returns the number and total weight of the columns that count towards
+    /// the area once the sequences in `excluded` are removed, leaving `retained`
+    /// sequences.
+    #[must_use]
+    pub fn counted_columns(
+        &self,
+        excluded: &Bitset,
+        retained: usize,
+        tolerance: GapTolerance,
+    ) -> (usize, usize) {
+        let mut count = self.gap_free_count;
+        let mut weight = self.gap_free_weight;
+        for (i, set) in self.sets.iter().enumerate() {
+            if tolerance.allows(set.count_difference(excluded), retained) {
+                count += self.counts[i];
+                weight += self.weights[i];
+            }
+        }
+        (count, weight)
+    }
+}
+
 /// Joins congruent (identical) sets and removes sets that cannot improve the alignment.
-/// Returns the number of gap columns that were removed.
+/// `alignment_area` is the (weighted) area to improve upon. Returns the number
+/// of gap columns that were removed.
 pub fn congruent_set_joining(
-    sets: &mut Vec<Vec<u8>>,
-    gaps: &mut Vec<Vec<u8>>,
+    sets: &mut Vec<Bitset>,
+    gaps: &mut Vec<Bitset>,
     alignment_area: usize,
-    sequence_count: usize,
-    alignment_length: usize,
+    weights: &Weights,
+    column_weights: &Weights,
 ) -> usize {
     let mut gap_columns = 0;
     let mut to_remove = HashSet::new();
+    let total_weight = weights.total();
+    let total_column_weight = column_weights.total();
 
     for (i, set) in sets.iter().enumerate() {
-        let size_i = count_bits(set);
-        if alignment_area > alignment_length * (sequence_count - size_i) {
+        let weight_i = weights.of_set(set);
+        if alignment_area > total_column_weight * (total_weight - weight_i) {
             to_remove.insert(i);
             gap_columns += 1;
         }
     }
 
-    let mut pattern_to_idx: HashMap<&[u8], usize> = HashMap::new();
+    let mut pattern_to_idx: HashMap<&Bitset, usize> = HashMap::new();
     for i in (0..sets.len()).rev() {
         if to_remove.contains(&i) {
             continue;
         }
 
-        if let Some(&last_idx) = pattern_to_idx.get(&sets[i] as &[u8]) {
+        if let Some(&last_idx) = pattern_to_idx.get(&sets[i]) {
             let gap_i = gaps[i].clone();
-            bitwise_or_assign(&mut gaps[last_idx], &gap_i);
+            gaps[last_idx].union_with(&gap_i);
             to_remove.insert(i);
         } else {
             pattern_to_idx.insert(&sets[i], i);
@@ -169,33 +501,28 @@ pub fn congruent_set_joining(
 }
 
 /// Propagates gap column benefits from subsets to their supersets.
-pub fn subset_joining(sets: &[Vec<u8>], gaps: &mut [Vec<u8>]) {
-    let mut merges: Vec<(usize, Vec<u8>)> = Vec::new();
+pub fn subset_joining(sets: &[Bitset], gaps: &mut [Bitset]) {
+    let mut merges: Vec<(usize, Bitset)> = Vec::new();
 
     for i in 0..sets.len() {
         for j in 0..sets.len() {
             if i == j {
                 continue;
             }
-            if is_subset_of(&sets[j], &sets[i]) {
+            if sets[j].is_subset(&sets[i]) {
                 merges.push((i, gaps[j].clone()));
             }
         }
     }
 
     for (target_idx, source_gap) in merges {
-        bitwise_or_assign(&mut gaps[target_idx], &source_gap);
+        gaps[target_idx].union_with(&source_gap);
     }
 }
 
-#[inline]
-fn is_subset_of(a: &[u8], b: &[u8]) -> bool {
-    a.iter().zip(b).all(|(&x, &y)| (x & y) == x)
-}
-
 fn remove_indices_from_parallel_vecs(
-    sets: &mut Vec<Vec<u8>>,
-    gaps: &mut Vec<Vec<u8>>,
+    sets: &mut Vec<Bitset>,
+    gaps: &mut Vec<Bitset>,
     to_remove: HashSet<usize>,
 ) {
     let mut indices: Vec<_> = to_remove.into_iter().collect();
@@ -207,22 +534,25 @@ fn remove_indices_from_parallel_vecs(
 }
 
 /// Eliminates sets that cannot lead to an improvement in alignment area.
-/// Returns the final number of gap columns.
+/// `gap_free_columns` is the total weight of the gap-free columns. Returns the
+/// final total weight of the gap columns that can't be made gap-free.
 pub fn set_elimination(
-    sets: &mut Vec<Vec<u8>>,
-    gaps: &mut Vec<Vec<u8>>,
+    sets: &mut Vec<Bitset>,
+    gaps: &mut Vec<Bitset>,
     alignment_area: usize,
-    sequence_count: usize,
-    alignment_length: usize,
+    weights: &Weights,
+    column_weights: &Weights,
     gap_free_columns: usize,
 ) -> usize {
-    let mut current_gap_columns = get_gap_columns(gaps, alignment_length, gap_free_columns);
+    let total_weight = weights.total();
+    let total_column_weight = column_weights.total();
+    let mut current_gap_columns = get_gap_columns(gaps, column_weights, gap_free_columns);
     loop {
         let mut to_remove = HashSet::new();
         for (i, set) in sets.iter().enumerate() {
-            let set_size = count_bits(set);
+            let set_weight = weights.of_set(set);
             if alignment_area
-                > (alignment_length - current_gap_columns) * (sequence_count - set_size)
+                > (total_column_weight - current_gap_columns) * (total_weight - set_weight)
             {
                 to_remove.insert(i);
             }
@@ -234,7 +564,7 @@ pub fn set_elimination(
 
         remove_indices_from_parallel_vecs(sets, gaps, to_remove);
 
-        let next_gap_columns = get_gap_columns(gaps, alignment_length, gap_free_columns);
+        let next_gap_columns = get_gap_columns(gaps, column_weights, gap_free_columns);
         if next_gap_columns == current_gap_columns {
             break;
         }
@@ -243,30 +573,56 @@ pub fn set_elimination(
     current_gap_columns
 }
 
-/// Calculates the number of gap columns from gap indicators.
+/// Calculates the total weight of the gap columns that are not covered by any
+/// of the gap indicators, given the total weight of the gap-free columns.
 #[must_use]
 pub fn get_gap_columns(
-    gaps: &[Vec<u8>],
-    alignment_length: usize,
+    gaps: &[Bitset],
+    column_weights: &Weights,
     gap_free_columns: usize,
 ) -> usize {
     if gaps.is_empty() {
         return 0;
     }
-    let mut union_vec = vec![0u8; alignment_length.div_ceil(8)];
+    let mut union_vec = Bitset::new(column_weights.len());
     for gap in gaps {
-        bitwise_or_assign(&mut union_vec, gap);
+        union_vec.union_with(gap);
     }
-    let gapped_columns = alignment_length - gap_free_columns;
-    gapped_columns - count_bits(&union_vec)
+    let gapped_columns = column_weights.total() - gap_free_columns;
+    gapped_columns - column_weights.of_set(&union_vec)
 }
 
-/// Removes all-gap columns from sequences and filters out excluded sequences.
+/// Returns a mask of the columns that contain at least one non-gap character
+/// in the sequences that are not excluded.
+#[must_use]
+pub fn find_retained_columns(
+    sequences: &[Vec<u8>],
+    excluded: &HashSet<usize>,
+    gap_chars: &GapChars,
+) -> Vec<bool> {
+    let seq_len = sequences.first().map_or(0, Vec::len);
+    let mut retained_columns = vec![false; seq_len];
+    for (idx, seq) in sequences.iter().enumerate() {
+        if excluded.contains(&idx) {
+            continue;
+        }
+        for (pos, &byte) in seq.iter().enumerate() {
+            if !gap_chars.contains(byte) {
+                retained_columns[pos] = true;
+            }
+        }
+    }
+    retained_columns
+}
+
+/// Removes columns made up only of gap characters from sequences and filters
+/// out excluded sequences.
 #[must_use]
 pub fn remove_all_gap_columns(
     sequences: &[Vec<u8>],
     headers: &[Vec<u8>],
     excluded: &HashSet<usize>,
+    gap_chars: &GapChars,
 ) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
     if sequences.is_empty() {
         return (Vec::new(), Vec::new());
@@ -280,15 +636,7 @@ pub fn remove_all_gap_columns(
         return (Vec::new(), Vec::new());
     }
 
-    let seq_len = sequences[0].len();
-    let mut gap_columns = vec![true; seq_len];
-    for &idx in &included_indices {
-        for (pos, &byte) in sequences[idx].iter().enumerate() {
-            if !is_gap_char(byte) {
-                gap_columns[pos] = false;
-            }
-        }
-    }
+    let retained_columns = find_retained_columns(sequences, excluded, gap_chars);
 
     let mut final_sequences = Vec::with_capacity(included_indices.len());
     let mut final_headers = Vec::with_capacity(included_indices.len());
@@ -297,7 +645,7 @@ pub fn remove_all_gap_columns(
         let new_seq: Vec<u8> = sequences[idx]
             .iter()
             .enumerate()
-            .filter(|&(pos, _)| !gap_columns[pos])
+            .filter(|&(pos, _)| retained_columns[pos])
             .map(|(_, &byte)| byte)
             .collect();
         final_sequences.push(new_seq);
@@ -1,88 +1,298 @@
 //! Bit manipulation utilities for efficient set operations.
 
-const BITS_PER_BYTE: usize = 8;
+const BITS_PER_WORD: usize = 64;
 
-/// Counts the number of set bits (1s) in a byte slice.
-#[must_use]
-pub fn count_bits(bytes: &[u8]) -> usize {
-    bytes.iter().map(|&b| b.count_ones() as usize).sum()
+/// Fixed-width set of bits backed by 64-bit words, used for sets of sequences
+/// and sets of columns. Counting operations on unions, intersections and
+/// differences don't allocate.
+#[derive(Clone, PartialEq, Eq, Hash, Default)]
+pub struct Bitset {
+    words: Vec<u64>,
+    width: usize,
 }
 
-/// Computes the bitwise OR (union) of two byte slices.
-#[must_use]
-pub fn bitwise_or(a: &[u8], b: &[u8]) -> Vec<u8> {
-    a.iter().zip(b).map(|(&x, &y)| x | y).collect()
+impl std::fmt::Debug for Bitset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.ones()).finish()
+    }
 }
 
-/// Computes the bitwise OR of two byte slices in place.
-pub fn bitwise_or_assign(dest: &mut [u8], src: &[u8]) {
-    for (d, &s) in dest.iter_mut().zip(src) {
-        *d |= s;
+impl Bitset {
+    /// Creates an empty set that can hold the indices `0..width`.
+    #[must_use]
+    pub fn new(width: usize) -> Self {
+        Self {
+            words: vec![0; width.div_ceil(BITS_PER_WORD)],
+            width,
+        }
+    }
+
+    /// Creates a set holding the indices of the `true` values.
+    #[must_use]
+    pub fn from_bools(bools: &[bool]) -> Self {
+        let mut set = Self::new(bools.len());
+        for (word, chunk) in set.words.iter_mut().zip(bools.chunks(BITS_PER_WORD)) {
+            for (bit, &b) in chunk.iter().enumerate() {
+                *word |= u64::from(b) << bit;
+            }
+        }
+        set
+    }
+
+    /// Creates a set holding the given indices.
+    #[must_use]
+    pub fn from_indices(width: usize, indices: impl IntoIterator<Item = usize>) -> Self {
+        let mut set = Self::new(width);
+        for idx in indices {
+            set.insert(idx);
+        }
+        set
+    }
+
+    /// Returns the number of indices the set can hold.
+    #[must_use]
+    pub const fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the underlying words. Bits beyond the width are always unset.
+    #[must_use]
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Adds an index to the set. Indices beyond the width are ignored.
+    pub fn insert(&mut self, idx: usize) {
+        if idx < self.width {
+            self.words[idx / BITS_PER_WORD] |= 1 << (idx % BITS_PER_WORD);
+        }
+    }
+
+    /// Returns `true` if the set holds the index.
+    #[must_use]
+    pub fn contains(&self, idx: usize) -> bool {
+        idx < self.width && (self.words[idx / BITS_PER_WORD] >> (idx % BITS_PER_WORD)) & 1 == 1
+    }
+
+    /// Returns `true` if the set holds any index.
+    #[must_use]
+    pub fn any(&self) -> bool {
+        self.words.iter().any(|&word| word != 0)
+    }
+
+    /// Returns the number of indices in the set.
+    #[must_use]
+    pub fn count_ones(&self) -> usize {
+        popcount(self.words.iter().copied())
+    }
+
+    /// Returns the union of two sets.
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = self.clone();
+        union.union_with(other);
+        union
+    }
+
+    /// Adds the indices of another set to this one.
+    pub fn union_with(&mut self, other: &Self) {
+        for (word, &other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    /// Removes the indices of another set from this one.
+    pub fn difference_with(&mut self, other: &Self) {
+        for (word, &other) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+    }
+
+    /// Returns `true` if the two sets have no index in common.
+    #[must_use]
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .all(|(&word, &other)| word & other == 0)
     }
-}
 
-/// Sets a single bit at the specified position.
-pub fn set_bit(vec: &mut [u8], position: usize) {
-    let byte_index = position / BITS_PER_BYTE;
-    let bit_offset = position % BITS_PER_BYTE;
-    if byte_index < vec.len() {
-        vec[byte_index] |= 1 << bit_offset;
+    /// Returns `true` if every index of this set is also in `other`.
+    #[must_use]
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .all(|(&word, &other)| word & other == word)
+    }
+
+    /// Counts the indices in the union of two sets.
+    #[must_use]
+    pub fn count_union(&self, other: &Self) -> usize {
+        popcount(self.words.iter().zip(&other.words).map(|(&a, &b)| a | b))
+    }
+
+    /// Counts the indices in the union of three sets.
+    #[must_use]
+    pub fn count_union_triple(&self, b: &Self, c: &Self) -> usize {
+        popcount(
+            self.words
+                .iter()
+                .zip(&b.words)
+                .zip(&c.words)
+                .map(|((&x, &y), &z)| x | y | z),
+        )
+    }
+
+    /// Counts the indices in both sets.
+    #[must_use]
+    pub fn count_intersection(&self, other: &Self) -> usize {
+        popcount(self.words.iter().zip(&other.words).map(|(&a, &b)| a & b))
+    }
+
+    /// Counts the indices of the union of two sets that are in `mask`.
+    #[must_use]
+    pub fn count_union_within(&self, other: &Self, mask: &Self) -> usize {
+        popcount(
+            self.words
+                .iter()
+                .zip(&other.words)
+                .zip(&mask.words)
+                .map(|((&a, &b), &m)| (a | b) & m),
+        )
+    }
+
+    /// Counts the indices of this set that are not in `other`.
+    #[must_use]
+    pub fn count_difference(&self, other: &Self) -> usize {
+        popcount(self.words.iter().zip(&other.words).map(|(&a, &b)| a & !b))
+    }
+
+    /// Returns the indices in the set, in increasing order.
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        word_ones(self.words.iter().copied())
     }
 }
 
-/// Packs a slice of booleans into a bit-packed byte vector.
-#[must_use]
-pub fn pack_bools_to_bits(bools: &[bool]) -> Vec<u8> {
-    bools
-        .chunks(BITS_PER_BYTE)
-        .map(|chunk| {
-            let mut byte = 0u8;
-            for (i, &b) in chunk.iter().enumerate() {
-                if b {
-                    byte |= 1 << i;
-                }
-            }
-            byte
+/// Returns the indices of the set bits of a sequence of words.
+pub fn word_ones(words: impl Iterator<Item = u64>) -> impl Iterator<Item = usize> {
+    words.enumerate().flat_map(|(word_idx, mut word)| {
+        std::iter::from_fn(move || {
+            (word != 0).then(|| {
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                word_idx * BITS_PER_WORD + bit
+            })
         })
-        .collect()
+    })
 }
 
-/// Returns the indices of all set bits in a bit-packed byte vector.
-#[must_use]
-pub fn get_set_bit_indices(bytes: &[u8], count: usize) -> Vec<usize> {
-    let mut indices = Vec::with_capacity(count_bits(bytes));
-    for (byte_idx, &byte) in bytes.iter().enumerate() {
-        if byte == 0 {
-            continue;
+/// Counts the set bits of a sequence of words, using the `popcnt` instruction
+/// if the `simd` feature is enabled and the CPU supports it.
+#[inline]
+fn popcount(words: impl Iterator<Item = u64>) -> usize {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("popcnt") {
+        // SAFETY: the CPU supports the `popcnt` instruction.
+        return unsafe { popcount_popcnt(words) };
+    }
+    words.map(|word| word.count_ones() as usize).sum()
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[target_feature(enable = "popcnt")]
+fn popcount_popcnt(words: impl Iterator<Item = u64>) -> usize {
+    words.map(|word| word.count_ones() as usize).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg64;
+    use std::collections::BTreeSet;
+
+    const WIDTHS: [usize; 6] = [0, 1, 63, 64, 65, 130];
+
+    fn random_set(rng: &mut Pcg64, width: usize) -> (Bitset, BTreeSet<usize>) {
+        let indices: BTreeSet<usize> = (0..width).filter(|_| rng.random_bool(0.4)).collect();
+        (
+            Bitset::from_indices(width, indices.iter().copied()),
+            indices,
+        )
+    }
+
+    #[test]
+    fn empty_sets_have_the_right_number_of_words() {
+        for (width, words) in [(0, 0), (1, 1), (63, 1), (64, 1), (65, 2), (130, 3)] {
+            let set = Bitset::new(width);
+            assert_eq!(set.width(), width);
+            assert_eq!(set.words().len(), words);
+            assert!(!set.any());
+            assert_eq!(set.count_ones(), 0);
+            assert_eq!(set.ones().count(), 0);
         }
-        for bit_idx in 0..BITS_PER_BYTE {
-            let i = byte_idx * BITS_PER_BYTE + bit_idx;
-            if i >= count {
-                break;
-            }
-            if (byte >> bit_idx) & 1 == 1 {
-                indices.push(i);
+    }
+
+    #[test]
+    fn ignores_indices_beyond_the_width() {
+        for width in WIDTHS {
+            let mut set = Bitset::new(width);
+            set.insert(width);
+            set.insert(width + 64);
+            assert!(!set.any());
+            assert!(!set.contains(width));
+            if width > 0 {
+                set.insert(width - 1);
+                assert!(set.contains(width - 1));
+                assert_eq!(set.ones().collect::<Vec<_>>(), [width - 1]);
             }
         }
     }
-    indices
-}
 
-/// Computes the population count of the bitwise OR of two byte slices.
-#[must_use]
-pub fn count_bits_union(a: &[u8], b: &[u8]) -> usize {
-    a.iter()
-        .zip(b)
-        .map(|(&x, &y)| (x | y).count_ones() as usize)
-        .sum()
-}
+    #[test]
+    fn boundary_bits_stay_in_their_word() {
+        let set = Bitset::from_indices(65, [0, 63, 64]);
+        assert_eq!(set.words(), [1 | 1 << 63, 1]);
+        assert_eq!(set.count_ones(), 3);
+        assert_eq!(set.ones().collect::<Vec<_>>(), [0, 63, 64]);
+        let bools: Vec<bool> = (0..65).map(|idx| set.contains(idx)).collect();
+        assert_eq!(Bitset::from_bools(&bools), set);
+    }
+
+    #[test]
+    fn operations_match_sets_of_indices() {
+        let mut rng = Pcg64::seed_from_u64(13);
+        for width in WIDTHS {
+            for _ in 0..20 {
+                let (a, a_set) = random_set(&mut rng, width);
+                let (b, b_set) = random_set(&mut rng, width);
+                let (c, c_set) = random_set(&mut rng, width);
+                let union: BTreeSet<usize> = a_set.union(&b_set).copied().collect();
 
-/// Computes the population count of the bitwise OR of three byte slices.
-#[must_use]
-pub fn count_bits_union_triple(a: &[u8], b: &[u8], c: &[u8]) -> usize {
-    a.iter()
-        .zip(b)
-        .zip(c)
-        .map(|((&x, &y), &z)| (x | y | z).count_ones() as usize)
-        .sum()
+                assert_eq!(a.ones().collect::<BTreeSet<_>>(), a_set);
+                assert_eq!(a.count_ones(), a_set.len());
+                assert_eq!(a.any(), !a_set.is_empty());
+                assert_eq!(a.union(&b).ones().collect::<BTreeSet<_>>(), union);
+                assert_eq!(a.count_union(&b), union.len());
+                assert_eq!(a.count_union_triple(&b, &c), union.union(&c_set).count());
+                assert_eq!(a.count_intersection(&b), a_set.intersection(&b_set).count());
+                assert_eq!(
+                    a.count_union_within(&b, &c),
+                    union.intersection(&c_set).count()
+                );
+                assert_eq!(a.count_difference(&b), a_set.difference(&b_set).count());
+                assert_eq!(a.is_disjoint(&b), a_set.is_disjoint(&b_set));
+                assert_eq!(a.is_subset(&b), a_set.is_subset(&b_set));
+                assert!(a.is_subset(&a.union(&b)));
+
+                let mut difference = a.clone();
+                difference.difference_with(&b);
+                assert_eq!(
+                    difference.ones().collect::<BTreeSet<_>>(),
+                    a_set.difference(&b_set).copied().collect()
+                );
+            }
+        }
+    }
 }
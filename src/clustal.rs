@@ -0,0 +1,112 @@
+//! Clustal file parsing utilities.
+
+use crate::error::{Error, Result};
+use crate::fasta::{SequenceData, build_sequence_data};
+use crate::stockholm::split_field;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// Parses an aligned Clustal file and returns the sequence data.
+///
+/// Sequences may be split across several blocks. Conservation lines and
+/// trailing residue counts are ignored.
+pub fn parse_clustal(mut reader: impl BufRead, keep_sequence: &[String]) -> Result<SequenceData> {
+    let mut headers: Vec<Vec<u8>> = Vec::new();
+    let mut sequences: Vec<Vec<u8>> = Vec::new();
+    let mut sequence_index: HashMap<Vec<u8>, usize> = HashMap::new();
+
+    let mut line = Vec::new();
+    let mut seen_header = false;
+
+    loop {
+        line.clear();
+        let bytes_read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| Error::ClustalParse(e.to_string()))?;
+        if bytes_read == 0 {
+            break;
+        }
+
+        let trimmed = line.trim_ascii_end();
+        if trimmed.trim_ascii_start().is_empty() {
+            continue;
+        }
+
+        if !seen_header {
+            seen_header = true;
+            continue;
+        }
+
+        // Conservation lines start with whitespace.
+        if trimmed[0].is_ascii_whitespace() {
+            continue;
+        }
+
+        let (name, rest) = split_field(trimmed);
+        let (data, count) = split_field(rest);
+        if data.is_empty() || !(count.is_empty() || count.iter().all(u8::is_ascii_digit)) {
+            return Err(Error::ClustalParse(format!(
+                "malformed alignment line for sequence '{}'",
+                String::from_utf8_lossy(name)
+            )));
+        }
+
+        if let Some(&idx) = sequence_index.get(name) {
+            sequences[idx].extend_from_slice(data);
+        } else {
+            sequence_index.insert(name.to_vec(), sequences.len());
+            headers.push(name.to_vec());
+            sequences.push(data.to_vec());
+        }
+    }
+
+    build_sequence_data(headers, sequences, keep_sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::GapChars;
+    use crate::output::write_clustal;
+
+    fn parse(text: &str) -> Result<SequenceData> {
+        parse_clustal(text.as_bytes(), &[])
+    }
+
+    #[test]
+    fn parses_blocks_and_ignores_counts_and_conservation() {
+        let text = "CLUSTAL W (1.83) multiple sequence alignment
+
+seq1      AC-GT 4
+seq2      ACTGT 5
+          ** **
+
+seq1      TT 6
+seq2      T- 6
+          *
+";
+        let data = parse(text).unwrap();
+        assert_eq!(data.headers, [b"seq1".to_vec(), b"seq2".to_vec()]);
+        assert_eq!(data.sequences, [b"AC-GTTT".to_vec(), b"ACTGTT-".to_vec()]);
+    }
+
+    #[test]
+    fn round_trips_through_the_writer() {
+        let headers = vec![b"alpha".to_vec(), b"beta".to_vec()];
+        let sequences = vec![b"AC-T".repeat(20), b"ACGT".repeat(20)];
+        let mut written = Vec::new();
+        write_clustal(&sequences, &headers, &GapChars::default(), &mut written).unwrap();
+        let data = parse_clustal(written.as_slice(), &[]).unwrap();
+        assert_eq!(data.headers, headers);
+        assert_eq!(data.sequences, sequences);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let text = "CLUSTAL W\n\nseq1 ACGT 4\nseq2\n";
+        assert!(matches!(parse(text), Err(Error::ClustalParse(_))));
+        let text = "CLUSTAL W\n\nseq1 ACGT four\n";
+        assert!(matches!(parse(text), Err(Error::ClustalParse(_))));
+        assert!(matches!(parse("CLUSTAL W\n"), Err(Error::EmptyInput)));
+    }
+}
@@ -0,0 +1,175 @@
+//! Transparent compression and decompression of input and output files.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Supported compression formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+            Self::Zstd => "zstd",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "bzip2" | "bz2" => Ok(Self::Bzip2),
+            "xz" => Ok(Self::Xz),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            _ => Err(format!(
+                "invalid compression format '{s}': must be none, gzip, bzip2, xz, or zstd"
+            )),
+        }
+    }
+}
+
+impl Compression {
+    /// Detects the compression format from the first bytes of a file.
+    #[must_use]
+    pub fn detect(buffer: &[u8]) -> Self {
+        if buffer.starts_with(GZIP_MAGIC) {
+            Self::Gzip
+        } else if buffer.starts_with(BZIP2_MAGIC) {
+            Self::Bzip2
+        } else if buffer.starts_with(XZ_MAGIC) {
+            Self::Xz
+        } else if buffer.starts_with(ZSTD_MAGIC) {
+            Self::Zstd
+        } else {
+            Self::None
+        }
+    }
+
+    /// Infers the compression format from a file extension.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("bz2") => Self::Bzip2,
+            Some("xz") => Self::Xz,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+}
+
+/// Wraps a reader with a decoder if its contents are compressed.
+pub fn decompress_reader<'a>(
+    mut reader: Box<dyn BufRead + Send + 'a>,
+) -> io::Result<Box<dyn BufRead + Send + 'a>> {
+    let compression = Compression::detect(reader.fill_buf()?);
+    Ok(match compression {
+        Compression::None => reader,
+        Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        Compression::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+        Compression::Xz => Box::new(BufReader::new(
+            liblzma::bufread::XzDecoder::new_multi_decoder(reader),
+        )),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?)),
+    })
+}
+
+/// A writer that compresses its output. [`CompressedWriter::finish`] must be
+/// called to write the end of the compressed stream.
+pub enum CompressedWriter<W: Write> {
+    None(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Bzip2(bzip2::write::BzEncoder<W>),
+    Xz(liblzma::write::XzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Wraps a writer with an encoder for the given compression format.
+    pub fn new(writer: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Self::None(writer),
+            Compression::Gzip => Self::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            Compression::Bzip2 => Self::Bzip2(bzip2::write::BzEncoder::new(
+                writer,
+                bzip2::Compression::default(),
+            )),
+            Compression::Xz => Self::Xz(liblzma::write::XzEncoder::new(writer, 6)),
+            Compression::Zstd => Self::Zstd(zstd::stream::write::Encoder::new(writer, 0)?),
+        })
+    }
+
+    /// Writes the end of the compressed stream and flushes the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = match self {
+            Self::None(w) => w,
+            Self::Gzip(w) => w.finish()?,
+            Self::Bzip2(w) => w.finish()?,
+            Self::Xz(w) => w.finish()?,
+            Self::Zstd(w) => w.finish()?,
+        };
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::None(w) => w.write(buf),
+            Self::Gzip(w) => w.write(buf),
+            Self::Bzip2(w) => w.write(buf),
+            Self::Xz(w) => w.write(buf),
+            Self::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::None(w) => w.flush(),
+            Self::Gzip(w) => w.flush(),
+            Self::Bzip2(w) => w.flush(),
+            Self::Xz(w) => w.flush(),
+            Self::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+/// Creates a file for writing, compressing it according to its extension.
+pub fn create_file(path: &Path) -> io::Result<CompressedWriter<BufWriter<File>>> {
+    let file = File::create(path)?;
+    CompressedWriter::new(BufWriter::new(file), Compression::from_path(path))
+}
+
+/// Opens a file for reading, decompressing it if needed.
+pub fn open_file(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path)?;
+    decompress_reader(Box::new(BufReader::new(file)))
+}
@@ -0,0 +1,1333 @@
+//! High-level entry point for running `MaxAlign` on an alignment.
+//!
+//! [`MaxAlign`] wraps the full pipeline (gap pattern extraction, the greedy
+//! heuristic and the optional branch-and-bound refinement) behind a
+//! builder-style API, so that callers don't need to wire the lower-level
+//! functions together themselves.
+
+use crate::alignment::{
+    AlignmentMetrics, ColumnPatterns, ExclusionBudget, ExclusionLimit, GapChars, GapTolerance,
+    SetData, create_gap_matrix, create_sets,
+};
+use crate::bitops::Bitset;
+use crate::error::{Error, Result};
+use crate::groups::SequenceGroups;
+use crate::heuristic::{HeuristicConfig, HeuristicMethod, run_heuristic, run_tolerant_heuristic};
+use crate::model::{ModelSolution, ReducedModel};
+use crate::optimize::{
+    BranchAndBoundResult, RefinementLimits, RefinementStatus, Solution, SolutionPool,
+    reduce_problem, run_branch_and_bound, run_tolerant_branch_and_bound,
+};
+use crate::pareto::{ParetoPoint, ParetoSource, heuristic_points, pareto_front, refine_front};
+use crate::polish::{PolishConfig, PolishMove, polish};
+use crate::weights::Weights;
+use itertools::Itertools;
+use log::info;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Configured `MaxAlign` engine.
+///
+/// ```no_run
+/// use maxalign_rs::{HeuristicMethod, MaxAlign};
+///
+/// let alignment: Vec<Vec<u8>> = vec![b"AC-T".to_vec(), b"ACGT".to_vec()];
+/// let result = MaxAlign::builder()
+///     .method(HeuristicMethod::PairwiseSynergy)
+///     .refinement(true)
+///     .keep([0])
+///     .run(&alignment)?;
+/// println!("{} sequences retained", result.retained.len());
+/// # Ok::<(), maxalign_rs::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MaxAlign {
+    heuristic: HeuristicConfig,
+    polish: bool,
+    polish_config: PolishConfig,
+    refinement: bool,
+    refinement_limits: RefinementLimits,
+    keep: HashSet<usize>,
+    gap_chars: GapChars,
+    weights: Option<Vec<f64>>,
+    column_weights: Option<Vec<f64>>,
+    gap_tolerance: GapTolerance,
+    exclusion_limit: ExclusionLimit,
+    groups: SequenceGroups,
+    threads: usize,
+    pareto_front: bool,
+    pareto_selection: Option<usize>,
+    solutions: bool,
+    top_solutions: Option<usize>,
+    solution_selection: Option<usize>,
+    model: bool,
+    model_solution: Option<ModelSolution>,
+    solve_model: bool,
+}
+
+/// Builder for [`MaxAlign`].
+#[derive(Debug, Clone)]
+pub struct MaxAlignBuilder {
+    heuristic: HeuristicConfig,
+    polish: bool,
+    polish_config: PolishConfig,
+    refinement: bool,
+    refinement_limits: RefinementLimits,
+    keep: HashSet<usize>,
+    gap_chars: GapChars,
+    weights: Option<Vec<f64>>,
+    column_weights: Option<Vec<f64>>,
+    gap_tolerance: GapTolerance,
+    exclusion_limit: ExclusionLimit,
+    groups: SequenceGroups,
+    threads: usize,
+    pareto_front: bool,
+    pareto_selection: Option<usize>,
+    solutions: bool,
+    top_solutions: Option<usize>,
+    solution_selection: Option<usize>,
+    model: bool,
+    model_solution: Option<ModelSolution>,
+    solve_model: bool,
+}
+
+impl Default for MaxAlignBuilder {
+    fn default() -> Self {
+        Self {
+            heuristic: HeuristicConfig {
+                method: HeuristicMethod::default(),
+                max_iterations: u32::MAX,
+                improvement_threshold: 0.0,
+                excluded_seqs_threshold: 1.0,
+                beam_width: 1,
+                exclusion_budget: ExclusionBudget::default(),
+            },
+            polish: false,
+            polish_config: PolishConfig::default(),
+            refinement: false,
+            refinement_limits: RefinementLimits::default(),
+            keep: HashSet::new(),
+            gap_chars: GapChars::default(),
+            weights: None,
+            column_weights: None,
+            gap_tolerance: GapTolerance::default(),
+            exclusion_limit: ExclusionLimit::default(),
+            groups: SequenceGroups::default(),
+            threads: 1,
+            pareto_front: false,
+            pareto_selection: None,
+            solutions: false,
+            top_solutions: None,
+            solution_selection: None,
+            model: false,
+            model_solution: None,
+            solve_model: false,
+        }
+    }
+}
+
+impl MaxAlignBuilder {
+    /// Sets the heuristic method.
+    #[must_use]
+    pub const fn method(mut self, method: HeuristicMethod) -> Self {
+        self.heuristic.method = method;
+        self
+    }
+
+    /// Sets the maximum number of heuristic iterations.
+    #[must_use]
+    pub const fn max_iterations(mut self, max_iterations: u32) -> Self {
+        self.heuristic.max_iterations = max_iterations;
+        self
+    }
+
+    /// Stops iterating if the relative improvement is below this threshold.
+    #[must_use]
+    pub const fn improvement_threshold(mut self, threshold: f64) -> Self {
+        self.heuristic.improvement_threshold = threshold;
+        self
+    }
+
+    /// Stops iterating if the fraction of excluded sequences reaches this threshold.
+    #[must_use]
+    pub const fn excluded_seqs_threshold(mut self, threshold: f64) -> Self {
+        self.heuristic.excluded_seqs_threshold = threshold;
+        self
+    }
+
+    /// Keeps this many partial solutions per iteration of the heuristic
+    /// instead of a single one (see [`run_heuristic`]). Only used when only
+    /// gap-free columns count.
+    #[must_use]
+    pub fn beam_width(mut self, width: usize) -> Self {
+        self.heuristic.beam_width = width.max(1);
+        self
+    }
+
+    /// Enables or disables the polishing of the heuristic solution by local
+    /// search (see [`polish`](crate::polish)).
+    #[must_use]
+    pub const fn polish(mut self, polish: bool) -> Self {
+        self.polish = polish;
+        self
+    }
+
+    /// Runs this many simulated annealing steps from the local optimum of the
+    /// polishing, which then resumes from the best solution visited. Enables
+    /// the polishing.
+    #[must_use]
+    pub const fn anneal_steps(mut self, steps: u64) -> Self {
+        self.polish = true;
+        self.polish_config.anneal_steps = steps;
+        self
+    }
+
+    /// Sets the seed of the random number generator of the simulated
+    /// annealing (0 by default).
+    #[must_use]
+    pub const fn seed(mut self, seed: u64) -> Self {
+        self.polish_config.seed = seed;
+        self
+    }
+
+    /// Enables or disables the branch-and-bound refinement.
+    #[must_use]
+    pub const fn refinement(mut self, refinement: bool) -> Self {
+        self.refinement = refinement;
+        self
+    }
+
+    /// Stops the branch-and-bound refinement after this time, keeping the best
+    /// solution found so far.
+    #[must_use]
+    pub const fn refinement_time_limit(mut self, limit: Duration) -> Self {
+        self.refinement_limits.time = Some(limit);
+        self
+    }
+
+    /// Stops the branch-and-bound refinement after exploring this many search
+    /// nodes, keeping the best solution found so far.
+    #[must_use]
+    pub const fn refinement_node_limit(mut self, limit: u64) -> Self {
+        self.refinement_limits.nodes = Some(limit);
+        self
+    }
+
+    /// Adds indices of sequences that must always be retained.
+    #[must_use]
+    pub fn keep(mut self, indices: impl IntoIterator<Item = usize>) -> Self {
+        self.keep.extend(indices);
+        self
+    }
+
+    /// Sets the characters that are treated as gaps (`-` and `.` by default).
+    #[must_use]
+    pub fn gap_chars(mut self, gap_chars: GapChars) -> Self {
+        self.gap_chars = gap_chars;
+        self
+    }
+
+    /// Sets per-sequence weights, one per sequence of the alignment. The
+    /// objective then becomes the total weight of the retained sequences
+    /// multiplied by the number of gap-free columns.
+    #[must_use]
+    pub fn sequence_weights(mut self, weights: Vec<f64>) -> Self {
+        self.weights = Some(weights);
+        self
+    }
+
+    /// Sets per-column weights, one per column of the alignment. The objective
+    /// then counts the total weight of the gap-free columns instead of their
+    /// number; columns with a weight of zero are ignored.
+    #[must_use]
+    pub fn column_weights(mut self, weights: Vec<f64>) -> Self {
+        self.column_weights = Some(weights);
+        self
+    }
+
+    /// Lets columns with at most this many gaps among the retained sequences
+    /// count towards the area.
+    #[must_use]
+    pub const fn max_column_gaps(mut self, count: usize) -> Self {
+        self.gap_tolerance = GapTolerance::Count(count);
+        self
+    }
+
+    /// Lets columns in which at most this fraction of the retained sequences
+    /// have a gap count towards the area.
+    #[must_use]
+    pub const fn max_column_gap_fraction(mut self, fraction: f64) -> Self {
+        self.gap_tolerance = GapTolerance::Fraction(fraction);
+        self
+    }
+
+    /// Retains at least this many sequences. Unlike
+    /// [`excluded_seqs_threshold`](Self::excluded_seqs_threshold), this is a
+    /// hard limit that the heuristic, the polishing and the refinement never
+    /// exceed.
+    #[must_use]
+    pub const fn min_retained(mut self, count: usize) -> Self {
+        self.exclusion_limit.min_retained = Some(count);
+        self
+    }
+
+    /// Excludes at most this many sequences (see
+    /// [`min_retained`](Self::min_retained)).
+    #[must_use]
+    pub const fn max_excluded(mut self, count: usize) -> Self {
+        self.exclusion_limit.max_excluded = Some(count);
+        self
+    }
+
+    /// Excludes at most this fraction of the sequences (see
+    /// [`min_retained`](Self::min_retained)).
+    #[must_use]
+    pub const fn max_excluded_fraction(mut self, fraction: f64) -> Self {
+        self.exclusion_limit.max_excluded_fraction = Some(fraction);
+        self
+    }
+
+    /// Assigns the sequences to groups. The quotas of the groups are hard
+    /// limits, like [`min_retained`](Self::min_retained): at least the
+    /// required number of sequences of each group is retained.
+    #[must_use]
+    pub fn groups(mut self, groups: SequenceGroups) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    /// Sets the number of threads used to score heuristic candidates and to
+    /// search the branch-and-bound tree (1 by default; 0 uses all available
+    /// cores). Results don't depend on the number of threads.
+    #[must_use]
+    pub const fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Enables or disables the computation of the Pareto front of area versus
+    /// number of retained sequences (see [`MaxAlignResult::pareto_front`]).
+    /// With the refinement, the front is tightened by running the
+    /// branch-and-bound algorithm with a maximum number of excluded sequences
+    /// for each number of retained sequences, and the refinement limits apply
+    /// to each of these runs.
+    #[must_use]
+    pub const fn pareto_front(mut self, pareto_front: bool) -> Self {
+        self.pareto_front = pareto_front;
+        self
+    }
+
+    /// Returns the solution of the Pareto front with the largest area among
+    /// those that retain at least this many sequences, instead of the
+    /// largest-area solution. Enables the computation of the Pareto front.
+    #[must_use]
+    pub const fn pareto_selection(mut self, min_retained: usize) -> Self {
+        self.pareto_front = true;
+        self.pareto_selection = Some(min_retained);
+        self
+    }
+
+    /// Enables or disables the listing of the solutions of the refinement that
+    /// reach the best area (see [`MaxAlignResult::solutions`]).
+    #[must_use]
+    pub const fn solutions(mut self, solutions: bool) -> Self {
+        self.solutions = solutions;
+        self
+    }
+
+    /// Lists this many best solutions of the refinement instead of only those
+    /// that reach the best area. The search then prunes against the area of
+    /// the last listed solution instead of the best one, so it explores more
+    /// nodes. Enables the listing of solutions.
+    #[must_use]
+    pub fn top_solutions(mut self, count: usize) -> Self {
+        self.solutions = true;
+        self.top_solutions = Some(count.max(1));
+        self
+    }
+
+    /// Returns the listed solution with this index (starting at 0) instead of
+    /// the first one. If the index is out of range, the first one is returned.
+    /// Enables the listing of solutions.
+    #[must_use]
+    pub const fn solution_selection(mut self, index: usize) -> Self {
+        self.solutions = true;
+        self.solution_selection = Some(index);
+        self
+    }
+
+    /// Enables or disables the construction of the model of the reduced problem
+    /// (see [`MaxAlignResult::model`]), for external exact solvers. Only
+    /// supported when only gap-free columns count.
+    #[must_use]
+    pub const fn model(mut self, model: bool) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Returns the alignment given by a solution of the model instead of the
+    /// solution of the heuristic. The model is built from the same input and
+    /// settings, so the solution must come from a model exported with them.
+    /// Enables the construction of the model.
+    #[must_use]
+    pub fn model_solution(mut self, solution: ModelSolution) -> Self {
+        self.model = true;
+        self.model_solution = Some(solution);
+        self
+    }
+
+    /// Solves the model with the bundled exact solver (see
+    /// [`ReducedModel::solve`]) and returns its solution instead of the
+    /// solution of the heuristic. Enables the construction of the model.
+    #[must_use]
+    pub const fn solve_model(mut self, solve: bool) -> Self {
+        self.model |= solve;
+        self.solve_model = solve;
+        self
+    }
+
+    /// Builds the configured engine.
+    #[must_use]
+    pub fn build(self) -> MaxAlign {
+        MaxAlign {
+            heuristic: self.heuristic,
+            polish: self.polish,
+            polish_config: self.polish_config,
+            refinement: self.refinement,
+            refinement_limits: self.refinement_limits,
+            keep: self.keep,
+            gap_chars: self.gap_chars,
+            weights: self.weights,
+            column_weights: self.column_weights,
+            gap_tolerance: self.gap_tolerance,
+            exclusion_limit: self.exclusion_limit,
+            groups: self.groups,
+            threads: self.threads,
+            pareto_front: self.pareto_front,
+            pareto_selection: self.pareto_selection,
+            solutions: self.solutions,
+            top_solutions: self.top_solutions,
+            solution_selection: self.solution_selection,
+            model: self.model,
+            model_solution: self.model_solution,
+            solve_model: self.solve_model,
+        }
+    }
+
+    /// Builds the engine and runs it on the given alignment.
+    ///
+    /// # Errors
+    ///
+    /// See [`MaxAlign::run`].
+    pub fn run(self, sequences: &[Vec<u8>]) -> Result<MaxAlignResult> {
+        self.build().run(sequences)
+    }
+}
+
+/// Outcome of a `MaxAlign` run.
+#[derive(Debug, Clone)]
+pub struct MaxAlignResult {
+    /// Indices of the retained sequences, in input order.
+    pub retained: Vec<usize>,
+    /// Indices of the excluded sequences.
+    pub excluded: HashSet<usize>,
+    /// Metrics of the input alignment.
+    pub initial_metrics: AlignmentMetrics,
+    /// Metrics after the heuristic stage.
+    pub heuristic_metrics: AlignmentMetrics,
+    /// Metrics of the final alignment (after refinement, if enabled).
+    pub final_metrics: AlignmentMetrics,
+    /// Sequences excluded and resulting alignment area at each heuristic iteration.
+    pub iteration_data: Vec<(Vec<usize>, usize)>,
+    /// Changes of the solution made by the polishing after the heuristic
+    /// iterations, each of which enlarges the weighted area. Empty unless the
+    /// polishing was enabled.
+    pub polish_moves: Vec<PolishMove>,
+    /// Outcome of the branch-and-bound refinement, if enabled. If a limit was
+    /// reached, the final solution may not be optimal.
+    pub refinement_status: Option<RefinementStatus>,
+    /// Solutions on the trade-off curve between the number of retained
+    /// sequences and the weighted area, sorted by decreasing number of retained
+    /// sequences. Empty unless the Pareto front was enabled.
+    pub pareto_front: Vec<ParetoPoint>,
+    /// Solutions of the refinement that reach the best area, or the best
+    /// solutions if more were requested, sorted by decreasing weighted area.
+    /// The first one is the solution the run returns by default. Empty unless
+    /// the refinement and the listing of solutions were enabled.
+    pub solutions: Vec<Solution>,
+    /// Model of the problem reduced for the area of the solution found before
+    /// any model solution was applied. `None` unless the model was enabled and
+    /// only gap-free columns count.
+    pub model: Option<ReducedModel>,
+    /// Sequence weights used in the objective.
+    pub sequence_weights: Weights,
+    /// Column weights used in the objective.
+    pub column_weights: Weights,
+}
+
+impl MaxAlignResult {
+    /// Returns `true` if sequences or columns were weighted.
+    #[must_use]
+    pub const fn is_weighted(&self) -> bool {
+        !self.sequence_weights.is_uniform() || !self.column_weights.is_uniform()
+    }
+
+    /// Returns the number of integer units per unit of weighted area.
+    #[must_use]
+    pub const fn weighted_area_scale(&self) -> usize {
+        self.sequence_weights.scale() * self.column_weights.scale()
+    }
+
+    /// Returns the weighted alignment area of the given metrics.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub const fn weighted_area(&self, metrics: &AlignmentMetrics) -> f64 {
+        metrics.weighted_area as f64 / self.weighted_area_scale() as f64
+    }
+}
+
+impl MaxAlign {
+    /// Returns a builder with the default settings.
+    #[must_use]
+    pub fn builder() -> MaxAlignBuilder {
+        MaxAlignBuilder::default()
+    }
+
+    /// Returns the heuristic configuration used by this engine.
+    #[must_use]
+    pub const fn heuristic_config(&self) -> &HeuristicConfig {
+        &self.heuristic
+    }
+
+    /// Returns the polishing configuration, if the polishing is enabled.
+    #[must_use]
+    pub const fn polish_config(&self) -> Option<&PolishConfig> {
+        if self.polish {
+            Some(&self.polish_config)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the branch-and-bound refinement is enabled.
+    #[must_use]
+    pub const fn refinement(&self) -> bool {
+        self.refinement
+    }
+
+    /// Returns the limits on the branch-and-bound refinement.
+    #[must_use]
+    pub const fn refinement_limits(&self) -> RefinementLimits {
+        self.refinement_limits
+    }
+
+    /// Returns the characters treated as gaps by this engine.
+    #[must_use]
+    pub const fn gap_chars(&self) -> &GapChars {
+        &self.gap_chars
+    }
+
+    /// Returns the number of gaps a column may contain and still count towards
+    /// the area.
+    #[must_use]
+    pub const fn gap_tolerance(&self) -> GapTolerance {
+        self.gap_tolerance
+    }
+
+    /// Returns the hard limits on the number of excluded sequences.
+    #[must_use]
+    pub const fn exclusion_limit(&self) -> ExclusionLimit {
+        self.exclusion_limit
+    }
+
+    /// Returns the groups of the sequences, with their quotas.
+    #[must_use]
+    pub const fn groups(&self) -> &SequenceGroups {
+        &self.groups
+    }
+
+    /// Returns the number of threads used by this engine (0 for all available
+    /// cores).
+    #[must_use]
+    pub const fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Returns the minimum number of retained sequences of the selected
+    /// solution of the Pareto front, if any.
+    #[must_use]
+    pub const fn pareto_selection(&self) -> Option<usize> {
+        self.pareto_selection
+    }
+
+    /// Returns the index of the selected solution of the refinement, if any.
+    #[must_use]
+    pub const fn solution_selection(&self) -> Option<usize> {
+        self.solution_selection
+    }
+
+    /// Runs `MaxAlign` on an alignment. Sequences shorter than the longest one
+    /// are treated as if they were padded with gaps.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if sequence or column weights or groups were given and
+    /// their number differs from the number of sequences or columns, if a
+    /// sequence to keep is out of range, or if the thread pool can't be
+    /// created, or if a model solution was given that doesn't fit the model.
+    pub fn run(&self, sequences: &[Vec<u8>]) -> Result<MaxAlignResult> {
+        let num_sequences = sequences.len();
+        let alignment_length = sequences.iter().map(Vec::len).max().unwrap_or(0);
+        let (weights, column_weights) = self.objective_weights(num_sequences, alignment_length)?;
+        if !self.groups.is_empty() && self.groups.num_sequences() != num_sequences {
+            return Err(Error::GroupsParse(format!(
+                "expected one group assignment per sequence, found {} for {num_sequences} sequences",
+                self.groups.num_sequences()
+            )));
+        }
+        if let Some(index) = self
+            .keep
+            .iter()
+            .copied()
+            .filter(|&idx| idx >= num_sequences)
+            .min()
+        {
+            return Err(Error::KeepIndex {
+                index,
+                count: num_sequences,
+            });
+        }
+
+        let budget = ExclusionBudget {
+            max_excluded: self.exclusion_limit.max_excluded(num_sequences),
+            groups: if self.groups.is_empty() {
+                Vec::new()
+            } else {
+                self.groups.limits()
+            },
+        };
+
+        let gap_matrix = create_gap_matrix(sequences, alignment_length, &self.gap_chars);
+        let Stages {
+            initial_metrics,
+            heuristic_metrics,
+            mut final_metrics,
+            excluded: mut final_excluded,
+            iteration_data,
+            polish_moves,
+            refinement_status,
+            pareto_front,
+            solutions,
+            model,
+        } = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()?
+            .install(|| {
+                if self.gap_tolerance.is_strict() {
+                    self.run_strict(
+                        &gap_matrix,
+                        alignment_length,
+                        &weights,
+                        &column_weights,
+                        &budget,
+                    )
+                } else {
+                    Ok(self.run_tolerant(
+                        &gap_matrix,
+                        alignment_length,
+                        &weights,
+                        &column_weights,
+                        &budget,
+                    ))
+                }
+            })?;
+
+        if let Some(solution) = self
+            .solution_selection
+            .and_then(|index| solutions.get(index))
+        {
+            final_metrics = solution.metrics.clone();
+            final_excluded = solution.excluded.clone();
+        } else if let Some(point) = self
+            .pareto_selection
+            .and_then(|min_retained| crate::pareto::select(&pareto_front, min_retained))
+        {
+            final_metrics = point.metrics.clone();
+            final_excluded = point.excluded.clone();
+        }
+
+        let retained: Vec<usize> = (0..num_sequences)
+            .filter(|idx| !final_excluded.contains(idx))
+            .collect();
+
+        self.trim_metrics(&mut final_metrics, sequences, &retained);
+
+        Ok(MaxAlignResult {
+            retained,
+            excluded: final_excluded,
+            initial_metrics,
+            heuristic_metrics,
+            final_metrics,
+            iteration_data,
+            polish_moves,
+            refinement_status,
+            pareto_front,
+            solutions,
+            model,
+            sequence_weights: weights,
+            column_weights,
+        })
+    }
+
+    /// Computes the metrics of an alignment after the given sequences are
+    /// excluded, without running any optimization, and returns them as the
+    /// final metrics of a result. The gap characters, weights and gap
+    /// tolerance of the engine apply, and sequences shorter than the longest
+    /// one are treated as if they were padded with gaps. Indices beyond the
+    /// number of sequences are ignored.
+    ///
+    /// The result has no heuristic iterations: its heuristic metrics are those
+    /// of the input.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if sequence or column weights were given and their
+    /// number differs from the number of sequences or columns.
+    pub fn apply(
+        &self,
+        sequences: &[Vec<u8>],
+        excluded: &HashSet<usize>,
+    ) -> Result<MaxAlignResult> {
+        let num_sequences = sequences.len();
+        let alignment_length = sequences.iter().map(Vec::len).max().unwrap_or(0);
+        let (weights, column_weights) = self.objective_weights(num_sequences, alignment_length)?;
+
+        let gap_matrix = create_gap_matrix(sequences, alignment_length, &self.gap_chars);
+        let patterns = ColumnPatterns::new(&gap_matrix, alignment_length, &column_weights);
+        let metrics_of = |excluded: &Bitset| {
+            let remaining = num_sequences - excluded.count_ones();
+            let (columns, column_weight) =
+                patterns.counted_columns(excluded, remaining, self.gap_tolerance);
+            AlignmentMetrics::new(remaining, columns, columns * remaining, alignment_length)
+                .with_weighted_area((weights.total() - weights.of_set(excluded)) * column_weight)
+        };
+
+        let excluded: HashSet<usize> = excluded
+            .iter()
+            .copied()
+            .filter(|&idx| idx < num_sequences)
+            .collect();
+        let initial_metrics = metrics_of(&Bitset::new(num_sequences));
+        let mut final_metrics = metrics_of(&Bitset::from_indices(
+            num_sequences,
+            excluded.iter().copied(),
+        ));
+        let retained: Vec<usize> = (0..num_sequences)
+            .filter(|idx| !excluded.contains(idx))
+            .collect();
+        self.trim_metrics(&mut final_metrics, sequences, &retained);
+
+        Ok(MaxAlignResult {
+            retained,
+            excluded,
+            heuristic_metrics: initial_metrics.clone(),
+            initial_metrics,
+            final_metrics,
+            iteration_data: Vec::new(),
+            polish_moves: Vec::new(),
+            refinement_status: None,
+            pareto_front: Vec::new(),
+            solutions: Vec::new(),
+            model: None,
+            sequence_weights: weights,
+            column_weights,
+        })
+    }
+
+    /// Returns the weights of the sequences and columns in the objective.
+    fn objective_weights(
+        &self,
+        num_sequences: usize,
+        alignment_length: usize,
+    ) -> Result<(Weights, Weights)> {
+        let weights = match &self.weights {
+            Some(weights) if weights.len() != num_sequences => {
+                return Err(Error::WeightsParse(format!(
+                    "expected one weight per sequence, found {} for {num_sequences} sequences",
+                    weights.len()
+                )));
+            }
+            Some(weights) => Weights::new(weights),
+            None => Weights::uniform(num_sequences),
+        };
+        let column_weights = match &self.column_weights {
+            Some(weights) if weights.len() != alignment_length => {
+                return Err(Error::ColumnWeightsParse(format!(
+                    "expected one weight per column, found {} for {alignment_length} columns",
+                    weights.len()
+                )));
+            }
+            Some(weights) => Weights::new(weights),
+            None => Weights::uniform(alignment_length),
+        };
+        Ok((weights, column_weights))
+    }
+
+    /// Sets the length of a final alignment to the number of columns in which a
+    /// retained sequence has a residue, since all-gap columns are removed from
+    /// the output.
+    fn trim_metrics(
+        &self,
+        metrics: &mut AlignmentMetrics,
+        sequences: &[Vec<u8>],
+        retained: &[usize],
+    ) {
+        if retained.is_empty() {
+            return;
+        }
+        metrics.alignment_length = (0..metrics.alignment_length)
+            .filter(|&col| {
+                retained.iter().any(|&idx| {
+                    sequences[idx]
+                        .get(col)
+                        .is_some_and(|&byte| !self.gap_chars.contains(byte))
+                })
+            })
+            .count();
+        metrics.gap_free_columns = metrics.alignment_area / metrics.sequence_count;
+    }
+
+    /// Runs the heuristic and refinement when only gap-free columns count.
+    fn run_strict(
+        &self,
+        gap_matrix: &[Vec<bool>],
+        alignment_length: usize,
+        weights: &Weights,
+        column_weights: &Weights,
+        budget: &ExclusionBudget,
+    ) -> Result<Stages> {
+        let num_sequences = gap_matrix.len();
+        let (orig_sets, orig_gaps, keep_pattern) =
+            create_sets(gap_matrix, &self.keep, alignment_length);
+
+        let kept_gaps_count = keep_pattern.iter().filter(|&&b| b).count();
+        let initial_gap_free_columns = alignment_length - orig_sets.len() - kept_gaps_count;
+
+        let initial_metrics = AlignmentMetrics::new(
+            num_sequences,
+            initial_gap_free_columns,
+            initial_gap_free_columns * num_sequences,
+            alignment_length,
+        )
+        .with_weighted_area(
+            (0..alignment_length)
+                .filter(|&col| gap_matrix.iter().all(|row| !row[col]))
+                .map(|col| column_weights.get(col))
+                .sum::<usize>()
+                * weights.total(),
+        );
+        self.log_start(&initial_metrics);
+
+        let mut metrics = initial_metrics.clone();
+        let mut state = SetData::new(orig_sets.clone(), orig_gaps.clone(), num_sequences);
+        let iteration_data = run_heuristic(
+            &mut state,
+            &mut metrics,
+            &HeuristicConfig {
+                exclusion_budget: budget.clone(),
+                ..self.heuristic.clone()
+            },
+            &keep_pattern,
+            weights,
+            column_weights,
+            num_sequences,
+        );
+
+        let mut excluded = state.excluded;
+        let gap_free_weight = column_weights.total()
+            - (0..alignment_length)
+                .filter(|&col| keep_pattern[col])
+                .map(|col| column_weights.get(col))
+                .sum::<usize>()
+            - orig_gaps
+                .iter()
+                .map(|gap| column_weights.of_set(gap))
+                .sum::<usize>();
+        let metrics_of = |excluded: &HashSet<usize>| {
+            let excluded = Bitset::from_indices(num_sequences, excluded.iter().copied());
+            let remaining = num_sequences - excluded.count_ones();
+            let (columns, column_weight) = orig_sets
+                .iter()
+                .zip(&orig_gaps)
+                .filter(|(set, _)| set.is_subset(&excluded))
+                .fold(
+                    (initial_gap_free_columns, gap_free_weight),
+                    |(columns, weight), (_, gap)| {
+                        (columns + 1, weight + column_weights.of_set(gap))
+                    },
+                );
+            AlignmentMetrics::new(remaining, columns, columns * remaining, alignment_length)
+                .with_weighted_area((weights.total() - weights.of_set(&excluded)) * column_weight)
+        };
+
+        let polish_moves = self.polish(
+            gap_matrix,
+            &mut excluded,
+            &mut metrics,
+            weights,
+            column_weights,
+            budget,
+            metrics_of,
+        );
+        let heuristic_metrics = metrics.clone();
+        let mut final_metrics = metrics;
+        let mut refinement_status = None;
+        let mut refined = None;
+        let mut solutions = Vec::new();
+
+        if self.refinement {
+            info!(
+                "Starting refinement using the branch-and-bound algorithm to find the optimal solution"
+            );
+            let bb_result = run_branch_and_bound(
+                &orig_sets,
+                &orig_gaps,
+                &heuristic_metrics,
+                &keep_pattern,
+                weights,
+                column_weights,
+                self.refinement_limits,
+                budget,
+                self.solution_pool(|count| {
+                    // Excluding the sequences of a single set is a solution of
+                    // the search, so the smallest sets give more solutions.
+                    let mut seen = HashSet::new();
+                    let mut sets: Vec<&Bitset> =
+                        orig_sets.iter().filter(|set| seen.insert(*set)).collect();
+                    sets.sort_by_key(|set| set.count_ones());
+                    known_solutions(
+                        heuristic_points(&iteration_data, &polish_moves, metrics_of),
+                        sets.into_iter().cloned(),
+                        count,
+                        budget,
+                        metrics_of,
+                    )
+                }),
+            );
+            refinement_status = Some(bb_result.status);
+            if bb_result.metrics.weighted_area > final_metrics.weighted_area {
+                final_metrics = bb_result.metrics;
+                excluded = bb_result.excluded;
+                refined = Some((final_metrics.clone(), excluded.clone()));
+            }
+            solutions = self.list_solutions(bb_result.solutions, &final_metrics, &excluded);
+        }
+
+        let model = self.model.then(|| {
+            let problem = reduce_problem(
+                &orig_sets,
+                &orig_gaps,
+                final_metrics.weighted_area,
+                &keep_pattern,
+                weights,
+                column_weights,
+            );
+            ReducedModel::new(
+                &problem,
+                &orig_sets,
+                &orig_gaps,
+                weights,
+                column_weights,
+                final_metrics.weighted_area,
+                budget,
+            )
+        });
+        if let Some(model) = &model {
+            info!(
+                "Built the reduced model ({} candidate sequences, {} column patterns)",
+                model.candidates.len(),
+                model.patterns.len()
+            );
+            let solved = match &self.model_solution {
+                Some(solution) => Some(model.excluded(solution)?),
+                None if self.solve_model => {
+                    info!("Solving the reduced model with the bundled solver");
+                    Some(model.solve())
+                }
+                None => None,
+            };
+            if let Some(solved) = solved {
+                final_metrics = metrics_of(&solved);
+                excluded = solved;
+            }
+        }
+
+        let pareto_front = if self.pareto_front {
+            let points = heuristic_points(&iteration_data, &polish_moves, metrics_of);
+            self.build_pareto_front(points, refined, num_sequences, |front_excluded, known| {
+                run_branch_and_bound(
+                    &orig_sets,
+                    &orig_gaps,
+                    known,
+                    &keep_pattern,
+                    weights,
+                    column_weights,
+                    self.refinement_limits,
+                    &budget.with_max_excluded(front_excluded),
+                    SolutionPool::Best,
+                )
+            })
+        } else {
+            Vec::new()
+        };
+
+        Ok(Stages {
+            initial_metrics,
+            heuristic_metrics,
+            final_metrics,
+            excluded,
+            iteration_data,
+            polish_moves,
+            refinement_status,
+            pareto_front,
+            solutions,
+            model,
+        })
+    }
+
+    /// Runs the heuristic and refinement when columns may contain some gaps.
+    fn run_tolerant(
+        &self,
+        gap_matrix: &[Vec<bool>],
+        alignment_length: usize,
+        weights: &Weights,
+        column_weights: &Weights,
+        budget: &ExclusionBudget,
+    ) -> Stages {
+        let num_sequences = gap_matrix.len();
+        let patterns = ColumnPatterns::new(gap_matrix, alignment_length, column_weights);
+        let (columns, column_weight) = patterns.counted_columns(
+            &Bitset::new(num_sequences),
+            num_sequences,
+            self.gap_tolerance,
+        );
+
+        let initial_metrics = AlignmentMetrics::new(
+            num_sequences,
+            columns,
+            columns * num_sequences,
+            alignment_length,
+        )
+        .with_weighted_area(column_weight * weights.total());
+        self.log_start(&initial_metrics);
+
+        let mut metrics = initial_metrics.clone();
+        let mut excluded = HashSet::new();
+        let iteration_data = run_tolerant_heuristic(
+            &patterns,
+            &mut metrics,
+            &HeuristicConfig {
+                exclusion_budget: budget.clone(),
+                ..self.heuristic.clone()
+            },
+            &self.keep,
+            weights,
+            self.gap_tolerance,
+            &mut excluded,
+        );
+
+        let metrics_of = |excluded: &HashSet<usize>| {
+            let excluded = Bitset::from_indices(num_sequences, excluded.iter().copied());
+            let remaining = num_sequences - excluded.count_ones();
+            let (columns, column_weight) =
+                patterns.counted_columns(&excluded, remaining, self.gap_tolerance);
+            AlignmentMetrics::new(remaining, columns, columns * remaining, alignment_length)
+                .with_weighted_area((weights.total() - weights.of_set(&excluded)) * column_weight)
+        };
+
+        let polish_moves = self.polish(
+            gap_matrix,
+            &mut excluded,
+            &mut metrics,
+            weights,
+            column_weights,
+            budget,
+            metrics_of,
+        );
+        let heuristic_metrics = metrics.clone();
+        let mut final_metrics = metrics;
+        let mut refinement_status = None;
+        let mut refined = None;
+        let mut solutions = Vec::new();
+
+        if self.refinement {
+            info!(
+                "Starting refinement using the branch-and-bound algorithm to find the optimal solution"
+            );
+            let bb_result = run_tolerant_branch_and_bound(
+                &patterns,
+                &heuristic_metrics,
+                &self.keep,
+                weights,
+                self.gap_tolerance,
+                self.refinement_limits,
+                budget,
+                self.solution_pool(|count| {
+                    // Excluding a single gapped sequence that isn't kept is a
+                    // solution of the search, so the gappiest ones give more
+                    // solutions.
+                    let mut gaps = vec![0; num_sequences];
+                    for (set, &columns) in patterns.sets.iter().zip(&patterns.counts) {
+                        for idx in set.ones() {
+                            gaps[idx] += columns;
+                        }
+                    }
+                    let mut gapped: Vec<usize> = (0..num_sequences)
+                        .filter(|idx| gaps[*idx] > 0 && !self.keep.contains(idx))
+                        .collect();
+                    gapped.sort_by_key(|&idx| std::cmp::Reverse(gaps[idx]));
+                    known_solutions(
+                        heuristic_points(&iteration_data, &polish_moves, metrics_of),
+                        gapped
+                            .into_iter()
+                            .map(|idx| Bitset::from_indices(num_sequences, [idx])),
+                        count,
+                        budget,
+                        metrics_of,
+                    )
+                }),
+            );
+            refinement_status = Some(bb_result.status);
+            if bb_result.metrics.weighted_area > final_metrics.weighted_area {
+                final_metrics = bb_result.metrics;
+                excluded = bb_result.excluded;
+                refined = Some((final_metrics.clone(), excluded.clone()));
+            }
+            solutions = self.list_solutions(bb_result.solutions, &final_metrics, &excluded);
+        }
+
+        let pareto_front = if self.pareto_front {
+            let points = heuristic_points(&iteration_data, &polish_moves, metrics_of);
+            self.build_pareto_front(points, refined, num_sequences, |front_excluded, known| {
+                run_tolerant_branch_and_bound(
+                    &patterns,
+                    known,
+                    &self.keep,
+                    weights,
+                    self.gap_tolerance,
+                    self.refinement_limits,
+                    &budget.with_max_excluded(front_excluded),
+                    SolutionPool::Best,
+                )
+            })
+        } else {
+            Vec::new()
+        };
+
+        Stages {
+            initial_metrics,
+            heuristic_metrics,
+            final_metrics,
+            excluded,
+            iteration_data,
+            polish_moves,
+            refinement_status,
+            pareto_front,
+            solutions,
+            model: None,
+        }
+    }
+
+    /// Builds the Pareto front from the states visited by the heuristic and
+    /// the solution of the refinement, if it improved on the heuristic. With
+    /// the refinement, the front is then tightened with `refine`, which runs
+    /// the branch-and-bound algorithm with a maximum number of excluded
+    /// sequences.
+    fn build_pareto_front(
+        &self,
+        mut points: Vec<ParetoPoint>,
+        refined: Option<(AlignmentMetrics, HashSet<usize>)>,
+        num_sequences: usize,
+        refine: impl Fn(usize, &AlignmentMetrics) -> BranchAndBoundResult,
+    ) -> Vec<ParetoPoint> {
+        if let Some((metrics, excluded)) = refined {
+            points.push(ParetoPoint {
+                metrics,
+                excluded,
+                source: ParetoSource::Refinement,
+            });
+        }
+        if !self.refinement {
+            return pareto_front(points);
+        }
+        info!("Tightening the Pareto front using the branch-and-bound algorithm");
+        refine_front(points, num_sequences, |max_excluded, known| {
+            let bb_result = refine(max_excluded, known);
+            (bb_result.metrics.weighted_area > known.weighted_area)
+                .then_some((bb_result.metrics, bb_result.excluded))
+        })
+    }
+
+    /// Polishes the heuristic solution if enabled, updating `excluded` and
+    /// `metrics`, and returns the moves of the polishing.
+    #[allow(clippy::too_many_arguments)]
+    fn polish(
+        &self,
+        gap_matrix: &[Vec<bool>],
+        excluded: &mut HashSet<usize>,
+        metrics: &mut AlignmentMetrics,
+        weights: &Weights,
+        column_weights: &Weights,
+        budget: &ExclusionBudget,
+        metrics_of: impl Fn(&HashSet<usize>) -> AlignmentMetrics,
+    ) -> Vec<PolishMove> {
+        if !self.polish {
+            return Vec::new();
+        }
+        let moves = polish(
+            gap_matrix,
+            excluded,
+            &self.keep,
+            weights,
+            column_weights,
+            self.gap_tolerance,
+            budget,
+            &self.polish_config,
+        );
+        if let Some(last) = moves.last() {
+            *metrics = metrics_of(excluded);
+            info!(
+                "Polishing made {} move(s) (alignment area: {})",
+                moves.len(),
+                last.alignment_area
+            );
+        }
+        moves
+    }
+
+    /// Returns the solutions to collect in the refinement. To list the best
+    /// solutions, the search starts from the area of that many best distinct
+    /// solutions known, given by `known` for the requested count. If fewer are
+    /// known, the search can't prune anything and explores every solution.
+    fn solution_pool(&self, known: impl FnOnce(usize) -> Vec<ParetoPoint>) -> SolutionPool {
+        match self.top_solutions {
+            Some(count) => {
+                let mut seen = HashSet::new();
+                let mut areas: Vec<usize> = known(count)
+                    .into_iter()
+                    .filter(|point| {
+                        seen.insert(point.excluded.iter().copied().sorted().collect_vec())
+                    })
+                    .map(|point| point.metrics.weighted_area)
+                    .collect();
+                areas.sort_unstable_by_key(|&area| std::cmp::Reverse(area));
+                SolutionPool::Top {
+                    count,
+                    min_area: areas.get(count - 1).copied().unwrap_or(0),
+                }
+            }
+            None if self.solutions => SolutionPool::CoOptimal,
+            None => SolutionPool::Best,
+        }
+    }
+
+    /// Returns the solutions of the refinement to list, with the solution
+    /// returned by default first. If the search didn't find it (e.g., because
+    /// a limit was reached), it is added.
+    fn list_solutions(
+        &self,
+        mut solutions: Vec<Solution>,
+        metrics: &AlignmentMetrics,
+        excluded: &HashSet<usize>,
+    ) -> Vec<Solution> {
+        if !self.solutions {
+            return Vec::new();
+        }
+        let default = solutions
+            .iter()
+            .position(|solution| solution.excluded == *excluded)
+            .map_or_else(
+                || Solution {
+                    metrics: metrics.clone(),
+                    excluded: excluded.clone(),
+                },
+                |idx| solutions.remove(idx),
+            );
+        solutions.insert(0, default);
+        if let Some(count) = self.top_solutions {
+            solutions.truncate(count);
+        }
+        solutions
+    }
+
+    fn log_start(&self, initial_metrics: &AlignmentMetrics) {
+        info!(
+            "Loaded input alignment (sequences: {}, length: {}, initial area: {})",
+            initial_metrics.sequence_count,
+            initial_metrics.alignment_length,
+            initial_metrics.alignment_area
+        );
+        info!(
+            "Processing alignment (heuristic method: {})",
+            self.heuristic.method
+        );
+    }
+}
+
+/// Metrics and exclusions of the stages of a run.
+struct Stages {
+    initial_metrics: AlignmentMetrics,
+    heuristic_metrics: AlignmentMetrics,
+    final_metrics: AlignmentMetrics,
+    excluded: HashSet<usize>,
+    iteration_data: Vec<(Vec<usize>, usize)>,
+    polish_moves: Vec<PolishMove>,
+    refinement_status: Option<RefinementStatus>,
+    pareto_front: Vec<ParetoPoint>,
+    solutions: Vec<Solution>,
+    model: Option<ReducedModel>,
+}
+
+/// Returns the states visited by the heuristic, followed by the solutions that
+/// exclude exactly one of `exclusions`, for up to `count` of those that fit in
+/// `budget`. `metrics_of` computes the metrics of the alignment without the
+/// given sequences.
+fn known_solutions(
+    mut states: Vec<ParetoPoint>,
+    exclusions: impl IntoIterator<Item = Bitset>,
+    count: usize,
+    budget: &ExclusionBudget,
+    metrics_of: impl Fn(&HashSet<usize>) -> AlignmentMetrics,
+) -> Vec<ParetoPoint> {
+    for set in exclusions
+        .into_iter()
+        .filter(|set| budget.admits(set))
+        .take(count)
+    {
+        let excluded: HashSet<usize> = set.ones().collect();
+        states.push(ParetoPoint {
+            metrics: metrics_of(&excluded),
+            excluded,
+            source: ParetoSource::Heuristic,
+        });
+    }
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_sequences_to_keep_beyond_the_alignment() {
+        let sequences = vec![b"AC-GT".to_vec(), b"ACTGT".to_vec()];
+        for builder in [MaxAlign::builder(), MaxAlign::builder().max_column_gaps(1)] {
+            let error = builder.keep([1, 5, 2]).run(&sequences).unwrap_err();
+            assert!(
+                matches!(error, Error::KeepIndex { index: 2, count: 2 }),
+                "{error}"
+            );
+        }
+        assert!(MaxAlign::builder().keep([1]).run(&sequences).is_ok());
+    }
+}
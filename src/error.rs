@@ -10,6 +10,65 @@ pub enum Error {
     #[error("failed to parse FASTA input: {0}")]
     FastaParse(String),
 
+    #[error("failed to parse Stockholm input: {0}")]
+    StockholmParse(String),
+
+    #[error("failed to parse Clustal input: {0}")]
+    ClustalParse(String),
+
+    #[error("failed to parse PHYLIP input: {0}")]
+    PhylipParse(String),
+
+    #[error("failed to parse NEXUS input: {0}")]
+    NexusParse(String),
+
+    #[error("failed to parse A2M/A3M input: {0}")]
+    A3mParse(String),
+
+    #[error("--drop-insertions only applies to A2M/A3M input, but the input is {0}")]
+    DropInsertions(String),
+
+    #[error("failed to parse sequence weights: {0}")]
+    WeightsParse(String),
+
+    #[error("failed to parse column weights: {0}")]
+    ColumnWeightsParse(String),
+
+    #[error("failed to read weights from '{path}': {source}")]
+    WeightsRead {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to parse sequence groups: {0}")]
+    GroupsParse(String),
+
+    #[error("failed to read groups from '{path}': {source}")]
+    GroupsRead {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to read sequence names from '{path}': {source}")]
+    NamesRead {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error(
+        "sequence index {index} to keep is out of range: the alignment has {count} sequence(s)"
+    )]
+    KeepIndex { index: usize, count: usize },
+
+    #[error("every sequence of the input was excluded in advance")]
+    AllExcluded,
+
+    #[error("failed to read input: {0}")]
+    ReadInput(#[source] io::Error),
+
     #[error("input file is empty")]
     EmptyInput,
 
@@ -29,6 +88,48 @@ pub enum Error {
         #[source]
         source: io::Error,
     },
+
+    #[error("failed to write Pareto front to '{path}': {source}")]
+    ParetoWrite {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to write solutions to '{path}': {source}")]
+    SolutionsWrite {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to write model to '{path}': {source}")]
+    ModelWrite {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to read model solution from '{path}': {source}")]
+    ModelSolutionRead {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("failed to parse model solution: {0}")]
+    ModelSolutionParse(String),
+
+    #[error(
+        "can't infer the model format from '{0}': use a .lp, .mps or .wcnf extension or --model-format"
+    )]
+    ModelFormat(PathBuf),
+
+    #[error("failed to create the thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+
+    #[error("solution index {index} is out of range: the refinement listed {count} solution(s)")]
+    SolutionIndex { index: usize, count: usize },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,11 +1,13 @@
 //! FASTA file parsing utilities.
 
 use crate::error::{Error, Result};
-use clio::Input;
+use crate::selection::SequenceSelection;
+use crate::stockholm::StockholmAnnotations;
 use itertools::Itertools;
 use log::warn;
-use needletail::{parse_fastx_file, parse_fastx_stdin};
+use needletail::parse_fastx_reader;
 use std::collections::HashSet;
+use std::io::Read;
 
 /// Extracts the accession (first word) from a FASTA header.
 pub fn get_record_accession_string(record_header: &[u8]) -> Option<String> {
@@ -18,31 +20,60 @@ pub fn get_record_accession_string(record_header: &[u8]) -> Option<String> {
     }
 }
 
-/// Parsed sequence data from a FASTA file.
+/// Parsed sequence data from an alignment file.
 pub struct SequenceData {
     pub headers: Vec<Vec<u8>>,
     pub sequences: Vec<Vec<u8>>,
     pub longest_length: usize,
     pub keep_indices: HashSet<usize>,
+    /// Annotation lines, if the input was a Stockholm file.
+    pub annotations: Option<StockholmAnnotations>,
 }
 
-/// Parses a FASTA file and returns the sequence data.
-pub fn parse_fasta(input: &Input, keep_sequence: &[String]) -> Result<SequenceData> {
-    let reader = if input.is_std() {
-        parse_fastx_stdin()
-    } else {
-        if input.is_empty().unwrap_or(false) {
-            return Err(Error::EmptyInput);
+impl SequenceData {
+    /// Removes the sequences for which `remove` returns `true`, renumbering
+    /// the sequences that must be retained. Annotations are left as they are.
+    pub fn remove_sequences(&mut self, remove: impl Fn(usize) -> bool) {
+        let mut new_index = Vec::with_capacity(self.sequences.len());
+        let mut count = 0;
+        for idx in 0..self.sequences.len() {
+            new_index.push((!remove(idx)).then(|| {
+                count += 1;
+                count - 1
+            }));
         }
-        parse_fastx_file(input.path().to_path_buf())
-    };
+        let kept = |items: Vec<Vec<u8>>| -> Vec<Vec<u8>> {
+            items
+                .into_iter()
+                .zip(&new_index)
+                .filter(|(_, new)| new.is_some())
+                .map(|(item, _)| item)
+                .collect()
+        };
+        self.headers = kept(std::mem::take(&mut self.headers));
+        self.sequences = kept(std::mem::take(&mut self.sequences));
+        self.keep_indices = self
+            .keep_indices
+            .iter()
+            .filter_map(|&idx| new_index[idx])
+            .collect();
+        self.longest_length = self.sequences.iter().map(Vec::len).max().unwrap_or(0);
+    }
+}
 
-    let mut reader = reader.map_err(|e| Error::FastaParse(e.to_string()))?;
+/// Parses a FASTA file and returns the sequence data.
+pub fn parse_fasta(reader: impl Read + Send, keep_sequence: &[String]) -> Result<SequenceData> {
+    let (headers, sequences) = read_fasta_records(reader)?;
+    build_sequence_data(headers, sequences, keep_sequence)
+}
+
+/// Reads the headers and sequences of all records in a FASTA file.
+#[allow(clippy::type_complexity)]
+pub(crate) fn read_fasta_records(reader: impl Read + Send) -> Result<(Vec<Vec<u8>>, Vec<Vec<u8>>)> {
+    let mut reader = parse_fastx_reader(reader).map_err(|e| Error::FastaParse(e.to_string()))?;
 
     let mut headers = Vec::new();
     let mut sequences = Vec::new();
-    let mut longest_length = 0;
-    let keep_set: HashSet<&str> = keep_sequence.iter().map(String::as_str).collect();
 
     while let Some(record) = reader.next() {
         let record = record.map_err(|e| Error::FastaParse(e.to_string()))?;
@@ -51,54 +82,48 @@ pub fn parse_fasta(input: &Input, keep_sequence: &[String]) -> Result<SequenceDa
         let mut sequence_bytes = record.seq().to_vec();
         sequence_bytes.retain(|&b| !b.is_ascii_whitespace());
 
-        longest_length = longest_length.max(sequence_bytes.len());
-
         headers.push(header_bytes);
         sequences.push(sequence_bytes);
     }
 
+    Ok((headers, sequences))
+}
+
+/// Builds the sequence data from parsed records, checking sequence lengths and
+/// resolving the indices of the sequences that must be retained.
+pub(crate) fn build_sequence_data(
+    headers: Vec<Vec<u8>>,
+    sequences: Vec<Vec<u8>>,
+    keep_sequence: &[String],
+) -> Result<SequenceData> {
     if sequences.is_empty() {
         return Err(Error::EmptyInput);
     }
 
-    let (min_length, longest_length_found) = sequences
+    let (min_length, longest_length) = sequences
         .iter()
         .map(Vec::len)
         .minmax()
         .into_option()
         .unwrap_or((0, 0));
 
-    if min_length != longest_length_found {
+    if min_length != longest_length {
         warn!(
-            "Sequences have different lengths ({min_length} to {longest_length_found}). Shorter sequences will be padded with gaps."
+            "Sequences have different lengths ({min_length} to {longest_length}). Shorter sequences will be padded with gaps."
         );
     }
 
-    let mut keep_indices = HashSet::new();
-    let mut found_keep_sequence: HashSet<String> = HashSet::new();
-
-    for (idx, header) in headers.iter().enumerate() {
-        if let Some(accession) = get_record_accession_string(header)
-            && keep_set.contains(accession.as_str())
-        {
-            keep_indices.insert(idx);
-            found_keep_sequence.insert(accession);
-        }
-    }
-
-    for accession in keep_sequence {
-        if !found_keep_sequence.contains(accession) {
-            warn!(
-                "Must-retain sequence '{}' was not found in the input alignment",
-                accession
-            );
-        }
+    let keep_indices = SequenceSelection {
+        names: keep_sequence.to_vec(),
+        ..SequenceSelection::default()
     }
+    .select(&headers, "Must-retain");
 
     Ok(SequenceData {
         headers,
         sequences,
         longest_length,
         keep_indices,
+        annotations: None,
     })
 }
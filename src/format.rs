@@ -0,0 +1,189 @@
+//! Alignment file formats and format detection.
+
+use crate::a3m::parse_a3m;
+use crate::clustal::parse_clustal;
+use crate::compression::{Compression, decompress_reader};
+use crate::error::{Error, Result};
+use crate::fasta::{SequenceData, parse_fasta};
+use crate::nexus::parse_nexus;
+use crate::phylip::parse_phylip;
+use crate::stockholm::{STOCKHOLM_HEADER, parse_stockholm};
+use clio::Input;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Supported alignment file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentFormat {
+    Fasta,
+    Stockholm,
+    Clustal,
+    /// PHYLIP with sequence names truncated or padded to 10 characters.
+    Phylip,
+    /// PHYLIP with whitespace-delimited sequence names of any length.
+    PhylipRelaxed,
+    Nexus,
+    /// FASTA with lowercase and `.` insert states padded to equal row lengths.
+    A2m,
+    /// FASTA with lowercase insert states and no insertion padding.
+    A3m,
+}
+
+impl std::fmt::Display for AlignmentFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Fasta => "fasta",
+            Self::Stockholm => "stockholm",
+            Self::Clustal => "clustal",
+            Self::Phylip => "phylip",
+            Self::PhylipRelaxed => "phylip-relaxed",
+            Self::Nexus => "nexus",
+            Self::A2m => "a2m",
+            Self::A3m => "a3m",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl std::str::FromStr for AlignmentFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fasta" => Ok(Self::Fasta),
+            "stockholm" => Ok(Self::Stockholm),
+            "clustal" => Ok(Self::Clustal),
+            "phylip" => Ok(Self::Phylip),
+            "phylip-relaxed" => Ok(Self::PhylipRelaxed),
+            "nexus" => Ok(Self::Nexus),
+            "a2m" => Ok(Self::A2m),
+            "a3m" => Ok(Self::A3m),
+            _ => Err(format!(
+                "invalid alignment format '{s}': must be fasta, stockholm, clustal, phylip, \
+                 phylip-relaxed, nexus, a2m, or a3m"
+            )),
+        }
+    }
+}
+
+impl AlignmentFormat {
+    /// Returns `true` for formats in which lowercase letters and `.` are
+    /// insert states rather than match columns.
+    #[must_use]
+    pub const fn has_insert_states(self) -> bool {
+        matches!(self, Self::A2m | Self::A3m)
+    }
+
+    /// Guesses the format from the first bytes of a file.
+    #[must_use]
+    pub fn detect(buffer: &[u8]) -> Self {
+        let first_line = buffer
+            .split(|&b| b == b'\n')
+            .map(<[u8]>::trim_ascii)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default();
+
+        if first_line.starts_with(STOCKHOLM_HEADER) {
+            Self::Stockholm
+        } else if first_line.len() >= 6 && first_line[..6].eq_ignore_ascii_case(b"#NEXUS") {
+            Self::Nexus
+        } else if [&b"CLUSTAL"[..], b"MUSCLE", b"PROBCONS"]
+            .iter()
+            .any(|prefix| first_line.starts_with(prefix))
+        {
+            Self::Clustal
+        } else if is_phylip_header(first_line) {
+            // Told apart from strict PHYLIP when the sequences are parsed.
+            Self::PhylipRelaxed
+        } else {
+            Self::Fasta
+        }
+    }
+
+    /// Guesses the format from a file extension, ignoring compression suffixes.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let path = if Compression::from_path(path) == Compression::None {
+            path.to_path_buf()
+        } else {
+            path.with_extension("")
+        };
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "fa" | "fas" | "fasta" | "fna" | "faa" | "afa" | "mfa" => Some(Self::Fasta),
+            "sto" | "sth" | "stk" | "stockholm" => Some(Self::Stockholm),
+            "aln" | "clw" | "clustal" => Some(Self::Clustal),
+            "phy" | "phylip" => Some(Self::PhylipRelaxed),
+            "nex" | "nxs" | "nexus" => Some(Self::Nexus),
+            "a2m" => Some(Self::A2m),
+            "a3m" => Some(Self::A3m),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `true` if the line consists of two integers (the sequence count and
+/// the alignment length), as in the first line of a PHYLIP file.
+fn is_phylip_header(line: &[u8]) -> bool {
+    let fields: Vec<&[u8]> = line
+        .split(u8::is_ascii_whitespace)
+        .filter(|f| !f.is_empty())
+        .collect();
+    fields.len() == 2 && fields.iter().all(|f| f.iter().all(u8::is_ascii_digit))
+}
+
+/// Reads an alignment from the given input, decompressing it if needed. If no
+/// format is given, it is detected from the contents of the input. Since A2M
+/// and A3M files can't be told apart from FASTA by their contents, they are
+/// detected by extension.
+pub fn read_alignment(
+    input: &Input,
+    format: Option<AlignmentFormat>,
+    keep_sequence: &[String],
+) -> Result<(AlignmentFormat, SequenceData)> {
+    let reader: Box<dyn BufRead + Send> = if input.is_std() {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        if input.is_empty().unwrap_or(false) {
+            return Err(Error::EmptyInput);
+        }
+        let file = std::fs::File::open(input.path().to_path_buf()).map_err(Error::ReadInput)?;
+        Box::new(BufReader::new(file))
+    };
+    let mut reader = decompress_reader(reader).map_err(Error::ReadInput)?;
+
+    let detected = format.unwrap_or_else(|| {
+        let detected = match reader.fill_buf() {
+            Ok(buffer) => AlignmentFormat::detect(buffer),
+            Err(_) => AlignmentFormat::Fasta,
+        };
+        let from_extension = (!input.is_std())
+            .then(|| AlignmentFormat::from_path(input.path()))
+            .flatten()
+            .filter(|f| f.has_insert_states());
+        match (detected, from_extension) {
+            (AlignmentFormat::Fasta, Some(format)) => format,
+            _ => detected,
+        }
+    });
+
+    let sequence_data = match detected {
+        AlignmentFormat::Fasta => parse_fasta(reader, keep_sequence),
+        AlignmentFormat::Stockholm => parse_stockholm(reader, keep_sequence),
+        AlignmentFormat::Clustal => parse_clustal(reader, keep_sequence),
+        AlignmentFormat::Phylip | AlignmentFormat::PhylipRelaxed => {
+            let strict = format.map(|format| format == AlignmentFormat::Phylip);
+            let (sequence_data, strict) = parse_phylip(reader, keep_sequence, strict)?;
+            let flavour = if strict {
+                AlignmentFormat::Phylip
+            } else {
+                AlignmentFormat::PhylipRelaxed
+            };
+            return Ok((flavour, sequence_data));
+        }
+        AlignmentFormat::Nexus => parse_nexus(reader, keep_sequence),
+        AlignmentFormat::A2m | AlignmentFormat::A3m => parse_a3m(reader, keep_sequence),
+    }?;
+
+    Ok((detected, sequence_data))
+}
@@ -0,0 +1,433 @@
+//! Sequence groups with retention quotas.
+//!
+//! Sequences can be assigned to groups (taxa, subfamilies, ...), and each group
+//! can be given a quota: a minimum number or fraction of its sequences that
+//! must be retained. Quotas are hard constraints on the optimization, so that
+//! a group is never wiped out just because its sequences are gappier than the
+//! rest.
+
+use crate::alignment::GroupLimit;
+use crate::bitops::Bitset;
+use crate::error::{Error, Result};
+use crate::fasta::get_record_accession_string;
+use log::warn;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Minimum number or fraction of the sequences of a group that must be
+/// retained.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupQuota {
+    /// At least this many sequences.
+    Count(usize),
+    /// At least this fraction of the sequences, rounded up.
+    Fraction(f64),
+}
+
+impl GroupQuota {
+    /// Returns the number of sequences to retain from a group of `size`
+    /// sequences. This is never more than `size`.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn required(self, size: usize) -> usize {
+        match self {
+            Self::Count(count) => count.min(size),
+            Self::Fraction(fraction) => {
+                // Tolerate rounding errors such as 0.3 * 10 = 3.0000000000000004.
+                ((fraction * size as f64 - 1e-9).ceil().max(0.0) as usize).min(size)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for GroupQuota {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Count(count) => write!(f, "{count}"),
+            Self::Fraction(fraction) => write!(f, "{fraction}"),
+        }
+    }
+}
+
+impl std::str::FromStr for GroupQuota {
+    type Err = String;
+
+    /// Parses a count (such as `2`) or, if the value contains a decimal
+    /// point, a fraction between 0 and 1 (such as `0.5`).
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.contains('.') {
+            match s.parse::<f64>() {
+                Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(Self::Fraction(fraction)),
+                _ => Err(format!(
+                    "invalid quota '{s}': fractions must be between 0 and 1"
+                )),
+            }
+        } else {
+            s.parse().map(Self::Count).map_err(|_| {
+                format!("invalid quota '{s}': must be a count or a fraction such as 0.5")
+            })
+        }
+    }
+}
+
+/// Number of sequences of a group in a solution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupSummary {
+    pub name: String,
+    pub size: usize,
+    /// Number of sequences that must be retained.
+    pub required: usize,
+    pub retained: usize,
+    pub excluded: usize,
+}
+
+/// Assignment of sequences to named groups, with an optional quota per group.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SequenceGroups {
+    names: Vec<String>,
+    group_of: Vec<Option<usize>>,
+    quotas: Vec<Option<GroupQuota>>,
+}
+
+impl SequenceGroups {
+    /// Creates groups from the group name of each sequence (`None` for
+    /// sequences outside any group). Groups are numbered in order of first
+    /// appearance and have no quota.
+    #[must_use]
+    pub fn new<S: AsRef<str>>(sequence_groups: &[Option<S>]) -> Self {
+        let mut names: Vec<String> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let group_of = sequence_groups
+            .iter()
+            .map(|name| {
+                name.as_ref().map(|name| {
+                    let name = name.as_ref();
+                    *index.entry(name.to_string()).or_insert_with(|| {
+                        names.push(name.to_string());
+                        names.len() - 1
+                    })
+                })
+            })
+            .collect();
+        let quotas = vec![None; names.len()];
+        Self {
+            names,
+            group_of,
+            quotas,
+        }
+    }
+
+    /// Returns the groups of the sequences at the given indices, in order.
+    /// Groups keep their quota even if none of their sequences are selected.
+    #[must_use]
+    pub fn select(&self, indices: &[usize]) -> Self {
+        Self {
+            names: self.names.clone(),
+            group_of: indices.iter().map(|&idx| self.group_of[idx]).collect(),
+            quotas: self.quotas.clone(),
+        }
+    }
+
+    /// Returns the names of the groups.
+    #[must_use]
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the number of groups.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns `true` if there are no groups.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Returns the number of sequences the groups were created for.
+    #[must_use]
+    pub const fn num_sequences(&self) -> usize {
+        self.group_of.len()
+    }
+
+    /// Returns the index of the group of a sequence, if any.
+    #[must_use]
+    pub fn group_of(&self, sequence: usize) -> Option<usize> {
+        self.group_of.get(sequence).copied().flatten()
+    }
+
+    /// Gives every group this quota.
+    pub fn set_default_quota(&mut self, quota: GroupQuota) {
+        self.quotas.fill(Some(quota));
+    }
+
+    /// Gives a group a quota. Returns `false` if there is no group with this
+    /// name.
+    pub fn set_quota(&mut self, name: &str, quota: GroupQuota) -> bool {
+        match self.names.iter().position(|group| group == name) {
+            Some(group) => {
+                self.quotas[group] = Some(quota);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the quota of a group, if any.
+    #[must_use]
+    pub fn quota(&self, group: usize) -> Option<GroupQuota> {
+        self.quotas[group]
+    }
+
+    /// Returns the number of sequences in each group.
+    #[must_use]
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.len()];
+        for group in self.group_of.iter().flatten() {
+            sizes[*group] += 1;
+        }
+        sizes
+    }
+
+    /// Returns the number of sequences of each group that must be retained.
+    #[must_use]
+    pub fn required(&self) -> Vec<usize> {
+        self.sizes()
+            .into_iter()
+            .zip(&self.quotas)
+            .map(|(size, quota)| quota.map_or(0, |quota| quota.required(size)))
+            .collect()
+    }
+
+    /// Returns the exclusion limits of the groups with a quota, as used by the
+    /// optimization.
+    #[must_use]
+    pub fn limits(&self) -> Vec<GroupLimit> {
+        let num_sequences = self.num_sequences();
+        self.sizes()
+            .into_iter()
+            .zip(self.required())
+            .enumerate()
+            .filter(|&(_, (_, required))| required > 0)
+            .map(|(group, (size, required))| GroupLimit {
+                members: Bitset::from_indices(
+                    num_sequences,
+                    (0..num_sequences).filter(|&idx| self.group_of(idx) == Some(group)),
+                ),
+                max_excluded: size - required,
+            })
+            .collect()
+    }
+
+    /// Counts the retained and excluded sequences of each group.
+    #[must_use]
+    pub fn summarize(&self, is_excluded: impl Fn(usize) -> bool) -> Vec<GroupSummary> {
+        let mut summaries: Vec<GroupSummary> = self
+            .names
+            .iter()
+            .zip(self.required())
+            .map(|(name, required)| GroupSummary {
+                name: name.clone(),
+                size: 0,
+                required,
+                retained: 0,
+                excluded: 0,
+            })
+            .collect();
+        for (idx, group) in self.group_of.iter().enumerate() {
+            if let Some(summary) = group.map(|group| &mut summaries[group]) {
+                summary.size += 1;
+                if is_excluded(idx) {
+                    summary.excluded += 1;
+                } else {
+                    summary.retained += 1;
+                }
+            }
+        }
+        summaries
+    }
+}
+
+/// Reads a tab-separated file with the accession of a sequence in the first
+/// column and the name of its group in the second. Empty lines and lines
+/// starting with `#` are ignored. Sequences that are not listed belong to no
+/// group.
+pub fn read_groups(path: &Path, headers: &[Vec<u8>]) -> Result<SequenceGroups> {
+    let listed: HashMap<String, String> = read_table(path, |line_number| {
+        format!("line {line_number} must contain an accession and a group separated by a tab")
+    })?
+    .into_iter()
+    .collect();
+
+    let mut found = 0;
+    let sequence_groups: Vec<Option<&String>> = headers
+        .iter()
+        .map(|header| {
+            let accession = get_record_accession_string(header).unwrap_or_default();
+            let group = listed.get(&accession);
+            found += usize::from(group.is_some());
+            group
+        })
+        .collect();
+
+    if found < headers.len() {
+        warn!(
+            "{} sequence(s) are not listed in the groups file and belong to no group",
+            headers.len() - found
+        );
+    }
+    if found < listed.len() {
+        warn!(
+            "{} sequence(s) listed in the groups file were not found in the input",
+            listed.len() - found
+        );
+    }
+
+    Ok(SequenceGroups::new(&sequence_groups))
+}
+
+/// Reads a tab-separated file with the name of a group in the first column and
+/// its quota (see [`GroupQuota`]) in the second. Empty lines and lines starting
+/// with `#` are ignored.
+pub fn read_group_quotas(path: &Path) -> Result<Vec<(String, GroupQuota)>> {
+    read_table(path, |line_number| {
+        format!("line {line_number} must contain a group and a quota separated by a tab")
+    })?
+    .into_iter()
+    .map(|(group, quota)| match quota.parse() {
+        Ok(quota) => Ok((group, quota)),
+        Err(e) => Err(Error::GroupsParse(format!("{e} for group '{group}'"))),
+    })
+    .collect()
+}
+
+/// Reads the first two tab-separated fields of each line of a file.
+fn read_table(
+    path: &Path,
+    missing_field: impl Fn(usize) -> String,
+) -> Result<Vec<(String, String)>> {
+    let read_err = |e| Error::GroupsRead {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let reader = crate::compression::open_file(path).map_err(read_err)?;
+
+    let mut rows = Vec::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(read_err)?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(key), Some(value)) = (fields.next(), fields.next()) else {
+            return Err(Error::GroupsParse(missing_field(line_number + 1)));
+        };
+        rows.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Writes a table to a temporary file named after the test.
+    fn write_table(name: &str, text: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("maxalign-rs-{}-{name}.tsv", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_counts_and_fractions() {
+        assert_eq!("1".parse(), Ok(GroupQuota::Count(1)));
+        assert_eq!(" 0 ".parse(), Ok(GroupQuota::Count(0)));
+        assert_eq!("1.0".parse(), Ok(GroupQuota::Fraction(1.0)));
+        assert_eq!(".5".parse(), Ok(GroupQuota::Fraction(0.5)));
+        assert_eq!("0.0".parse(), Ok(GroupQuota::Fraction(0.0)));
+        for value in ["1.5", "-1", "-0.5", "abc", "", "0.5.1"] {
+            assert!(value.parse::<GroupQuota>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn rounds_fractions_up_without_rounding_errors() {
+        assert_eq!(GroupQuota::Count(2).required(5), 2);
+        assert_eq!(GroupQuota::Count(5).required(3), 3);
+        assert_eq!(GroupQuota::Fraction(0.5).required(3), 2);
+        assert_eq!(GroupQuota::Fraction(0.25).required(1), 1);
+        assert_eq!(GroupQuota::Fraction(0.0).required(4), 0);
+        assert_eq!(GroupQuota::Fraction(1.0).required(7), 7);
+        // 0.3 * 10 and 0.1 * 30 are slightly above 3 in floating point.
+        assert_eq!(GroupQuota::Fraction(0.3).required(10), 3);
+        assert_eq!(GroupQuota::Fraction(0.1).required(30), 3);
+        assert_eq!(GroupQuota::Fraction(0.3).required(0), 0);
+    }
+
+    #[test]
+    fn reads_groups_by_accession() {
+        let path = write_table(
+            "groups",
+            "# accession\tgroup\n\nseq1\tA\nseq3\tB\nseq2\tA\nmissing\tC\n",
+        );
+        let headers = [
+            b"seq1 first".to_vec(),
+            b"seq2".to_vec(),
+            b"seq3".to_vec(),
+            b"seq4".to_vec(),
+        ];
+        let groups = read_groups(&path, &headers).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(groups.names(), ["A", "B"]);
+        assert_eq!(
+            (0..4).map(|idx| groups.group_of(idx)).collect::<Vec<_>>(),
+            [Some(0), Some(0), Some(1), None]
+        );
+        assert_eq!(groups.sizes(), [2, 1]);
+
+        let path = write_table("groups-missing-field", "seq1\tA\nseq2 A\n");
+        let error = read_groups(&path, &headers).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("line 2 must contain"), "{error}");
+    }
+
+    #[test]
+    fn reads_quotas_and_reports_unknown_groups() {
+        let mut groups = SequenceGroups::new(&[Some("A"), Some("A"), Some("B"), None]);
+        let path = write_table("quotas", "# group\tquota\nA\t0.5\nC\t1\n");
+        let quotas = read_group_quotas(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            quotas,
+            [
+                ("A".to_string(), GroupQuota::Fraction(0.5)),
+                ("C".to_string(), GroupQuota::Count(1))
+            ]
+        );
+        let unknown: Vec<&str> = quotas
+            .iter()
+            .filter(|(name, quota)| !groups.set_quota(name, *quota))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(unknown, ["C"]);
+        assert_eq!(groups.quota(0), Some(GroupQuota::Fraction(0.5)));
+        assert_eq!(groups.quota(1), None);
+        assert_eq!(groups.required(), [1, 0]);
+
+        let path = write_table("quotas-invalid", "A\t2\nB\t1.5\n");
+        let error = read_group_quotas(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("for group 'B'"), "{error}");
+    }
+}
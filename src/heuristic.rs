@@ -1,26 +1,56 @@
 //! Heuristic algorithm for sequence exclusion.
 
-use crate::alignment::{AlignmentMetrics, SetData, congruent_set_joining, subset_joining};
-use crate::bitops::{
-    bitwise_or, count_bits, count_bits_union, count_bits_union_triple, get_set_bit_indices,
-    pack_bools_to_bits,
-};
+use crate::alignment::{AlignmentMetrics, ColumnPatterns, ExclusionBudget, GapTolerance, SetData};
+use crate::bitops::Bitset;
+use crate::weights::Weights;
 use log::info;
-use std::collections::HashSet;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 
-/// The heuristic method to use for finding sequences to exclude.
+/// The heuristic method to use for finding sequences to exclude. The method
+/// sets the synergy order: the largest number of sets whose union is scored
+/// as a single candidate.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[allow(clippy::enum_variant_names)]
 pub enum HeuristicMethod {
-    NoSynergy = 1,
+    NoSynergy,
     #[default]
-    PairwiseSynergy = 2,
-    TripleSynergy = 3,
+    PairwiseSynergy,
+    TripleSynergy,
+    /// Synergy between up to the given number of sets. Orders below 4 behave
+    /// like the variants above.
+    KWaySynergy(usize),
+}
+
+impl HeuristicMethod {
+    /// Returns the method with the given synergy order, or `None` if the
+    /// order is 0.
+    #[must_use]
+    pub const fn with_order(order: usize) -> Option<Self> {
+        match order {
+            0 => None,
+            1 => Some(Self::NoSynergy),
+            2 => Some(Self::PairwiseSynergy),
+            3 => Some(Self::TripleSynergy),
+            _ => Some(Self::KWaySynergy(order)),
+        }
+    }
+
+    /// Returns the synergy order of the method.
+    #[must_use]
+    pub const fn order(self) -> usize {
+        match self {
+            Self::NoSynergy => 1,
+            Self::PairwiseSynergy => 2,
+            Self::TripleSynergy => 3,
+            Self::KWaySynergy(order) => order,
+        }
+    }
 }
 
 impl std::fmt::Display for HeuristicMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", *self as u8)
+        write!(f, "{}", self.order())
     }
 }
 
@@ -28,14 +58,10 @@ impl std::str::FromStr for HeuristicMethod {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "1" => Ok(Self::NoSynergy),
-            "2" => Ok(Self::PairwiseSynergy),
-            "3" => Ok(Self::TripleSynergy),
-            _ => Err(format!(
-                "invalid heuristic method '{s}': must be 1, 2, or 3"
-            )),
-        }
+        s.parse()
+            .ok()
+            .and_then(Self::with_order)
+            .ok_or_else(|| format!("invalid heuristic method '{s}': must be a positive integer"))
     }
 }
 
@@ -46,69 +72,103 @@ pub struct HeuristicConfig {
     pub max_iterations: u32,
     pub improvement_threshold: f64,
     pub excluded_seqs_threshold: f64,
+    /// Number of partial solutions kept per iteration (see
+    /// [`run_heuristic`]). With 1, the heuristic is greedy.
+    pub beam_width: usize,
+    /// Hard limits on the excluded sequences, in total and per group.
+    /// Candidates that would exceed them are never chosen.
+    pub exclusion_budget: ExclusionBudget,
 }
 
-/// Runs the heuristic algorithm to find sequences to exclude.
+/// Runs the heuristic algorithm to find sequences to exclude. The objective is
+/// the weighted alignment area (`metrics.weighted_area`), where `weights` holds
+/// the weights of the original sequences and `column_weights` those of the
+/// alignment columns.
+///
+/// The working sets are kept up to date across iterations (see
+/// [`WorkingSets`]) rather than rebuilt after each exclusion, so the cost of
+/// an iteration doesn't grow with the number of iterations already run.
+///
+/// With a beam width above 1, the heuristic keeps that many partial solutions
+/// per iteration instead of a single one (see [`run_beam_heuristic`]).
 #[allow(clippy::cast_precision_loss)]
 pub fn run_heuristic(
     state: &mut SetData,
     metrics: &mut AlignmentMetrics,
     config: &HeuristicConfig,
     keep_pattern: &[bool],
+    weights: &Weights,
+    column_weights: &Weights,
     num_orig_seqs: usize,
 ) -> Vec<(Vec<usize>, usize)> {
+    if config.beam_width > 1 {
+        return run_beam_heuristic(
+            state,
+            metrics,
+            config,
+            keep_pattern,
+            weights,
+            column_weights,
+            num_orig_seqs,
+        );
+    }
+
     let kept_gaps_count = keep_pattern.iter().filter(|&&b| b).count();
+    let kept_gaps_weight: usize = keep_pattern
+        .iter()
+        .enumerate()
+        .filter(|&(_, &kept)| kept)
+        .map(|(col, _)| column_weights.get(col))
+        .sum();
     let mut iteration_data = Vec::new();
     let mut iterations_count: u32 = 0;
+    let mut working = WorkingSets::new(state, num_orig_seqs, column_weights);
+    let mut retained_weight: usize = state.translation.iter().map(|&idx| weights.get(idx)).sum();
 
     loop {
         if iterations_count >= config.max_iterations {
             break;
         }
 
-        let (working_sets, working_gaps) = create_working_sets(
-            &state.sets,
-            &state.gaps,
-            &state.excluded,
-            &state.translation,
-            num_orig_seqs,
-        );
-
         let sequence_count = state.translation.len();
-        let gap_free_columns = metrics.alignment_length - working_sets.len() - kept_gaps_count;
+        let gap_free_columns = metrics.alignment_length - working.gapped_count - kept_gaps_count;
+        let gap_free_weight = column_weights.total() - kept_gaps_weight - working.gapped_weight;
 
         metrics.sequence_count = sequence_count;
         metrics.gap_free_columns = gap_free_columns;
 
-        let mut current_sets = working_sets;
-        let mut current_gaps = working_gaps;
-
-        congruent_set_joining(
-            &mut current_sets,
-            &mut current_gaps,
-            metrics.alignment_area,
-            metrics.sequence_count,
-            metrics.alignment_length,
+        let (current_sets, current_gaps) = working.current(
+            metrics.weighted_area,
+            retained_weight,
+            weights,
+            column_weights,
         );
 
-        subset_joining(&current_sets, &mut current_gaps);
-
         if current_sets.is_empty() {
             break;
         }
 
-        let (best_set, new_alignment_area) = find_greatest_impact_set(
+        let (best_set, new_weighted_area, freed_columns) = find_greatest_impact_sets(
             &current_sets,
             &current_gaps,
-            metrics.alignment_area,
-            sequence_count,
-            gap_free_columns,
+            metrics.weighted_area,
+            weights,
+            retained_weight,
+            column_weights,
+            gap_free_weight,
             config.method,
-        );
+            &config.exclusion_budget.remaining(&Bitset::from_indices(
+                num_orig_seqs,
+                state.excluded.iter().copied(),
+            )),
+            1,
+        )
+        .pop()
+        .unwrap_or_default();
 
-        if config.improvement_threshold != 0.0 && metrics.alignment_area != 0 {
-            let improvement = (new_alignment_area as f64 - metrics.alignment_area as f64)
-                / metrics.alignment_area as f64;
+        if config.improvement_threshold != 0.0 && metrics.weighted_area != 0 {
+            let improvement = (new_weighted_area as f64 - metrics.weighted_area as f64)
+                / metrics.weighted_area as f64;
             if improvement < config.improvement_threshold {
                 info!(
                     "Early stopping: relative improvement ({:.4}) is below threshold ({:.4})",
@@ -127,122 +187,1286 @@ pub fn run_heuristic(
             break;
         }
 
-        if metrics.alignment_area >= new_alignment_area {
+        if metrics.weighted_area >= new_weighted_area {
             break;
         }
 
-        let excluded_indices = get_set_bit_indices(&best_set, sequence_count);
-        let mut exseq = Vec::with_capacity(excluded_indices.len());
-        for pointer in excluded_indices {
-            let orig_idx = state.translation[pointer];
-            state.excluded.insert(orig_idx);
-            exseq.push(orig_idx);
-        }
+        let exseq: Vec<usize> = best_set.ones().collect();
+        state.excluded.extend(&exseq);
+        retained_weight -= weights.of_set(&best_set);
+        working.exclude(&best_set, &state.gaps, column_weights);
 
         state.translation = (0..num_orig_seqs)
             .filter(|&i| !state.excluded.contains(&i))
             .collect();
 
         metrics.sequence_count = state.translation.len();
-        metrics.alignment_area = new_alignment_area;
-        iteration_data.push((exseq, new_alignment_area));
+        metrics.gap_free_columns = gap_free_columns + freed_columns;
+        metrics.alignment_area = metrics.sequence_count * metrics.gap_free_columns;
+        metrics.weighted_area = new_weighted_area;
+        iteration_data.push((exseq, metrics.alignment_area));
         iterations_count += 1;
     }
 
     iteration_data
 }
 
-/// Finds the set that, when excluded, provides the greatest improvement.
-#[allow(clippy::cast_precision_loss, clippy::float_cmp)]
-fn find_greatest_impact_set(
-    sets: &[Vec<u8>],
-    gaps: &[Vec<u8>],
+/// A partial solution of [`run_beam_heuristic`].
+#[derive(Clone)]
+struct BeamState {
+    excluded: Bitset,
+    working: WorkingSets,
+    retained_weight: usize,
+    gap_free_columns: usize,
+    weighted_area: usize,
+    iteration_data: Vec<(Vec<usize>, usize)>,
+    /// Whether the state was reached by the greedy heuristic.
+    greedy: bool,
+}
+
+/// Runs the heuristic as a beam search. Each iteration scores the candidates
+/// of every partial solution as the greedy heuristic does, but keeps the
+/// `beam_width` best candidates of each instead of only the best one. Among
+/// the resulting solutions, those with the largest weighted area (excluding
+/// the least weight on ties) form the next beam, along with the solution the
+/// greedy heuristic would reach, so the result is never worse than the greedy
+/// one. The search stops when no
+/// candidate enlarges the area, and the best solution visited is returned.
+/// The stopping thresholds of the configuration apply to each partial
+/// solution.
+#[allow(clippy::cast_precision_loss)]
+fn run_beam_heuristic(
+    state: &mut SetData,
+    metrics: &mut AlignmentMetrics,
+    config: &HeuristicConfig,
+    keep_pattern: &[bool],
+    weights: &Weights,
+    column_weights: &Weights,
+    num_orig_seqs: usize,
+) -> Vec<(Vec<usize>, usize)> {
+    let kept_gaps_count = keep_pattern.iter().filter(|&&b| b).count();
+    let kept_gaps_weight: usize = keep_pattern
+        .iter()
+        .enumerate()
+        .filter(|&(_, &kept)| kept)
+        .map(|(col, _)| column_weights.get(col))
+        .sum();
+    let working = WorkingSets::new(state, num_orig_seqs, column_weights);
+    let initial = BeamState {
+        excluded: Bitset::from_indices(num_orig_seqs, state.excluded.iter().copied()),
+        gap_free_columns: metrics.alignment_length - working.gapped_count - kept_gaps_count,
+        working,
+        retained_weight: state.translation.iter().map(|&idx| weights.get(idx)).sum(),
+        weighted_area: metrics.weighted_area,
+        iteration_data: Vec::new(),
+        greedy: true,
+    };
+    let mut best = initial.clone();
+    let mut beam = vec![initial];
+    let mut iterations_count: u32 = 0;
+
+    while iterations_count < config.max_iterations {
+        // Candidates of all partial solutions: parent, set, weighted area,
+        // freed columns and whether the greedy heuristic would choose it.
+        let mut children: Vec<(usize, Bitset, usize, usize, bool)> = Vec::new();
+        for (parent, partial) in beam.iter().enumerate() {
+            let excluded_fraction = partial.excluded.count_ones() as f64 / num_orig_seqs as f64;
+            if excluded_fraction >= config.excluded_seqs_threshold {
+                continue;
+            }
+            let gap_free_weight =
+                column_weights.total() - kept_gaps_weight - partial.working.gapped_weight;
+            let (current_sets, current_gaps) = partial.working.current(
+                partial.weighted_area,
+                partial.retained_weight,
+                weights,
+                column_weights,
+            );
+            if current_sets.is_empty() {
+                continue;
+            }
+            for (rank, (set, new_weighted_area, freed_columns)) in find_greatest_impact_sets(
+                &current_sets,
+                &current_gaps,
+                partial.weighted_area,
+                weights,
+                partial.retained_weight,
+                column_weights,
+                gap_free_weight,
+                config.method,
+                &config.exclusion_budget.remaining(&partial.excluded),
+                config.beam_width,
+            )
+            .into_iter()
+            .enumerate()
+            {
+                if new_weighted_area <= partial.weighted_area {
+                    continue;
+                }
+                if config.improvement_threshold != 0.0 && partial.weighted_area != 0 {
+                    let improvement = (new_weighted_area as f64 - partial.weighted_area as f64)
+                        / partial.weighted_area as f64;
+                    if improvement < config.improvement_threshold {
+                        continue;
+                    }
+                }
+                children.push((
+                    parent,
+                    set,
+                    new_weighted_area,
+                    freed_columns,
+                    partial.greedy && rank == 0,
+                ));
+            }
+        }
+        if children.is_empty() {
+            break;
+        }
+
+        // The solution of the greedy heuristic is always kept, so that the
+        // search never ends below it.
+        children.sort_by_key(|(parent, set, area, _, greedy)| {
+            (
+                !greedy,
+                std::cmp::Reverse(*area),
+                weights.of_set(&beam[*parent].excluded) + weights.of_set(set),
+            )
+        });
+        let mut seen = HashSet::new();
+        let mut next_beam = Vec::with_capacity(config.beam_width);
+        for (parent, set, new_weighted_area, freed_columns, greedy) in children {
+            if next_beam.len() == config.beam_width {
+                break;
+            }
+            let excluded = beam[parent].excluded.union(&set);
+            if !seen.insert(excluded.clone()) {
+                continue;
+            }
+            let mut child = beam[parent].clone();
+            child.working.exclude(&set, &state.gaps, column_weights);
+            child.excluded = excluded;
+            child.retained_weight -= weights.of_set(&set);
+            child.gap_free_columns =
+                metrics.alignment_length - beam[parent].working.gapped_count - kept_gaps_count
+                    + freed_columns;
+            child.weighted_area = new_weighted_area;
+            child.greedy = greedy;
+            let sequence_count = num_orig_seqs - child.excluded.count_ones();
+            child.iteration_data.push((
+                set.ones().collect(),
+                sequence_count * child.gap_free_columns,
+            ));
+            next_beam.push(child);
+        }
+        if let Some(child) = next_beam
+            .iter()
+            .rev()
+            .filter(|child| child.weighted_area > best.weighted_area)
+            .max_by_key(|child| child.weighted_area)
+        {
+            best = child.clone();
+        }
+        beam = next_beam;
+        iterations_count += 1;
+    }
+
+    info!(
+        "Beam search kept up to {} partial solutions per iteration; the best one was reached after {} iterations",
+        config.beam_width,
+        best.iteration_data.len()
+    );
+    state.excluded = best.excluded.ones().collect();
+    state.translation = (0..num_orig_seqs)
+        .filter(|&i| !state.excluded.contains(&i))
+        .collect();
+    metrics.sequence_count = state.translation.len();
+    metrics.gap_free_columns = best.gap_free_columns;
+    metrics.alignment_area = metrics.sequence_count * metrics.gap_free_columns;
+    metrics.weighted_area = best.weighted_area;
+    best.iteration_data
+}
+
+/// Finds the sets that, when excluded, provide the greatest improvement of the
+/// weighted area per unit of excluded weight. `total_weight` is the weight of
+/// the retained sequences and `gap_free_columns` the total weight of the
+/// gap-free columns. Candidates that `budget` doesn't admit are skipped.
+/// Returns up to `count` candidates, best first, each with its set,
+/// the resulting weighted area and the number of columns that become gap-free.
+///
+/// Candidates are the unions of up to `method.order()` sets, enumerated depth
+/// first (see [`SynergySearch`]). A union is not scored or extended once it
+/// holds too much weight to enlarge the area even if all gap columns became
+/// gap-free, the bound [`set_elimination`](crate::alignment::set_elimination)
+/// applies to single sets.
+///
+/// The candidates that start with each set are scored in parallel on the
+/// current rayon thread pool, and the best candidates of each set are then
+/// combined in order, so the chosen sets and tie-breaking don't depend on the
+/// number of threads.
+#[allow(clippy::too_many_arguments)]
+fn find_greatest_impact_sets(
+    sets: &[Bitset],
+    gaps: &[Bitset],
     current_area: usize,
-    sequence_count: usize,
+    weights: &Weights,
+    total_weight: usize,
+    column_weights: &Weights,
     gap_free_columns: usize,
     method: HeuristicMethod,
-) -> (Vec<u8>, usize) {
-    let mut best_set = Vec::new();
-    let mut best_impact = 0;
-    let mut best_efficiency = -1.0;
-    let mut best_gap_count = 0;
-
-    let mut evaluate_candidate =
-        |set_size: usize, gap_count: usize, candidate_fn: &dyn Fn() -> Vec<u8>| {
-            let this_impact = (sequence_count - set_size) * (gap_free_columns + gap_count);
-            let this_efficiency = (this_impact as f64 - current_area as f64) / set_size as f64;
-
-            if this_efficiency > best_efficiency
-                || (this_efficiency == best_efficiency && gap_count >= best_gap_count)
-            {
-                best_efficiency = this_efficiency;
-                best_impact = this_impact;
-                best_set = candidate_fn();
-                best_gap_count = gap_count;
+    budget: &ExclusionBudget,
+    count: usize,
+) -> Vec<(Bitset, usize, usize)> {
+    let search = SynergySearch {
+        sets,
+        gaps,
+        weights,
+        column_weights,
+        total_weight,
+        gap_free_columns,
+        current_area,
+        max_columns: column_weights.total()
+            - crate::alignment::get_gap_columns(gaps, column_weights, gap_free_columns),
+        order: method.order(),
+        budget: (!budget.is_unlimited()).then_some(budget),
+    };
+
+    let best = (0..sets.len())
+        .into_par_iter()
+        .map(|i| {
+            let mut best = Ranking::new(count);
+            let mut position = (i, 0);
+            let set_weight = weights.of_set(&sets[i]);
+            if search.is_hopeless(set_weight) || search.exceeds_budget(&sets[i]) {
+                return best;
+            }
+            best.offer(
+                search.score(set_weight, column_weights.of_set(&gaps[i]), &mut position),
+                &[],
+                i,
+            );
+            let mut path = vec![i];
+            let mut scratch = vec![
+                (Bitset::new(sets[i].width()), Bitset::new(gaps[i].width()));
+                search.order.saturating_sub(2)
+            ];
+            search.extend(
+                &mut path,
+                &sets[i],
+                &gaps[i],
+                &mut scratch,
+                &mut best,
+                &mut position,
+            );
+            best
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .fold(Ranking::new(count), |mut best, ranking| {
+            for candidate in ranking.best {
+                best.insert(candidate);
+            }
+            best
+        });
+
+    best.best
+        .into_iter()
+        .map(|candidate| {
+            let mut set = Bitset::new(sets[0].width());
+            let mut candidate_gaps = Bitset::new(gaps[0].width());
+            for idx in candidate.members {
+                set.union_with(&sets[idx]);
+                candidate_gaps.union_with(&gaps[idx]);
+            }
+            (set, candidate.impact, candidate_gaps.count_ones())
+        })
+        .collect()
+}
+
+/// Relative gain in area per unit of excluded weight. Sets of zero weight are
+/// free to exclude: they rank above all others if they gain area, below all
+/// others if they lose area, and at zero otherwise.
+#[allow(clippy::cast_precision_loss)]
+fn efficiency(impact: usize, current_area: usize, set_weight: usize) -> f64 {
+    let gain = impact as f64 - current_area as f64;
+    if set_weight > 0 {
+        gain / set_weight as f64
+    } else if impact > current_area {
+        f64::INFINITY
+    } else if impact < current_area {
+        f64::NEG_INFINITY
+    } else {
+        0.0
+    }
+}
+
+/// Enumeration of the candidates of [`find_greatest_impact_sets`]. The members
+/// of a candidate are listed in decreasing order, and a candidate is scored
+/// before the candidates that extend it, so the order of the scored
+/// candidates follows that of the members.
+struct SynergySearch<'a> {
+    sets: &'a [Bitset],
+    gaps: &'a [Bitset],
+    weights: &'a Weights,
+    column_weights: &'a Weights,
+    total_weight: usize,
+    gap_free_columns: usize,
+    current_area: usize,
+    /// Total column weight that can become gap-free.
+    max_columns: usize,
+    order: usize,
+    /// Limits on the sequences of a candidate, if any.
+    budget: Option<&'a ExclusionBudget>,
+}
+
+impl SynergySearch<'_> {
+    /// Returns `true` if excluding `set_weight` can't enlarge the area.
+    const fn is_hopeless(&self, set_weight: usize) -> bool {
+        self.current_area >= self.max_columns * (self.total_weight - set_weight)
+    }
+
+    /// Returns `true` if excluding `set` exceeds the budget.
+    fn exceeds_budget(&self, set: &Bitset) -> bool {
+        self.budget.is_some_and(|budget| !budget.admits(set))
+    }
+
+    /// Returns `true` if the union of `set` with the set `j` can't be a
+    /// candidate nor be extended into one, given the weight of the union.
+    fn prunes(&self, set: &Bitset, j: usize, set_weight: usize) -> bool {
+        self.is_hopeless(set_weight)
+            || self
+                .budget
+                .is_some_and(|budget| !budget.admits_union(set, &self.sets[j]))
+    }
+
+    fn score(
+        &self,
+        set_weight: usize,
+        gap_weight: usize,
+        position: &mut (usize, usize),
+    ) -> ScoredCandidate {
+        position.1 += 1;
+        let impact = (self.total_weight - set_weight) * (self.gap_free_columns + gap_weight);
+        ScoredCandidate {
+            members: Vec::new(),
+            position: *position,
+            impact,
+            efficiency: efficiency(impact, self.current_area, set_weight),
+            gap_weight,
+        }
+    }
+
+    /// Scores the candidates that extend `path`, whose union is `set` with
+    /// the gap vector `gaps`, by sets with a lower index. `scratch` holds the
+    /// unions of the deeper levels.
+    fn extend(
+        &self,
+        path: &mut Vec<usize>,
+        set: &Bitset,
+        gaps: &Bitset,
+        scratch: &mut [(Bitset, Bitset)],
+        best: &mut Ranking,
+        position: &mut (usize, usize),
+    ) {
+        if path.len() >= self.order {
+            return;
+        }
+        let last = path[path.len() - 1];
+        let Some(((union_set, union_gaps), rest)) = scratch.split_first_mut() else {
+            // The last level, where unions aren't extended any further.
+            for j in 0..last {
+                let set_weight = self.weights.of_union(set, &self.sets[j]);
+                if !self.prunes(set, j, set_weight) {
+                    best.offer(
+                        self.score(
+                            set_weight,
+                            self.column_weights.of_union(gaps, &self.gaps[j]),
+                            position,
+                        ),
+                        path,
+                        j,
+                    );
+                }
             }
+            return;
         };
+        for j in 0..last {
+            let set_weight = self.weights.of_union(set, &self.sets[j]);
+            if self.prunes(set, j, set_weight) {
+                continue;
+            }
+            best.offer(
+                self.score(
+                    set_weight,
+                    self.column_weights.of_union(gaps, &self.gaps[j]),
+                    position,
+                ),
+                path,
+                j,
+            );
+            union_set.clone_from(set);
+            union_set.union_with(&self.sets[j]);
+            union_gaps.clone_from(gaps);
+            union_gaps.union_with(&self.gaps[j]);
+            path.push(j);
+            self.extend(path, union_set, union_gaps, rest, best, position);
+            path.pop();
+        }
+    }
+}
 
-    for (i, set_i) in sets.iter().enumerate() {
-        evaluate_candidate(count_bits(set_i), count_bits(&gaps[i]), &|| set_i.to_vec());
+/// A candidate of [`find_greatest_impact_sets`]: a union of sets.
+struct ScoredCandidate {
+    /// Indices of the sets, filled in once the candidate is ranked.
+    members: Vec<usize>,
+    /// First set and order in which the candidate was scored among those that
+    /// start with it.
+    position: (usize, usize),
+    impact: usize,
+    efficiency: f64,
+    gap_weight: usize,
+}
 
-        if method as u8 >= 2 {
-            for j in 0..i {
-                evaluate_candidate(
-                    count_bits_union(set_i, &sets[j]),
-                    count_bits_union(&gaps[i], &gaps[j]),
-                    &|| bitwise_or(set_i, &sets[j]),
-                );
+impl ScoredCandidate {
+    /// Returns `true` if this candidate ranks above `other`. Candidates with a
+    /// higher efficiency rank higher, and ties are broken in favour of the
+    /// candidate that frees more column weight, then of the later one.
+    #[allow(clippy::float_cmp)]
+    fn beats(&self, other: &Self) -> bool {
+        self.efficiency > other.efficiency
+            || (self.efficiency == other.efficiency
+                && (self.gap_weight, self.position) > (other.gap_weight, other.position))
+    }
+}
 
-                if method as u8 >= 3 {
-                    for k in 0..j {
-                        evaluate_candidate(
-                            count_bits_union_triple(set_i, &sets[j], &sets[k]),
-                            count_bits_union_triple(&gaps[i], &gaps[j], &gaps[k]),
-                            &|| bitwise_or(&bitwise_or(set_i, &sets[j]), &sets[k]),
-                        );
-                    }
+/// The best candidates offered so far, best first.
+struct Ranking {
+    count: usize,
+    best: Vec<ScoredCandidate>,
+}
+
+impl Ranking {
+    fn new(count: usize) -> Self {
+        Self {
+            count,
+            best: Vec::with_capacity(count + 1),
+        }
+    }
+
+    /// Adds a candidate with the sets `path` and `last` if it ranks among the
+    /// best ones.
+    fn offer(&mut self, mut candidate: ScoredCandidate, path: &[usize], last: usize) {
+        if self.admits(&candidate) {
+            candidate.members = path.iter().copied().chain([last]).collect();
+            self.place(candidate);
+        }
+    }
+
+    /// Adds a candidate if it ranks among the best ones.
+    fn insert(&mut self, candidate: ScoredCandidate) {
+        if self.admits(&candidate) {
+            self.place(candidate);
+        }
+    }
+
+    /// Returns `true` if a candidate ranks among the best ones. Candidates must
+    /// have an efficiency of at least -1.
+    fn admits(&self, candidate: &ScoredCandidate) -> bool {
+        candidate.efficiency >= -1.0
+            && self
+                .best
+                .get(self.count - 1)
+                .is_none_or(|last| candidate.beats(last))
+    }
+
+    fn place(&mut self, candidate: ScoredCandidate) {
+        let pos = self.best.partition_point(|other| other.beats(&candidate));
+        self.best.insert(pos, candidate);
+        self.best.truncate(self.count);
+    }
+}
+
+/// Gap patterns of the columns that still have gaps in the retained sequences,
+/// maintained across the iterations of [`run_heuristic`].
+///
+/// Patterns are kept in the index space of the original sequences, so that
+/// excluding sequences only clears bits. After an exclusion, only the patterns
+/// that contained an excluded sequence are merged with equal patterns and have
+/// their subset relations updated, instead of rebuilding the working sets and
+/// joining them again from scratch.
+#[derive(Clone)]
+struct WorkingSets {
+    /// Distinct patterns. Patterns without members were merged into an equal
+    /// pattern or no longer have gaps in the retained sequences.
+    patterns: Vec<Pattern>,
+    /// Pattern of each original set, or `None` once the set has no gaps in the
+    /// retained sequences.
+    pattern_of: Vec<Option<usize>>,
+    /// Index of each distinct pattern in `patterns`.
+    lookup: HashMap<Bitset, usize>,
+    /// Number of original sets that still have gaps in the retained sequences.
+    gapped_count: usize,
+    /// Total column weight of the gap vectors of these sets.
+    gapped_weight: usize,
+}
+
+/// A distinct gap pattern of [`WorkingSets`].
+#[derive(Clone, Default)]
+struct Pattern {
+    set: Bitset,
+    /// Union of the gap vectors of the original sets with this pattern.
+    gaps: Bitset,
+    /// Union of the gap vectors of this pattern and of all of its subsets.
+    joined_gaps: Bitset,
+    /// Original sets with this pattern, in increasing order.
+    members: Vec<usize>,
+}
+
+impl WorkingSets {
+    fn new(state: &SetData, num_orig_seqs: usize, column_weights: &Weights) -> Self {
+        let excluded = Bitset::from_indices(num_orig_seqs, state.excluded.iter().copied());
+        let mut working = Self {
+            patterns: Vec::new(),
+            pattern_of: vec![None; state.sets.len()],
+            lookup: HashMap::new(),
+            gapped_count: 0,
+            gapped_weight: 0,
+        };
+
+        for (i, (set, gaps)) in state.sets.iter().zip(&state.gaps).enumerate() {
+            let mut set = set.clone();
+            set.difference_with(&excluded);
+            if !set.any() {
+                continue;
+            }
+            working.gapped_count += 1;
+            working.gapped_weight += column_weights.of_set(gaps);
+
+            let id = if let Some(&id) = working.lookup.get(&set) {
+                id
+            } else {
+                let id = working.patterns.len();
+                working.lookup.insert(set.clone(), id);
+                working.patterns.push(Pattern {
+                    set,
+                    gaps: Bitset::new(gaps.width()),
+                    ..Pattern::default()
+                });
+                id
+            };
+            working.patterns[id].gaps.union_with(gaps);
+            working.patterns[id].members.push(i);
+            working.pattern_of[i] = Some(id);
+        }
+
+        let all: Vec<usize> = (0..working.patterns.len()).collect();
+        working.join_subsets(&all);
+        working
+    }
+
+    /// Removes the `excluded` sequences from the patterns. Patterns left
+    /// without gaps are dropped, and patterns that become equal are merged.
+    fn exclude(&mut self, excluded: &Bitset, orig_gaps: &[Bitset], column_weights: &Weights) {
+        let mut touched = Vec::new();
+        for (id, pattern) in self.patterns.iter_mut().enumerate() {
+            if pattern.members.is_empty() || pattern.set.is_disjoint(excluded) {
+                continue;
+            }
+            self.lookup.remove(&pattern.set);
+            pattern.set.difference_with(excluded);
+            if pattern.set.any() {
+                touched.push(id);
+                continue;
+            }
+            for &member in &pattern.members {
+                self.pattern_of[member] = None;
+                self.gapped_weight -= column_weights.of_set(&orig_gaps[member]);
+            }
+            self.gapped_count -= pattern.members.len();
+            pattern.members.clear();
+        }
+
+        let mut changed = Vec::with_capacity(touched.len());
+        for id in touched {
+            if let Some(&target) = self.lookup.get(&self.patterns[id].set) {
+                let merged = std::mem::take(&mut self.patterns[id]);
+                for &member in &merged.members {
+                    self.pattern_of[member] = Some(target);
                 }
+                let pattern = &mut self.patterns[target];
+                pattern.gaps.union_with(&merged.gaps);
+                pattern.members.extend(merged.members);
+                pattern.members.sort_unstable();
+                changed.push(target);
+            } else {
+                self.lookup.insert(self.patterns[id].set.clone(), id);
+                changed.push(id);
             }
         }
+        changed.sort_unstable();
+        changed.dedup();
+        self.join_subsets(&changed);
     }
 
-    (best_set, best_impact)
+    /// Updates the joined gaps after the `changed` patterns were created or
+    /// modified. Since excluding sequences preserves subset relations, only
+    /// the changed patterns can have become subsets of other patterns.
+    fn join_subsets(&mut self, changed: &[usize]) {
+        for &id in changed {
+            let mut joined_gaps = Bitset::new(self.patterns[id].gaps.width());
+            for other in &self.patterns {
+                if !other.members.is_empty() && other.set.is_subset(&self.patterns[id].set) {
+                    joined_gaps.union_with(&other.gaps);
+                }
+            }
+            self.patterns[id].joined_gaps = joined_gaps;
+        }
+        for &id in changed {
+            let (set, gaps) = (&self.patterns[id].set, &self.patterns[id].gaps);
+            let supersets: Vec<usize> = (0..self.patterns.len())
+                .filter(|&other| {
+                    other != id
+                        && !self.patterns[other].members.is_empty()
+                        && set.is_subset(&self.patterns[other].set)
+                })
+                .collect();
+            let gaps = gaps.clone();
+            for other in supersets {
+                self.patterns[other].joined_gaps.union_with(&gaps);
+            }
+        }
+    }
+
+    /// Returns the working sets and their joined gap vectors in the order in
+    /// which [`create_working_sets`], [`crate::alignment::congruent_set_joining`]
+    /// and [`crate::alignment::subset_joining`] produce them, so that ties
+    /// between candidates are broken in the same way. Patterns whose exclusion
+    /// can't increase `alignment_area` are left out.
+    fn current(
+        &self,
+        alignment_area: usize,
+        total_weight: usize,
+        weights: &Weights,
+        column_weights: &Weights,
+    ) -> (Vec<Bitset>, Vec<Bitset>) {
+        let total_column_weight = column_weights.total();
+        let hopeless: Vec<bool> = self
+            .patterns
+            .iter()
+            .map(|pattern| {
+                !pattern.members.is_empty()
+                    && alignment_area
+                        > total_column_weight * (total_weight - weights.of_set(&pattern.set))
+            })
+            .collect();
+
+        // Each pattern is represented by its last member, and the others are
+        // removed with `swap_remove` in decreasing order of position.
+        let mut order: Vec<(usize, usize)> = self
+            .pattern_of
+            .iter()
+            .enumerate()
+            .filter_map(|(member, &id)| id.map(|id| (member, id)))
+            .collect();
+        for pos in (0..order.len()).rev() {
+            let (member, id) = order[pos];
+            if hopeless[id] || self.patterns[id].members.last() != Some(&member) {
+                order.swap_remove(pos);
+            }
+        }
+
+        order
+            .into_iter()
+            .map(|(_, id)| {
+                let pattern = &self.patterns[id];
+                (pattern.set.clone(), pattern.joined_gaps.clone())
+            })
+            .unzip()
+    }
+}
+
+/// Number of most efficient single candidates that are combined into unions of
+/// up to `k` candidates by the `k`-way synergy methods when columns may contain
+/// gaps. Other single candidates are only scored on their own.
+const TOLERANT_SYNERGY_CANDIDATES: usize = 24;
+
+/// Runs the heuristic when columns may contain some gaps (see
+/// [`GapTolerance`]). Since a column then counts without excluding all of its
+/// gapped sequences, each column that doesn't count yields a single candidate:
+/// the fewest sequences that must be excluded for it to count, preferring the
+/// sequences with the most gaps overall. With synergy, the unions of up to
+/// `config.method.order()` of the best candidates are evaluated as well,
+/// except those too heavy to enlarge the area. Candidates that would exceed
+/// `config.exclusion_budget` are skipped. Sequences in `keep` are
+/// never excluded, and excluded sequences are added to `excluded`.
+#[allow(clippy::cast_precision_loss, clippy::float_cmp)]
+pub fn run_tolerant_heuristic(
+    patterns: &ColumnPatterns,
+    metrics: &mut AlignmentMetrics,
+    config: &HeuristicConfig,
+    keep: &HashSet<usize>,
+    weights: &Weights,
+    tolerance: GapTolerance,
+    excluded: &mut HashSet<usize>,
+) -> Vec<(Vec<usize>, usize)> {
+    let num_seqs = weights.len();
+    let total_weight = weights.total();
+    let total_columns = patterns.gap_free_weight + patterns.weights.iter().sum::<usize>();
+    let preference = gappiest_first(patterns, weights);
+    let mut excluded_bits = Bitset::from_indices(num_seqs, excluded.iter().copied());
+
+    let mut iteration_data = Vec::new();
+    let mut iterations_count: u32 = 0;
+
+    loop {
+        if iterations_count >= config.max_iterations {
+            break;
+        }
+
+        let retained = num_seqs - excluded.len();
+        let budget = config.exclusion_budget.remaining(&excluded_bits);
+        let exceeds_budget = |candidate: &Bitset| !budget.admits(candidate);
+        let evaluate = |candidate: Bitset| {
+            let union = excluded_bits.union(&candidate);
+            let remaining = retained - candidate.count_ones();
+            let (columns, column_weight) = patterns.counted_columns(&union, remaining, tolerance);
+            let area = (total_weight - weights.of_set(&union)) * column_weight;
+            let efficiency = efficiency(area, metrics.weighted_area, weights.of_set(&candidate));
+            TolerantCandidate {
+                set: candidate,
+                area,
+                columns,
+                efficiency,
+            }
+        };
+
+        let mut seen = HashSet::new();
+        let singles: Vec<Bitset> = patterns
+            .sets
+            .iter()
+            .filter_map(|set| {
+                column_candidate(set, &excluded_bits, retained, keep, &preference, tolerance)
+            })
+            .filter(|candidate| !exceeds_budget(candidate) && seen.insert(candidate.clone()))
+            .collect();
+        let mut candidates: Vec<TolerantCandidate> =
+            singles.into_par_iter().map(evaluate).collect();
+
+        if config.method.order() >= 2 {
+            let mut top: Vec<usize> = (0..candidates.len()).collect();
+            top.sort_by(|&a, &b| {
+                candidates[b]
+                    .efficiency
+                    .total_cmp(&candidates[a].efficiency)
+            });
+            top.truncate(TOLERANT_SYNERGY_CANDIDATES);
+            let top: Vec<Bitset> = top.iter().map(|&i| candidates[i].set.clone()).collect();
+            let prunes = |union: &Bitset| {
+                exceeds_budget(union)
+                    || metrics.weighted_area
+                        >= (total_weight - weights.of_union(&excluded_bits, union)) * total_columns
+            };
+            let mut combinations = Vec::new();
+            for i in 0..top.len() {
+                for j in 0..i {
+                    collect_combinations(
+                        &top,
+                        top[i].union(&top[j]),
+                        j,
+                        config.method.order() - 2,
+                        &prunes,
+                        &mut seen,
+                        &mut combinations,
+                    );
+                }
+            }
+            candidates.par_extend(combinations.into_par_iter().map(evaluate));
+        }
+
+        let Some(best) = candidates.into_iter().reduce(|best, candidate| {
+            if candidate.efficiency > best.efficiency
+                || (candidate.efficiency == best.efficiency && candidate.area > best.area)
+            {
+                candidate
+            } else {
+                best
+            }
+        }) else {
+            break;
+        };
+
+        if config.improvement_threshold != 0.0 && metrics.weighted_area != 0 {
+            let improvement =
+                (best.area as f64 - metrics.weighted_area as f64) / metrics.weighted_area as f64;
+            if improvement < config.improvement_threshold {
+                info!(
+                    "Early stopping: relative improvement ({:.4}) is below threshold ({:.4})",
+                    improvement, config.improvement_threshold
+                );
+                break;
+            }
+        }
+
+        let excluded_fraction = excluded.len() as f64 / num_seqs as f64;
+        if excluded_fraction >= config.excluded_seqs_threshold {
+            info!(
+                "Early stopping: excluded sequence fraction ({:.4}) reached threshold ({:.4})",
+                excluded_fraction, config.excluded_seqs_threshold
+            );
+            break;
+        }
+
+        if metrics.weighted_area >= best.area {
+            break;
+        }
+
+        let exseq: Vec<usize> = best.set.ones().collect();
+        excluded.extend(&exseq);
+        excluded_bits.union_with(&best.set);
+
+        metrics.sequence_count = num_seqs - excluded.len();
+        metrics.gap_free_columns = best.columns;
+        metrics.alignment_area = metrics.sequence_count * metrics.gap_free_columns;
+        metrics.weighted_area = best.area;
+        iteration_data.push((exseq, metrics.alignment_area));
+        iterations_count += 1;
+    }
+
+    iteration_data
+}
+
+/// Collects `union` and its unions with up to `depth` further candidates of
+/// `top` that come before `below`, each extension before the union it extends.
+/// Unions that are already known are skipped, and those that `prunes` rejects
+/// are skipped along with their extensions, since it must reject every
+/// superset of a rejected union.
+fn collect_combinations(
+    top: &[Bitset],
+    union: Bitset,
+    below: usize,
+    depth: usize,
+    prunes: &impl Fn(&Bitset) -> bool,
+    seen: &mut HashSet<Bitset>,
+    combinations: &mut Vec<Bitset>,
+) {
+    if prunes(&union) {
+        return;
+    }
+    if depth > 0 {
+        for (next, other) in top[..below].iter().enumerate() {
+            collect_combinations(
+                top,
+                union.union(other),
+                next,
+                depth - 1,
+                prunes,
+                seen,
+                combinations,
+            );
+        }
+    }
+    if seen.insert(union.clone()) {
+        combinations.push(union);
+    }
+}
+
+/// A candidate set of sequences to exclude when columns may contain gaps.
+struct TolerantCandidate {
+    set: Bitset,
+    /// Weighted area after excluding the set.
+    area: usize,
+    /// Number of columns that count after excluding the set.
+    columns: usize,
+    efficiency: f64,
+}
+
+/// Returns the rank of each sequence when choosing which gapped sequences to
+/// exclude from a column: sequences with the largest weight of gapped columns
+/// come first, then the lighter ones.
+pub(crate) fn gappiest_first(patterns: &ColumnPatterns, weights: &Weights) -> Vec<usize> {
+    let num_seqs = weights.len();
+    let mut gap_weight = vec![(0usize, 0usize); num_seqs];
+    for (i, set) in patterns.sets.iter().enumerate() {
+        for idx in set.ones() {
+            gap_weight[idx].0 += patterns.weights[i];
+            gap_weight[idx].1 += patterns.counts[i];
+        }
+    }
+
+    let mut order: Vec<usize> = (0..num_seqs).collect();
+    order.sort_by_key(|&idx| (std::cmp::Reverse(gap_weight[idx]), weights.get(idx), idx));
+    let mut rank = vec![0; num_seqs];
+    for (position, &idx) in order.iter().enumerate() {
+        rank[idx] = position;
+    }
+    rank
+}
+
+/// Returns the fewest sequences that must be excluded, on top of `excluded`,
+/// for a column with the gap set `set` to count towards the area, or `None` if
+/// it already counts or can't be made to count without excluding kept
+/// sequences.
+fn column_candidate(
+    set: &Bitset,
+    excluded: &Bitset,
+    retained: usize,
+    keep: &HashSet<usize>,
+    rank: &[usize],
+    tolerance: GapTolerance,
+) -> Option<Bitset> {
+    let gapped: Vec<usize> = set.ones().filter(|&idx| !excluded.contains(idx)).collect();
+    let gaps = gapped.len();
+    if gaps >= retained || tolerance.allows(gaps, retained) {
+        return None;
+    }
+
+    let mut removable: Vec<usize> = gapped
+        .into_iter()
+        .filter(|idx| !keep.contains(idx))
+        .collect();
+    removable.sort_by_key(|&idx| rank[idx]);
+    let count = (1..=removable.len()).find(|&n| tolerance.allows(gaps - n, retained - n))?;
+
+    Some(Bitset::from_indices(
+        excluded.width(),
+        removable[..count].iter().copied(),
+    ))
 }
 
 /// Creates working sets by filtering out excluded sequences.
 pub fn create_working_sets(
-    orig_sets: &[Vec<u8>],
-    orig_gaps: &[Vec<u8>],
+    orig_sets: &[Bitset],
+    orig_gaps: &[Bitset],
     excluded: &HashSet<usize>,
     translation: &[usize],
     num_orig_seqs: usize,
-) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+) -> (Vec<Bitset>, Vec<Bitset>) {
     let mut working_sets = Vec::new();
     let mut working_gaps = Vec::new();
 
-    let included: Vec<bool> = (0..num_orig_seqs)
-        .map(|idx| !excluded.contains(&idx))
-        .collect();
+    let mut working_index = vec![None; num_orig_seqs];
+    for (pointer, &orig_idx) in translation.iter().enumerate() {
+        if !excluded.contains(&orig_idx) {
+            working_index[orig_idx] = Some(pointer);
+        }
+    }
 
     for (i, orig_set) in orig_sets.iter().enumerate() {
-        let mut bools = Vec::with_capacity(translation.len());
-        let mut has_any_gap = false;
+        let working_set = Bitset::from_indices(
+            translation.len(),
+            orig_set.ones().filter_map(|idx| working_index[idx]),
+        );
+        if working_set.any() {
+            working_sets.push(working_set);
+            working_gaps.push(orig_gaps[i].clone());
+        }
+    }
+
+    (working_sets, working_gaps)
+}
 
-        for (idx, &is_included) in included.iter().enumerate() {
-            if is_included {
-                let byte_idx = idx / 8;
-                let bit_idx = idx % 8;
-                let is_gap = byte_idx < orig_set.len() && (orig_set[byte_idx] >> bit_idx) & 1 == 1;
-                bools.push(is_gap);
-                has_any_gap |= is_gap;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{congruent_set_joining, create_sets, subset_joining};
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg64;
+
+    /// Random gap matrix in which each sequence has its own gap density, and
+    /// some sequences repeat the gaps of an earlier one.
+    fn random_gap_matrix(rng: &mut Pcg64, num_seqs: usize, length: usize) -> Vec<Vec<bool>> {
+        let mut matrix: Vec<Vec<bool>> = Vec::with_capacity(num_seqs);
+        for _ in 0..num_seqs {
+            if !matrix.is_empty() && rng.random_bool(0.2) {
+                let copied = rng.random_range(0..matrix.len());
+                matrix.push(matrix[copied].clone());
+            } else {
+                let density = rng.random_range(0.0..0.3);
+                matrix.push((0..length).map(|_| rng.random_bool(density)).collect());
             }
         }
+        matrix
+    }
 
-        if has_any_gap {
-            working_sets.push(pack_bools_to_bits(&bools));
-            working_gaps.push(orig_gaps[i].clone());
+    /// The candidate selection of the heuristic before candidates were pruned
+    /// and scored in parallel: every union of up to `order` working sets is
+    /// scored, and ties go to the candidate that frees more columns, then to
+    /// the later one.
+    #[allow(clippy::cast_precision_loss, clippy::float_cmp)]
+    fn baseline_greatest_impact_set(
+        sets: &[Bitset],
+        gaps: &[Bitset],
+        current_area: usize,
+        sequence_count: usize,
+        gap_free_columns: usize,
+        order: usize,
+    ) -> (Bitset, usize) {
+        let mut best = (Bitset::new(0), 0);
+        let mut best_efficiency = -1.0;
+        let mut best_gap_count = 0;
+        let mut evaluate = |members: &[usize]| {
+            let mut set = Bitset::new(sets[0].width());
+            let mut gap = Bitset::new(gaps[0].width());
+            for &idx in members {
+                set.union_with(&sets[idx]);
+                gap.union_with(&gaps[idx]);
+            }
+            let (set_size, gap_count) = (set.count_ones(), gap.count_ones());
+            let impact = (sequence_count - set_size) * (gap_free_columns + gap_count);
+            let efficiency = (impact as f64 - current_area as f64) / set_size as f64;
+            if efficiency > best_efficiency
+                || (efficiency == best_efficiency && gap_count >= best_gap_count)
+            {
+                best = (set, impact);
+                best_efficiency = efficiency;
+                best_gap_count = gap_count;
+            }
+        };
+        for i in 0..sets.len() {
+            evaluate(&[i]);
+            for j in (0..i).filter(|_| order >= 2) {
+                evaluate(&[i, j]);
+                for k in (0..j).filter(|_| order >= 3) {
+                    evaluate(&[i, j, k]);
+                }
+            }
         }
+        best
     }
 
-    (working_sets, working_gaps)
+    /// The greedy heuristic as it ran before the working sets were maintained
+    /// incrementally: the working sets are rebuilt and joined from scratch at
+    /// each iteration, and candidates are selected by
+    /// [`baseline_greatest_impact_set`].
+    fn baseline_heuristic(
+        gap_matrix: &[Vec<bool>],
+        length: usize,
+        order: usize,
+    ) -> Vec<(Vec<usize>, usize)> {
+        let num_seqs = gap_matrix.len();
+        let (sets, gaps, _) = create_sets(gap_matrix, &HashSet::new(), length);
+        let mut state = SetData::new(sets, gaps, num_seqs);
+        let mut area = num_seqs * (length - state.sets.len());
+        let mut iteration_data = Vec::new();
+        loop {
+            let (mut sets, mut gaps) = create_working_sets(
+                &state.sets,
+                &state.gaps,
+                &state.excluded,
+                &state.translation,
+                num_seqs,
+            );
+            let sequence_count = state.translation.len();
+            let gap_free_columns = length - sets.len();
+            congruent_set_joining(
+                &mut sets,
+                &mut gaps,
+                area,
+                &Weights::uniform(sequence_count),
+                &Weights::uniform(length),
+            );
+            subset_joining(&sets, &mut gaps);
+            if sets.is_empty() {
+                break;
+            }
+            let (set, new_area) = baseline_greatest_impact_set(
+                &sets,
+                &gaps,
+                area,
+                sequence_count,
+                gap_free_columns,
+                order,
+            );
+            if area >= new_area {
+                break;
+            }
+            let exseq: Vec<usize> = set
+                .ones()
+                .map(|pointer| state.translation[pointer])
+                .collect();
+            state.excluded.extend(&exseq);
+            state.translation = (0..num_seqs)
+                .filter(|idx| !state.excluded.contains(idx))
+                .collect();
+            area = new_area;
+            iteration_data.push((exseq, new_area));
+        }
+        iteration_data
+    }
+
+    fn greedy_heuristic(
+        gap_matrix: &[Vec<bool>],
+        length: usize,
+        method: HeuristicMethod,
+    ) -> Vec<(Vec<usize>, usize)> {
+        let num_seqs = gap_matrix.len();
+        let (sets, gaps, keep_pattern) = create_sets(gap_matrix, &HashSet::new(), length);
+        let gap_free_columns = length - sets.len();
+        let mut state = SetData::new(sets, gaps, num_seqs);
+        let mut metrics = AlignmentMetrics::new(
+            num_seqs,
+            gap_free_columns,
+            gap_free_columns * num_seqs,
+            length,
+        )
+        .with_weighted_area(gap_free_columns * num_seqs);
+        let config = HeuristicConfig {
+            method,
+            max_iterations: u32::MAX,
+            improvement_threshold: 0.0,
+            excluded_seqs_threshold: 1.0,
+            beam_width: 1,
+            exclusion_budget: ExclusionBudget::default(),
+        };
+        run_heuristic(
+            &mut state,
+            &mut metrics,
+            &config,
+            &keep_pattern,
+            &Weights::uniform(num_seqs),
+            &Weights::uniform(length),
+            num_seqs,
+        )
+    }
+
+    #[test]
+    fn greedy_heuristic_matches_the_baseline() {
+        let pools: Vec<rayon::ThreadPool> = [1, 3]
+            .into_iter()
+            .map(|threads| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let mut rng = Pcg64::seed_from_u64(10);
+        for _ in 0..30 {
+            let num_seqs = rng.random_range(2..80);
+            let length = rng.random_range(1..50);
+            let gap_matrix = random_gap_matrix(&mut rng, num_seqs, length);
+            for method in [
+                HeuristicMethod::NoSynergy,
+                HeuristicMethod::PairwiseSynergy,
+                HeuristicMethod::TripleSynergy,
+            ] {
+                let expected = baseline_heuristic(&gap_matrix, length, method.order());
+                for pool in &pools {
+                    let actual = pool.install(|| greedy_heuristic(&gap_matrix, length, method));
+                    assert_eq!(actual, expected, "method {method}, {num_seqs} x {length}");
+                }
+            }
+        }
+    }
+
+    /// Random weights, some of them zero, or uniform weights.
+    fn random_weights(rng: &mut Pcg64, count: usize) -> Weights {
+        if rng.random_bool(0.5) {
+            Weights::uniform(count)
+        } else {
+            let weights: Vec<f64> = (0..count)
+                .map(|_| {
+                    if rng.random_bool(0.1) {
+                        0.0
+                    } else {
+                        rng.random_range(0.0..2.0)
+                    }
+                })
+                .collect();
+            Weights::new(&weights)
+        }
+    }
+
+    /// The working sets rebuilt and joined from scratch, with the sequences
+    /// mapped back to their original indices.
+    fn rebuilt_working_sets(
+        state: &SetData,
+        alignment_area: usize,
+        weights: &Weights,
+        column_weights: &Weights,
+    ) -> (Vec<Bitset>, Vec<Bitset>) {
+        let num_seqs = weights.len();
+        let (mut sets, mut gaps) = create_working_sets(
+            &state.sets,
+            &state.gaps,
+            &state.excluded,
+            &state.translation,
+            num_seqs,
+        );
+        congruent_set_joining(
+            &mut sets,
+            &mut gaps,
+            alignment_area,
+            &weights.select(&state.translation),
+            column_weights,
+        );
+        subset_joining(&sets, &mut gaps);
+        let sets = sets
+            .iter()
+            .map(|set| {
+                Bitset::from_indices(
+                    num_seqs,
+                    set.ones().map(|pointer| state.translation[pointer]),
+                )
+            })
+            .collect();
+        (sets, gaps)
+    }
+
+    #[test]
+    fn working_sets_match_a_rebuild_after_each_exclusion() {
+        let mut rng = Pcg64::seed_from_u64(14);
+        for _ in 0..40 {
+            let num_seqs = rng.random_range(1..80);
+            let length = rng.random_range(1..50);
+            let gap_matrix = random_gap_matrix(&mut rng, num_seqs, length);
+            let weights = random_weights(&mut rng, num_seqs);
+            let column_weights = random_weights(&mut rng, length);
+            let (sets, gaps, _) = create_sets(&gap_matrix, &HashSet::new(), length);
+            let mut state = SetData::new(sets, gaps, num_seqs);
+            let mut working = WorkingSets::new(&state, num_seqs, &column_weights);
+
+            while !state.translation.is_empty() {
+                let excluded = Bitset::from_indices(num_seqs, state.excluded.iter().copied());
+                let gapped: Vec<&Bitset> = state
+                    .sets
+                    .iter()
+                    .zip(&state.gaps)
+                    .filter(|(set, _)| !set.is_subset(&excluded))
+                    .map(|(_, gaps)| gaps)
+                    .collect();
+                assert_eq!(working.gapped_count, gapped.len());
+                assert_eq!(
+                    working.gapped_weight,
+                    gapped
+                        .iter()
+                        .map(|gaps| column_weights.of_set(gaps))
+                        .sum::<usize>()
+                );
+
+                let retained_weight = weights.total() - weights.of_set(&excluded);
+                let max_area = retained_weight * column_weights.total();
+                for alignment_area in [0, rng.random_range(0..=max_area), max_area] {
+                    assert_eq!(
+                        working.current(alignment_area, retained_weight, &weights, &column_weights),
+                        rebuilt_working_sets(&state, alignment_area, &weights, &column_weights),
+                    );
+                }
+
+                let first = state.translation[rng.random_range(0..state.translation.len())];
+                let newly_excluded = Bitset::from_indices(
+                    num_seqs,
+                    state
+                        .translation
+                        .iter()
+                        .copied()
+                        .filter(|&idx| idx == first || rng.random_bool(0.1)),
+                );
+                working.exclude(&newly_excluded, &state.gaps, &column_weights);
+                state.excluded.extend(newly_excluded.ones());
+                state
+                    .translation
+                    .retain(|idx| !state.excluded.contains(idx));
+            }
+        }
+    }
 }
@@ -0,0 +1,52 @@
+//! A Rust reimplementation of the `MaxAlign` algorithm for optimizing multiple
+//! sequence alignments by maximizing the alignment area.
+//!
+//! The [`MaxAlign`] builder is the simplest way to run the full pipeline. The
+//! lower-level building blocks ([`run_heuristic`], [`run_branch_and_bound`] and
+//! the functions in [`alignment`]) are also exposed for callers that need more
+//! control.
+
+pub mod a3m;
+pub mod alignment;
+pub mod bitops;
+pub mod clustal;
+pub mod compression;
+pub mod engine;
+pub mod error;
+pub mod fasta;
+pub mod format;
+pub mod groups;
+pub mod heuristic;
+pub mod model;
+pub mod nexus;
+pub mod optimize;
+pub mod output;
+pub mod pareto;
+pub mod phylip;
+pub mod polish;
+pub mod report;
+pub mod selection;
+pub mod stats;
+pub mod stockholm;
+pub mod weights;
+
+pub use crate::alignment::{
+    AlignmentMetrics, ColumnPatterns, ExclusionBudget, ExclusionLimit, GapChars, GapTolerance,
+    GroupLimit, SetData,
+};
+pub use crate::bitops::Bitset;
+pub use crate::engine::{MaxAlign, MaxAlignBuilder, MaxAlignResult};
+pub use crate::error::{Error, Result};
+pub use crate::groups::{GroupQuota, SequenceGroups};
+pub use crate::heuristic::{
+    HeuristicConfig, HeuristicMethod, run_heuristic, run_tolerant_heuristic,
+};
+pub use crate::model::{ModelFormat, ModelSolution, ReducedModel};
+pub use crate::optimize::{
+    BranchAndBoundResult, ReducedProblem, Solution, SolutionPool, run_branch_and_bound,
+    run_tolerant_branch_and_bound,
+};
+pub use crate::pareto::{ParetoPoint, ParetoSource};
+pub use crate::polish::{PolishConfig, PolishMove};
+pub use crate::stats::{AlignmentStats, StatsFormat};
+pub use crate::weights::{SequenceWeighting, Weights};
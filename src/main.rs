@@ -1,31 +1,40 @@
-mod alignment;
-mod bitops;
-mod error;
-mod fasta;
-mod heuristic;
-mod optimize;
-mod output;
-mod report;
-
-use crate::alignment::{
-    AlignmentMetrics, SetData, create_gap_matrix, create_sets, remove_all_gap_columns,
-};
-use crate::error::{Error, Result};
-use crate::fasta::parse_fasta;
-use crate::heuristic::{HeuristicConfig, HeuristicMethod, run_heuristic};
-use crate::optimize::run_branch_and_bound;
-use crate::output::{write_fasta, write_headers_list};
-use crate::report::{ReportConfig, ReportData, write_report};
 use clap::{
-    CommandFactory, Parser,
+    Args, CommandFactory, Parser, Subcommand,
     builder::styling::{AnsiColor, Style, Styles},
 };
 use clio::{Input, Output};
 use env_logger::Builder;
 use itertools::Itertools;
-use log::{LevelFilter, debug, info};
-use std::io::{IsTerminal, Write};
+use log::{LevelFilter, debug, info, warn};
+use maxalign_rs::a3m::{find_match_columns, select_columns};
+use maxalign_rs::alignment::{GapChars, find_retained_columns, remove_all_gap_columns};
+use maxalign_rs::compression::{CompressedWriter, Compression};
+use maxalign_rs::error::{Error, Result};
+use maxalign_rs::fasta::{SequenceData, get_record_accession_string};
+use maxalign_rs::format::{AlignmentFormat, read_alignment};
+use maxalign_rs::groups::{GroupQuota, read_group_quotas, read_groups};
+use maxalign_rs::heuristic::HeuristicMethod;
+use maxalign_rs::model::{ModelFormat, ModelSolution};
+use maxalign_rs::output::{
+    write_alignment, write_headers_list, write_pareto_front, write_solutions,
+};
+use maxalign_rs::report::{
+    ApplyReportConfig, ReportConfig, ReportData, write_apply_report, write_report,
+};
+use maxalign_rs::selection::{SequenceSelection, read_names};
+use maxalign_rs::stats::{AlignmentStats, StatsFormat, write_stats};
+use maxalign_rs::stockholm::StockholmAnnotations;
+use maxalign_rs::weights::{
+    SequenceWeighting, henikoff_weights, identity_weights, parse_mask_string, read_column_mask,
+    read_column_weights, read_weights,
+};
+use maxalign_rs::{MaxAlign, MaxAlignBuilder, MaxAlignResult};
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::path::Path;
 use std::process::ExitCode;
+use std::time::Duration;
 
 const STYLES: Styles = Styles::styled()
     .header(AnsiColor::Cyan.on_default().bold())
@@ -50,29 +59,123 @@ fn parse_threshold(s: &str) -> std::result::Result<f64, String> {
     }
 }
 
+fn parse_time_limit(s: &str) -> std::result::Result<f64, String> {
+    let v: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+    if v.is_finite() && v > 0.0 {
+        Ok(v)
+    } else {
+        Err("value must be a positive number of seconds".to_string())
+    }
+}
+
+fn parse_positive(s: &str) -> std::result::Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("value must be at least 1".to_string()),
+        Ok(v) => Ok(v),
+        Err(_) => Err(format!("`{s}` is not a positive integer")),
+    }
+}
+
+fn parse_regex(s: &str) -> std::result::Result<Regex, String> {
+    Regex::new(s).map_err(|e| format!("invalid regular expression: {e}"))
+}
+
+fn parse_fraction(s: &str) -> std::result::Result<f64, String> {
+    let v: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+    if (0.0..=1.0).contains(&v) {
+        Ok(v)
+    } else {
+        Err("value must be between 0 and 1".to_string())
+    }
+}
+
 #[derive(Parser)]
-#[command(version, about, styles = STYLES, max_term_width = 88)]
+#[command(
+    version,
+    about,
+    styles = STYLES,
+    max_term_width = 88,
+    args_conflicts_with_subcommands = true
+)]
 struct Cli {
-    /// Input FASTA file
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input alignment file
     #[arg(default_value = "-")]
     input: Input,
 
-    /// Output FASTA file
+    /// Output alignment file
     #[arg(default_value = "-")]
     output: Output,
 
-    /// Heuristic method: 1 (no synergy), 2 (pairwise synergy), 3 (three-way synergy)
+    /// Input format: fasta, stockholm, clustal, phylip, phylip-relaxed, nexus, a2m, or a3m
+    /// (detected from the input if not specified)
+    #[arg(long, value_parser = clap::value_parser!(AlignmentFormat))]
+    input_format: Option<AlignmentFormat>,
+
+    /// Output format: fasta, stockholm, clustal, phylip, phylip-relaxed, nexus, a2m, or a3m
+    /// (inferred from the output file extension or the input format if not specified)
+    #[arg(long, value_parser = clap::value_parser!(AlignmentFormat))]
+    output_format: Option<AlignmentFormat>,
+
+    /// Compress the output alignment: none, gzip, bzip2, xz, or zstd
+    /// (inferred from the output file extension if not specified)
+    #[arg(long, value_parser = clap::value_parser!(Compression))]
+    output_compression: Option<Compression>,
+
+    /// Remove insert-state columns (lowercase letters and '.') from A2M/A3M alignments
+    #[arg(long, default_value = "false")]
+    drop_insertions: bool,
+
+    #[command(flatten)]
+    gaps: GapArgs,
+
+    /// Heuristic method: 1 (no synergy), 2 (pairwise synergy), 3 (three-way synergy),
+    /// k (k-way synergy). With gap tolerance, only the 24 most efficient single
+    /// removals are combined
     #[arg(short = 'm', long, default_value = "2", value_parser = clap::value_parser!(HeuristicMethod))]
     heuristic_method: HeuristicMethod,
 
+    /// Keep this many partial solutions per heuristic iteration (beam search) instead of one
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = parse_positive,
+        conflicts_with_all = ["max_column_gaps", "max_column_gap_fraction"]
+    )]
+    beam_width: usize,
+
     /// Maximum number of iterations (-1 for unlimited iterations)
     #[arg(short = 'i', long, default_value = "-1", value_parser = parse_max_iterations)]
     max_iterations: u32,
 
+    /// Polish the heuristic solution with re-insertion, exclusion and swap moves that enlarge
+    /// the alignment area
+    #[arg(long, default_value = "false")]
+    polish: bool,
+
+    /// Run this many simulated annealing steps during the polishing, to escape local optima
+    #[arg(long, requires = "polish")]
+    anneal_steps: Option<u64>,
+
+    /// Seed of the random number generator of the simulated annealing
+    #[arg(long, requires = "anneal_steps", default_value = "0")]
+    seed: u64,
+
     /// Perform refinement using the branch-and-bound algorithm to find the optimal solution
     #[arg(short = 'o', long, default_value = "false")]
     refinement: bool,
 
+    /// Stop the refinement after this many seconds, keeping the best solution found so far
+    #[arg(long, requires = "refinement", value_parser = parse_time_limit)]
+    refinement_time_limit: Option<f64>,
+
+    /// Stop the refinement after exploring this many search nodes, keeping the best solution
+    /// found so far
+    #[arg(long, requires = "refinement")]
+    refinement_node_limit: Option<u64>,
+
     /// Stop iterating if the relative improvement is below this threshold
     #[arg(short = 't', long, default_value = "0.0", value_parser = parse_threshold)]
     improvement_threshold: f64,
@@ -81,10 +184,95 @@ struct Cli {
     #[arg(short = 's', long, default_value = "1.0", value_parser = parse_threshold)]
     excluded_seqs_threshold: f64,
 
+    /// Retain at least this many sequences, in the heuristic and in the refinement
+    #[arg(long)]
+    min_retained: Option<usize>,
+
+    /// Exclude at most this many sequences, in the heuristic and in the refinement
+    #[arg(long)]
+    max_excluded: Option<usize>,
+
+    /// Exclude at most this fraction of the sequences, in the heuristic and in the refinement
+    #[arg(long, value_parser = parse_fraction)]
+    max_excluded_fraction: Option<f64>,
+
+    /// Tab-separated file with the accession and group of each sequence
+    #[arg(long)]
+    groups: Option<String>,
+
+    /// Retain at least this many sequences (e.g., 2) or this fraction of the sequences
+    /// (e.g., 0.5) of every group
+    #[arg(long, requires = "groups", value_parser = clap::value_parser!(GroupQuota))]
+    group_quota: Option<GroupQuota>,
+
+    /// Tab-separated file with the name and quota of each group, overriding --group-quota
+    #[arg(long, requires = "groups")]
+    group_quota_file: Option<String>,
+
+    /// Number of threads to use (0 for all available cores)
+    #[arg(short = 'j', long, default_value = "1")]
+    threads: usize,
+
+    /// Tab-separated file with the accession and weight of each sequence
+    #[arg(long, conflicts_with = "weighting")]
+    weights: Option<String>,
+
+    /// Compute sequence weights: henikoff (position-based) or identity (inverse of the
+    /// number of sequences above the identity threshold)
+    #[arg(long, value_parser = clap::value_parser!(SequenceWeighting))]
+    weighting: Option<SequenceWeighting>,
+
+    /// Identity threshold for identity-based sequence weights
+    #[arg(long, default_value = "0.8", value_parser = parse_fraction)]
+    weighting_identity: f64,
+
+    /// File with one weight per alignment column, separated by whitespace
+    #[arg(long, conflicts_with_all = ["column_mask", "column_mask_feature"])]
+    column_weights: Option<String>,
+
+    /// Column mask file: BED-like ranges (0-based, end-exclusive) or a #=GC-style string.
+    /// Only columns in the mask count towards the alignment area
+    #[arg(long, conflicts_with = "column_mask_feature")]
+    column_mask: Option<String>,
+
+    /// Use the #=GC line with this feature (e.g., RF) of a Stockholm input as the column mask
+    #[arg(long)]
+    column_mask_feature: Option<String>,
+
+    #[command(flatten)]
+    tolerance: ToleranceArgs,
+
     /// Sequence to always retain (can be specified multiple times)
     #[arg(short = 'k', long)]
     keep_sequence: Vec<String>,
 
+    /// File with the sequences to always retain, one per line
+    #[arg(long)]
+    keep_file: Option<String>,
+
+    /// Always retain the sequences that match this regular expression (can be specified
+    /// multiple times)
+    #[arg(long, value_parser = parse_regex)]
+    keep_regex: Vec<Regex>,
+
+    /// Sequence to exclude before the optimization (can be specified multiple times)
+    #[arg(long)]
+    exclude_sequence: Vec<String>,
+
+    /// File with the sequences to exclude before the optimization, one per line
+    #[arg(long)]
+    exclude_file: Option<String>,
+
+    /// Exclude the sequences that match this regular expression before the optimization (can
+    /// be specified multiple times)
+    #[arg(long, value_parser = parse_regex)]
+    exclude_regex: Vec<Regex>,
+
+    /// Match the sequences and regular expressions to keep or exclude against the whole
+    /// description line instead of the accession
+    #[arg(long, default_value = "false")]
+    match_description: bool,
+
     /// Report file path
     #[arg(short = 'r', long)]
     report: Option<String>,
@@ -97,11 +285,220 @@ struct Cli {
     #[arg(long)]
     excluded_sequences: Option<String>,
 
+    /// Write the Pareto front of alignment area versus number of retained sequences to a
+    /// tab-separated file
+    #[arg(long)]
+    pareto_front: Option<String>,
+
+    /// Output the Pareto front solution with the largest area among those that retain at
+    /// least this many sequences, instead of the maximum-area solution
+    #[arg(long)]
+    pareto_select: Option<usize>,
+
+    /// Write the solutions of the refinement that reach the largest area to a tab-separated
+    /// file, or to a JSON file if its extension is .json
+    #[arg(long, requires = "refinement")]
+    solutions: Option<String>,
+
+    /// List this many best solutions of the refinement instead of only those that reach the
+    /// largest area
+    #[arg(long, requires = "refinement", value_parser = parse_positive)]
+    top_solutions: Option<usize>,
+
+    /// Output the listed solution of the refinement with this index (starting at 1) instead of
+    /// the first one
+    #[arg(long, requires = "refinement", conflicts_with = "pareto_select", value_parser = parse_positive)]
+    solution_index: Option<usize>,
+
+    /// Write the problem reduced for the area of the output solution as a model for an
+    /// external exact solver: an integer linear program (.lp or .mps) or a weighted MaxSAT
+    /// instance (.wcnf)
+    #[arg(long, conflicts_with_all = ["max_column_gaps", "max_column_gap_fraction"])]
+    export_model: Option<String>,
+
+    /// Model format: lp, mps, or wcnf (inferred from the model file extension if not specified)
+    #[arg(long, requires = "export_model", value_parser = clap::value_parser!(ModelFormat))]
+    model_format: Option<ModelFormat>,
+
+    /// Output the solution of the model in this solver solution file, which must come from a
+    /// model exported from the same input with the same options
+    #[arg(
+        long,
+        conflicts_with_all = ["refinement", "solve_model", "pareto_select", "max_column_gaps", "max_column_gap_fraction"]
+    )]
+    import_solution: Option<String>,
+
+    /// Solve the model with the bundled exact solver (for small problems), write its solution
+    /// to this file and output it
+    #[arg(
+        long,
+        conflicts_with_all = ["refinement", "pareto_select", "max_column_gaps", "max_column_gap_fraction"]
+    )]
+    solve_model: Option<String>,
+
     /// Verbosity level (-v for normal logging, -vv for detailed logging)
-    #[arg(short = 'v', long, action = clap::ArgAction::Count)]
+    #[arg(short = 'v', long, action = clap::ArgAction::Count, global = true)]
     verbosity: u8,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Apply a list of retained or excluded sequences, such as written by a previous run, to an
+    /// alignment without any optimization
+    Apply(ApplyArgs),
+    /// Print statistics on the gap structure of an alignment without any optimization
+    Stats(StatsArgs),
+}
+
+#[derive(Args)]
+struct ApplyArgs {
+    /// Input alignment file
+    #[arg(default_value = "-")]
+    input: Input,
+
+    /// Output alignment file
+    #[arg(default_value = "-")]
+    output: Output,
+
+    /// Input format: fasta, stockholm, clustal, phylip, phylip-relaxed, nexus, a2m, or a3m
+    /// (detected from the input if not specified)
+    #[arg(long, value_parser = clap::value_parser!(AlignmentFormat))]
+    input_format: Option<AlignmentFormat>,
+
+    /// Output format: fasta, stockholm, clustal, phylip, phylip-relaxed, nexus, a2m, or a3m
+    /// (inferred from the output file extension or the input format if not specified)
+    #[arg(long, value_parser = clap::value_parser!(AlignmentFormat))]
+    output_format: Option<AlignmentFormat>,
+
+    /// Compress the output alignment: none, gzip, bzip2, xz, or zstd
+    /// (inferred from the output file extension if not specified)
+    #[arg(long, value_parser = clap::value_parser!(Compression))]
+    output_compression: Option<Compression>,
+
+    /// Remove insert-state columns (lowercase letters and '.') from A2M/A3M alignments
+    #[arg(long, default_value = "false")]
+    drop_insertions: bool,
+
+    #[command(flatten)]
+    gaps: GapArgs,
+
+    #[command(flatten)]
+    tolerance: ToleranceArgs,
+
+    #[command(flatten)]
+    list: ListArgs,
+
+    /// Report file path
+    #[arg(short = 'r', long)]
+    report: Option<String>,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    /// Input alignment file
+    #[arg(default_value = "-")]
+    input: Input,
+
+    /// Output statistics file
+    #[arg(default_value = "-")]
+    output: Output,
+
+    /// Input format: fasta, stockholm, clustal, phylip, phylip-relaxed, nexus, a2m, or a3m
+    /// (detected from the input if not specified)
+    #[arg(long, value_parser = clap::value_parser!(AlignmentFormat))]
+    input_format: Option<AlignmentFormat>,
+
+    #[command(flatten)]
+    gaps: GapArgs,
+
+    #[command(flatten)]
+    tolerance: ToleranceArgs,
+
+    /// Statistics format: table, tsv, or json (inferred from the output file extension if not
+    /// specified, and table otherwise)
+    #[arg(long, value_parser = clap::value_parser!(StatsFormat))]
+    format: Option<StatsFormat>,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct ListArgs {
+    /// File with the sequences to retain, one per line, such as written by
+    /// --retained-sequences
+    #[arg(long)]
+    retained_list: Option<String>,
+
+    /// File with the sequences to exclude, one per line, such as written by
+    /// --excluded-sequences
+    #[arg(long)]
+    excluded_list: Option<String>,
+}
+
+#[derive(Args)]
+struct GapArgs {
+    /// Characters treated as gaps or missing data (e.g., '-.?~')
+    #[arg(long, default_value = "-.", allow_hyphen_values = true, value_parser = clap::value_parser!(GapChars))]
+    gap_chars: GapChars,
+
+    /// Also treat 'N' and 'n' (unknown nucleotides) as gaps
+    #[arg(long, default_value = "false")]
+    treat_n_as_gap: bool,
+
+    /// Also treat 'X' and 'x' (unknown amino acids) as gaps
+    #[arg(long, default_value = "false")]
+    treat_x_as_gap: bool,
+
+    /// Also treat '?' and '~' (missing data) as gaps
+    #[arg(long, default_value = "false")]
+    treat_missing_as_gap: bool,
+}
+
+impl GapArgs {
+    /// Returns the characters treated as gaps in an alignment of the given
+    /// format.
+    fn gap_chars(&self, format: AlignmentFormat) -> GapChars {
+        let mut gap_chars = self.gap_chars.clone();
+        if self.treat_n_as_gap {
+            gap_chars.insert(b"Nn");
+        }
+        if self.treat_x_as_gap {
+            gap_chars.insert(b"Xx");
+        }
+        if self.treat_missing_as_gap {
+            gap_chars.insert(b"?~");
+        }
+        // In A2M/A3M alignments, '.' pads insertions and is always a gap.
+        if format.has_insert_states() {
+            gap_chars.insert(b".");
+        }
+        gap_chars
+    }
+}
+
+#[derive(Args)]
+struct ToleranceArgs {
+    /// Count columns in which at most this fraction of the retained sequences have a gap
+    #[arg(long, value_parser = parse_fraction, conflicts_with = "max_column_gaps")]
+    max_column_gap_fraction: Option<f64>,
+
+    /// Count columns in which at most this many retained sequences have a gap
+    #[arg(long)]
+    max_column_gaps: Option<usize>,
+}
+
+impl ToleranceArgs {
+    /// Sets the gap tolerance of an engine.
+    const fn configure(&self, builder: MaxAlignBuilder) -> MaxAlignBuilder {
+        if let Some(count) = self.max_column_gaps {
+            builder.max_column_gaps(count)
+        } else if let Some(fraction) = self.max_column_gap_fraction {
+            builder.max_column_gap_fraction(fraction)
+        } else {
+            builder
+        }
+    }
+}
+
 fn setup_logging(verbosity: u8) {
     let level = match verbosity {
         0 => LevelFilter::Off,
@@ -118,81 +515,226 @@ fn setup_logging(verbosity: u8) {
 
 #[allow(clippy::too_many_lines)]
 fn run(cli: &Cli) -> Result<()> {
-    if cli.input.is_std() && std::io::stdin().is_terminal() {
-        #[allow(clippy::unwrap_used)]
-        Cli::command().print_help().unwrap();
-        return Ok(());
+    match cli.command {
+        Some(Command::Apply(ref args)) => return run_apply(args),
+        Some(Command::Stats(ref args)) => return run_stats(args),
+        None => {}
     }
 
-    let sequence_data = match parse_fasta(&cli.input, &cli.keep_sequence) {
-        Ok(data) => data,
-        Err(Error::EmptyInput) if cli.input.is_std() => {
-            #[allow(clippy::unwrap_used)]
-            Cli::command().print_help().unwrap();
-            return Ok(());
-        }
-        Err(e) => return Err(e),
+    let Some((input_format, mut sequence_data)) = read_input(&cli.input, cli.input_format, None)?
+    else {
+        return Ok(());
     };
 
-    let num_sequences = sequence_data.headers.len();
+    let selection = |names: &[String], file: Option<&String>, patterns: &[Regex]| {
+        let mut names = names.to_vec();
+        if let Some(path) = file {
+            names.extend(read_names(Path::new(path))?);
+        }
+        Ok::<_, Error>(SequenceSelection {
+            names,
+            patterns: patterns.to_vec(),
+            match_description: cli.match_description,
+        })
+    };
+    sequence_data.keep_indices =
+        selection(&cli.keep_sequence, cli.keep_file.as_ref(), &cli.keep_regex)?
+            .select(&sequence_data.headers, "Must-retain");
 
-    let mut sequences = sequence_data.sequences;
-    for seq in &mut sequences {
-        seq.resize(sequence_data.longest_length, b'-');
+    // Sequences excluded in advance are removed before the optimization, but
+    // the weights and groups files are matched against the whole input.
+    let all_headers = sequence_data.headers.clone();
+    let pre_excluded = selection(
+        &cli.exclude_sequence,
+        cli.exclude_file.as_ref(),
+        &cli.exclude_regex,
+    )?
+    .select(&all_headers, "Must-exclude");
+    if pre_excluded.len() == all_headers.len() {
+        return Err(Error::AllExcluded);
     }
+    let conflicts = pre_excluded
+        .intersection(&sequence_data.keep_indices)
+        .count();
+    if conflicts > 0 {
+        warn!(
+            "{conflicts} sequence(s) were selected both to retain and to exclude, and were excluded"
+        );
+    }
+    if !pre_excluded.is_empty() {
+        info!("Excluding {} sequence(s) in advance", pre_excluded.len());
+        sequence_data.remove_sequences(|idx| pre_excluded.contains(&idx));
+    }
+    let active: Vec<usize> = (0..all_headers.len())
+        .filter(|idx| !pre_excluded.contains(idx))
+        .collect();
 
-    let gap_matrix = create_gap_matrix(&sequences, sequence_data.longest_length);
-    let (orig_sets, orig_gaps, keep_pattern) = create_sets(
-        &gap_matrix,
-        &sequence_data.keep_indices,
-        sequence_data.longest_length,
-    );
-
-    let kept_gaps_count = keep_pattern.iter().filter(|&&b| b).count();
-    let initial_gap_free_columns = sequence_data.longest_length - orig_sets.len() - kept_gaps_count;
-
-    let initial_metrics = AlignmentMetrics::new(
-        num_sequences,
-        initial_gap_free_columns,
-        initial_gap_free_columns * num_sequences,
+    let gap_chars = cli.gaps.gap_chars(input_format);
+    let (sequences, match_sequences) = prepare_sequences(
+        sequence_data.sequences,
         sequence_data.longest_length,
-    );
+        input_format,
+        &gap_chars,
+        cli.drop_insertions,
+    )?;
 
-    info!(
-        "Loaded input alignment (sequences: {}, length: {}, initial area: {})",
-        initial_metrics.sequence_count,
-        initial_metrics.alignment_length,
-        initial_metrics.alignment_area
-    );
+    let objective_sequences = match_sequences.as_ref().unwrap_or(&sequences);
+    let weights = if let Some(ref path) = cli.weights {
+        let weights = read_weights(Path::new(path), &all_headers)?;
+        Some(active.iter().map(|&idx| weights[idx]).collect())
+    } else {
+        cli.weighting.map(|weighting| match weighting {
+            SequenceWeighting::Henikoff => henikoff_weights(objective_sequences, &gap_chars),
+            SequenceWeighting::Identity => {
+                identity_weights(objective_sequences, &gap_chars, cli.weighting_identity)
+            }
+        })
+    };
 
-    let mut metrics = initial_metrics.clone();
-    let mut state = SetData::new(orig_sets.clone(), orig_gaps.clone(), num_sequences);
+    let alignment_length = objective_sequences.first().map_or(0, Vec::len);
+    let column_weights = if let Some(ref path) = cli.column_weights {
+        Some(read_column_weights(Path::new(path), alignment_length)?)
+    } else if let Some(ref path) = cli.column_mask {
+        Some(read_column_mask(Path::new(path), alignment_length)?)
+    } else if let Some(ref feature) = cli.column_mask_feature {
+        let mask = sequence_data
+            .annotations
+            .as_ref()
+            .and_then(|annotations| {
+                annotations
+                    .column
+                    .iter()
+                    .find(|annotation| annotation.feature == feature.as_bytes())
+            })
+            .ok_or_else(|| {
+                Error::ColumnWeightsParse(format!("no '#=GC {feature}' line found in the input"))
+            })?;
+        let weights = parse_mask_string(&mask.data);
+        if weights.len() != alignment_length {
+            return Err(Error::ColumnWeightsParse(format!(
+                "'#=GC {feature}' has {} columns, but the alignment has {alignment_length}",
+                weights.len()
+            )));
+        }
+        Some(weights)
+    } else {
+        None
+    };
 
-    let heuristic_config = HeuristicConfig {
-        method: cli.heuristic_method,
-        max_iterations: cli.max_iterations,
-        improvement_threshold: cli.improvement_threshold,
-        excluded_seqs_threshold: cli.excluded_seqs_threshold,
+    let groups = if let Some(ref path) = cli.groups {
+        let mut groups = read_groups(Path::new(path), &all_headers)?.select(&active);
+        if let Some(quota) = cli.group_quota {
+            groups.set_default_quota(quota);
+        }
+        if let Some(ref path) = cli.group_quota_file {
+            for (name, quota) in read_group_quotas(Path::new(path))? {
+                if !groups.set_quota(&name, quota) {
+                    warn!("Group '{name}' of the quota file has no sequences in the input");
+                }
+            }
+        }
+        Some(groups)
+    } else {
+        None
     };
 
-    info!(
-        "Processing alignment (heuristic method: {})",
-        cli.heuristic_method
-    );
-    let iteration_data = run_heuristic(
-        &mut state,
-        &mut metrics,
-        &heuristic_config,
-        &keep_pattern,
-        num_sequences,
-    );
+    let mut builder = MaxAlign::builder()
+        .method(cli.heuristic_method)
+        .max_iterations(cli.max_iterations)
+        .beam_width(cli.beam_width)
+        .improvement_threshold(cli.improvement_threshold)
+        .excluded_seqs_threshold(cli.excluded_seqs_threshold)
+        .polish(cli.polish)
+        .refinement(cli.refinement)
+        .threads(cli.threads)
+        .gap_chars(gap_chars.clone())
+        .keep(sequence_data.keep_indices.iter().copied());
+    if let Some(weights) = weights {
+        builder = builder.sequence_weights(weights);
+    }
+    if let Some(column_weights) = column_weights {
+        builder = builder.column_weights(column_weights);
+    }
+    if let Some(steps) = cli.anneal_steps {
+        builder = builder.anneal_steps(steps).seed(cli.seed);
+    }
+    if let Some(limit) = cli.refinement_time_limit {
+        builder = builder.refinement_time_limit(Duration::from_secs_f64(limit));
+    }
+    if let Some(limit) = cli.refinement_node_limit {
+        builder = builder.refinement_node_limit(limit);
+    }
+    if let Some(count) = cli.min_retained {
+        builder = builder.min_retained(count);
+    }
+    if let Some(count) = cli.max_excluded {
+        builder = builder.max_excluded(count);
+    }
+    if let Some(fraction) = cli.max_excluded_fraction {
+        builder = builder.max_excluded_fraction(fraction);
+    }
+    if let Some(ref groups) = groups {
+        builder = builder.groups(groups.clone());
+    }
+    builder = cli.tolerance.configure(builder);
+    if cli.pareto_front.is_some() {
+        builder = builder.pareto_front(true);
+    }
+    if let Some(min_retained) = cli.pareto_select {
+        builder = builder.pareto_selection(min_retained);
+    }
+    if cli.solutions.is_some() {
+        builder = builder.solutions(true);
+    }
+    if let Some(count) = cli.top_solutions {
+        builder = builder.top_solutions(count);
+    }
+    if let Some(index) = cli.solution_index {
+        builder = builder.solution_selection(index - 1);
+    }
+    let model_format = cli
+        .export_model
+        .as_ref()
+        .map(|path| {
+            cli.model_format
+                .or_else(|| ModelFormat::from_path(Path::new(path)))
+                .ok_or_else(|| Error::ModelFormat(path.into()))
+        })
+        .transpose()?;
+    if cli.export_model.is_some() {
+        builder = builder.model(true);
+    }
+    if let Some(ref path) = cli.import_solution {
+        builder = builder.model_solution(ModelSolution::read(path)?);
+    }
+    if cli.solve_model.is_some() {
+        builder = builder.solve_model(true);
+    }
+    let engine = builder.build();
+    let gap_tolerance = engine.gap_tolerance();
+    let polish_config = engine.polish_config().copied();
+    let max_excluded = engine
+        .exclusion_limit()
+        .max_excluded(objective_sequences.len());
+    let mut result = engine.run(objective_sequences)?;
+    if match_sequences.is_some() {
+        count_output_columns(&mut result, &sequences, &gap_chars);
+    }
+
+    if let Some(index) = cli.solution_index
+        && index > result.solutions.len()
+    {
+        return Err(Error::SolutionIndex {
+            index,
+            count: result.solutions.len(),
+        });
+    }
 
-    for (iter, (exseq, area)) in iteration_data.iter().enumerate() {
+    for (iter, (exseq, area)) in result.iteration_data.iter().enumerate() {
         let names = exseq
             .iter()
             .map(|&idx| {
-                crate::fasta::get_record_accession_string(&sequence_data.headers[idx])
-                    .unwrap_or_default()
+                get_record_accession_string(&sequence_data.headers[idx]).unwrap_or_default()
             })
             .format(", ")
             .to_string();
@@ -205,97 +747,190 @@ fn run(cli: &Cli) -> Result<()> {
         );
     }
 
-    let heuristic_metrics = metrics.clone();
-    let mut final_excluded = state.excluded.clone();
-    let mut final_metrics = metrics;
-
-    if cli.refinement {
-        info!(
-            "Starting refinement using the branch-and-bound algorithm to find the optimal solution"
-        );
-        let bb_result = run_branch_and_bound(
-            &orig_sets,
-            &orig_gaps,
-            &heuristic_metrics,
-            &keep_pattern,
-            num_sequences,
+    for (number, polish_move) in result.polish_moves.iter().enumerate() {
+        let names = |indices: &[usize]| {
+            indices
+                .iter()
+                .map(|&idx| {
+                    get_record_accession_string(&sequence_data.headers[idx]).unwrap_or_default()
+                })
+                .join(", ")
+        };
+        let changes = [
+            ("excluded", &polish_move.excluded),
+            ("re-inserted", &polish_move.reinserted),
+        ]
+        .into_iter()
+        .filter(|(_, indices)| !indices.is_empty())
+        .map(|(change, indices)| format!("{change} {}", names(indices)))
+        .join("; ");
+        debug!(
+            "Polishing move {}: alignment area is {} ({changes})",
+            number + 1,
+            polish_move.alignment_area,
         );
-        if bb_result.metrics.alignment_area > final_metrics.alignment_area {
-            final_metrics = bb_result.metrics;
-            final_excluded = bb_result.excluded;
-        }
     }
 
+    let initial_metrics = &result.initial_metrics;
+    let final_metrics = &result.final_metrics;
+    let final_excluded = &result.excluded;
+    // Exclusions in terms of the whole input, including those made in advance.
+    let all_excluded: HashSet<usize> = final_excluded
+        .iter()
+        .map(|&idx| active[idx])
+        .chain(pre_excluded.iter().copied())
+        .collect();
+
     let excluded_count = initial_metrics.sequence_count - final_metrics.sequence_count;
     if excluded_count == 0 {
         info!(
             "No sequences were excluded. Alignment area remained {} ({} sequences)",
             initial_metrics.alignment_area, initial_metrics.sequence_count
         );
+    } else if !result.is_weighted() {
+        // A selected solution of the refinement may be worse than the input.
+        #[allow(clippy::cast_possible_wrap)]
+        let area_change =
+            final_metrics.alignment_area as i64 - initial_metrics.alignment_area as i64;
+        info!(
+            "A total of {} sequences were excluded. Alignment area changed by {:+} (from {} to {})",
+            excluded_count,
+            area_change,
+            initial_metrics.alignment_area,
+            final_metrics.alignment_area
+        );
     } else {
+        // With sequence or column weights, the unweighted area may decrease.
         info!(
-            "A total of {} sequences were excluded. Alignment area improved by {} (from {} to {})",
+            "A total of {} sequences were excluded. Weighted alignment area changed from {:.3} \
+             to {:.3} (alignment area changed from {} to {})",
             excluded_count,
-            final_metrics.alignment_area - initial_metrics.alignment_area,
+            result.weighted_area(initial_metrics),
+            result.weighted_area(final_metrics),
             initial_metrics.alignment_area,
             final_metrics.alignment_area
         );
     }
 
-    let (final_sequences, final_headers) =
-        remove_all_gap_columns(&sequences, &sequence_data.headers, &final_excluded);
-
-    if !final_sequences.is_empty()
-        && let Some(final_alignment_length) = final_sequences.iter().map(Vec::len).max()
+    if let Some(status) = result
+        .refinement_status
+        .filter(|status| !status.is_optimal())
     {
-        final_metrics.alignment_length = final_alignment_length;
-        final_metrics.gap_free_columns =
-            final_metrics.alignment_area / final_metrics.sequence_count;
+        warn!(
+            "Refinement stopped at its {} limit after exploring {} nodes, so the result was not \
+             proven optimal (optimality gap: {:.2}%)",
+            status
+                .limit_reached
+                .map_or_else(String::new, |limit| limit.to_string()),
+            status.nodes,
+            status.optimality_gap() * 100.0
+        );
     }
 
-    let mut output = cli.output.clone();
-    let output_name = if output.is_std() {
-        "stdout".to_string()
-    } else {
-        output.path().to_string_lossy().into_owned()
-    };
-
-    write_fasta(&final_sequences, &final_headers, &mut output)?;
-    info!("Output written to {}", output_name);
+    let output_format = cli
+        .output_format
+        .unwrap_or_else(|| output_format_of(&cli.output, input_format));
+    write_output(
+        &cli.output,
+        output_format,
+        cli.output_compression,
+        &sequences,
+        &sequence_data.headers,
+        sequence_data.annotations.as_ref(),
+        final_excluded,
+        &gap_chars,
+    )?;
 
     if let Some(ref report_path) = cli.report {
-        let input_path = if cli.input.is_std() {
-            "<stdin>".to_string()
-        } else {
-            cli.input.path().to_string_lossy().to_string()
-        };
-
-        let output_path = if cli.output.is_std() {
-            "<stdout>".to_string()
-        } else {
-            cli.output.path().to_string_lossy().to_string()
-        };
-
         let config = ReportConfig {
-            input_path,
-            output_path,
+            input_path: input_name(&cli.input),
+            output_path: output_name(&cli.output),
+            input_format,
+            output_format,
+            gap_chars: &gap_chars,
             heuristic_method: cli.heuristic_method,
             max_iterations: cli.max_iterations,
             improvement_threshold: cli.improvement_threshold,
             excluded_seqs_threshold: cli.excluded_seqs_threshold,
+            max_excluded,
+            groups: cli.groups.as_ref().map(|path| {
+                let mut value = path.clone();
+                if let Some(quota) = cli.group_quota {
+                    value.push_str(&format!(", quota {quota}"));
+                }
+                if let Some(ref quotas) = cli.group_quota_file {
+                    value.push_str(&format!(", quotas from {quotas}"));
+                }
+                value
+            }),
+            beam_width: cli.beam_width,
+            polish: polish_config,
             refinement: cli.refinement,
-            keep_sequence: &cli.keep_sequence,
+            refinement_time_limit: cli.refinement_time_limit,
+            refinement_node_limit: cli.refinement_node_limit,
+            sequence_weights: cli.weights.clone().or_else(|| {
+                cli.weighting.map(|weighting| match weighting {
+                    SequenceWeighting::Henikoff => weighting.to_string(),
+                    SequenceWeighting::Identity => {
+                        format!("{weighting} ({})", cli.weighting_identity)
+                    }
+                })
+            }),
+            column_weights: cli
+                .column_weights
+                .clone()
+                .or_else(|| cli.column_mask.as_ref().map(|path| format!("mask {path}")))
+                .or_else(|| {
+                    cli.column_mask_feature
+                        .as_ref()
+                        .map(|feature| format!("mask #=GC {feature}"))
+                }),
+            gap_tolerance,
+            keep_sequence: describe_selection(
+                &cli.keep_sequence,
+                cli.keep_file.as_ref(),
+                &cli.keep_regex,
+            ),
+            exclude_sequence: describe_selection(
+                &cli.exclude_sequence,
+                cli.exclude_file.as_ref(),
+                &cli.exclude_regex,
+            ),
+            match_description: cli.match_description,
             retained_sequences: cli.retained_sequences.clone(),
             excluded_sequences: cli.excluded_sequences.clone(),
+            pareto_front: cli.pareto_front.clone(),
+            pareto_select: cli.pareto_select,
+            solutions: cli.solutions.clone(),
+            top_solutions: cli.top_solutions,
+            solution_index: cli.solution_index,
+            export_model: cli.export_model.clone(),
+            import_solution: cli.import_solution.clone(),
+            solve_model: cli.solve_model.clone(),
         };
 
         let data = ReportData {
-            initial_metrics: &initial_metrics,
-            heuristic_metrics: &heuristic_metrics,
-            final_metrics: &final_metrics,
-            iteration_data: &iteration_data,
+            initial_metrics,
+            heuristic_metrics: &result.heuristic_metrics,
+            final_metrics,
+            iteration_data: &result.iteration_data,
+            polish_moves: &result.polish_moves,
             headers: &sequence_data.headers,
-            excluded: &final_excluded,
+            excluded: final_excluded,
+            pre_excluded: &pre_excluded
+                .iter()
+                .sorted_unstable()
+                .map(|&idx| get_record_accession_string(&all_headers[idx]).unwrap_or_default())
+                .collect::<Vec<_>>(),
+            weighted_area_scale: result.is_weighted().then(|| result.weighted_area_scale()),
+            refinement_status: result.refinement_status.as_ref(),
+            pareto_front: &result.pareto_front,
+            solutions: &result.solutions,
+            model: result.model.as_ref(),
+            groups: &groups
+                .as_ref()
+                .map(|groups| groups.summarize(|idx| final_excluded.contains(&idx)))
+                .unwrap_or_default(),
         };
 
         write_report(report_path, &config, &data)?;
@@ -303,17 +938,347 @@ fn run(cli: &Cli) -> Result<()> {
     }
 
     if let Some(ref path) = cli.retained_sequences {
-        write_headers_list(path, &sequence_data.headers, &final_excluded, true)?;
+        write_headers_list(path, &all_headers, &all_excluded, true)?;
         info!("List of retained sequences written to {}", path);
     }
     if let Some(ref path) = cli.excluded_sequences {
-        write_headers_list(path, &sequence_data.headers, &final_excluded, false)?;
+        write_headers_list(path, &all_headers, &all_excluded, false)?;
         info!("List of excluded sequences written to {}", path);
     }
+    if let Some(ref path) = cli.pareto_front {
+        write_pareto_front(
+            path,
+            &result.pareto_front,
+            &sequence_data.headers,
+            result.is_weighted().then(|| result.weighted_area_scale()),
+        )?;
+        info!("Pareto front written to {}", path);
+    }
+    if let Some(ref path) = cli.solutions {
+        write_solutions(
+            path,
+            &result.solutions,
+            &sequence_data.headers,
+            result.is_weighted().then(|| result.weighted_area_scale()),
+        )?;
+        info!("Solutions written to {}", path);
+    }
+    if let Some(ref model) = result.model {
+        if let (Some(path), Some(format)) = (&cli.export_model, model_format) {
+            model.write(path, format)?;
+            info!("Model written to {}", path);
+        }
+        if let Some(ref path) = cli.solve_model {
+            model.write_solution(path, &result.excluded)?;
+            info!("Model solution written to {}", path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Filters an alignment with a list of retained or excluded sequences, writes
+/// it and reports its metrics, without any optimization.
+fn run_apply(args: &ApplyArgs) -> Result<()> {
+    let Some((input_format, sequence_data)) =
+        read_input(&args.input, args.input_format, Some("apply"))?
+    else {
+        return Ok(());
+    };
+
+    let (list_path, list_retained) = match (&args.list.retained_list, &args.list.excluded_list) {
+        (Some(path), _) => (path, true),
+        (None, Some(path)) => (path, false),
+        (None, None) => unreachable!("clap requires a list of retained or excluded sequences"),
+    };
+    let listed = SequenceSelection {
+        names: read_names(Path::new(list_path))?,
+        ..SequenceSelection::default()
+    }
+    .select(&sequence_data.headers, "Listed");
+    let excluded: HashSet<usize> = if list_retained {
+        (0..sequence_data.headers.len())
+            .filter(|idx| !listed.contains(idx))
+            .collect()
+    } else {
+        listed
+    };
+    if excluded.len() == sequence_data.headers.len() {
+        return Err(Error::AllExcluded);
+    }
+
+    let gap_chars = args.gaps.gap_chars(input_format);
+    let (sequences, match_sequences) = prepare_sequences(
+        sequence_data.sequences,
+        sequence_data.longest_length,
+        input_format,
+        &gap_chars,
+        args.drop_insertions,
+    )?;
+    let engine = args
+        .tolerance
+        .configure(MaxAlign::builder().gap_chars(gap_chars.clone()))
+        .build();
+    let mut result = engine.apply(match_sequences.as_ref().unwrap_or(&sequences), &excluded)?;
+    if match_sequences.is_some() {
+        count_output_columns(&mut result, &sequences, &gap_chars);
+    }
+    info!(
+        "{} sequence(s) were excluded. Alignment area changed from {} to {}",
+        result.excluded.len(),
+        result.initial_metrics.alignment_area,
+        result.final_metrics.alignment_area
+    );
+
+    let output_format = args
+        .output_format
+        .unwrap_or_else(|| output_format_of(&args.output, input_format));
+    write_output(
+        &args.output,
+        output_format,
+        args.output_compression,
+        &sequences,
+        &sequence_data.headers,
+        sequence_data.annotations.as_ref(),
+        &result.excluded,
+        &gap_chars,
+    )?;
+
+    if let Some(ref report_path) = args.report {
+        let config = ApplyReportConfig {
+            input_path: input_name(&args.input),
+            output_path: output_name(&args.output),
+            input_format,
+            output_format,
+            gap_chars: &gap_chars,
+            gap_tolerance: engine.gap_tolerance(),
+            list_path: list_path.clone(),
+            list_retained,
+        };
+        write_apply_report(
+            report_path,
+            &config,
+            &result.initial_metrics,
+            &result.final_metrics,
+            &sequence_data.headers,
+            &result.excluded,
+        )?;
+        info!("Report written to {}", report_path);
+    }
+
+    Ok(())
+}
 
+/// Writes statistics on the gap structure of an alignment.
+fn run_stats(args: &StatsArgs) -> Result<()> {
+    let Some((input_format, sequence_data)) =
+        read_input(&args.input, args.input_format, Some("stats"))?
+    else {
+        return Ok(());
+    };
+
+    let gap_chars = args.gaps.gap_chars(input_format);
+    let (sequences, match_sequences) = prepare_sequences(
+        sequence_data.sequences,
+        sequence_data.longest_length,
+        input_format,
+        &gap_chars,
+        false,
+    )?;
+    let gap_tolerance = args
+        .tolerance
+        .configure(MaxAlign::builder())
+        .build()
+        .gap_tolerance();
+    let stats = AlignmentStats::new(
+        match_sequences.as_ref().unwrap_or(&sequences),
+        &gap_chars,
+        gap_tolerance,
+    );
+
+    let format = args.format.unwrap_or_else(|| {
+        (!args.output.is_std())
+            .then(|| StatsFormat::from_path(args.output.path()))
+            .flatten()
+            .unwrap_or(StatsFormat::Table)
+    });
+    let compression = if args.output.is_std() {
+        Compression::None
+    } else {
+        Compression::from_path(args.output.path())
+    };
+    let mut writer = CompressedWriter::new(BufWriter::new(args.output.clone()), compression)?;
+    write_stats(&mut writer, &stats, &sequence_data.headers, format)?;
+    writer.finish()?;
     Ok(())
 }
 
+/// Reads the input alignment. Returns `None` after printing the help of the
+/// command (or of the given subcommand) if there is no input to read.
+fn read_input(
+    input: &Input,
+    format: Option<AlignmentFormat>,
+    subcommand: Option<&str>,
+) -> Result<Option<(AlignmentFormat, SequenceData)>> {
+    let print_help = || {
+        let mut command = Cli::command();
+        #[allow(clippy::unwrap_used)]
+        match subcommand.and_then(|name| command.find_subcommand_mut(name)) {
+            Some(subcommand) => subcommand.print_help().unwrap(),
+            None => command.print_help().unwrap(),
+        }
+    };
+
+    if input.is_std() && std::io::stdin().is_terminal() {
+        print_help();
+        return Ok(None);
+    }
+    match read_alignment(input, format, &[]) {
+        Ok(data) => Ok(Some(data)),
+        Err(Error::EmptyInput) if input.is_std() => {
+            print_help();
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Pads the sequences to the same length and, in A2M/A3M alignments, selects
+/// the match-state columns, which are the only ones that count towards the
+/// area. Returns the sequences to output and the match-state sequences, if
+/// any. With `drop_insertions`, both are the match-state sequences, and other
+/// formats are an error.
+#[allow(clippy::type_complexity)]
+fn prepare_sequences(
+    mut sequences: Vec<Vec<u8>>,
+    longest_length: usize,
+    format: AlignmentFormat,
+    gap_chars: &GapChars,
+    drop_insertions: bool,
+) -> Result<(Vec<Vec<u8>>, Option<Vec<Vec<u8>>>)> {
+    let padding = if gap_chars.contains(b'-') {
+        b'-'
+    } else {
+        gap_chars.chars()[0]
+    };
+    for seq in &mut sequences {
+        seq.resize(longest_length, padding);
+    }
+
+    let match_sequences = format
+        .has_insert_states()
+        .then(|| select_columns(&sequences, &find_match_columns(&sequences)));
+    if drop_insertions {
+        let Some(ref match_sequences) = match_sequences else {
+            return Err(Error::DropInsertions(format.to_string()));
+        };
+        sequences.clone_from(match_sequences);
+    }
+    Ok((sequences, match_sequences))
+}
+
+/// Sets the number of columns in the metrics of a result to that of the input
+/// and output alignments, which keep the insert-state columns of A2M/A3M
+/// input that the metrics were computed without.
+fn count_output_columns(result: &mut MaxAlignResult, sequences: &[Vec<u8>], gap_chars: &GapChars) {
+    result.initial_metrics.alignment_length = sequences.first().map_or(0, Vec::len);
+    if !result.retained.is_empty() {
+        result.final_metrics.alignment_length =
+            find_retained_columns(sequences, &result.excluded, gap_chars)
+                .into_iter()
+                .filter(|&retained| retained)
+                .count();
+    }
+}
+
+/// Returns the output format inferred from the output file extension, or the
+/// input format. PHYLIP extensions keep the flavour of PHYLIP input.
+fn output_format_of(output: &Output, input_format: AlignmentFormat) -> AlignmentFormat {
+    let from_extension = (!output.is_std())
+        .then(|| AlignmentFormat::from_path(output.path()))
+        .flatten();
+    match (from_extension, input_format) {
+        (Some(AlignmentFormat::PhylipRelaxed), AlignmentFormat::Phylip) => input_format,
+        _ => from_extension.unwrap_or(input_format),
+    }
+}
+
+/// Writes the alignment without the excluded sequences and the columns left
+/// with only gaps, along with the annotations of the retained sequences and
+/// columns.
+#[allow(clippy::too_many_arguments)]
+fn write_output(
+    output: &Output,
+    output_format: AlignmentFormat,
+    compression: Option<Compression>,
+    sequences: &[Vec<u8>],
+    headers: &[Vec<u8>],
+    annotations: Option<&StockholmAnnotations>,
+    excluded: &HashSet<usize>,
+    gap_chars: &GapChars,
+) -> Result<()> {
+    let (final_sequences, final_headers) =
+        remove_all_gap_columns(sequences, headers, excluded, gap_chars);
+
+    let output_compression = compression.unwrap_or_else(|| {
+        if output.is_std() {
+            Compression::None
+        } else {
+            Compression::from_path(output.path())
+        }
+    });
+    let mut writer = CompressedWriter::new(BufWriter::new(output.clone()), output_compression)?;
+
+    let annotations = annotations.map(|annotations| {
+        let retained_names: HashSet<&[u8]> = final_headers.iter().map(Vec::as_slice).collect();
+        let retained_columns = find_retained_columns(sequences, excluded, gap_chars);
+        annotations.filter(&retained_names, &retained_columns)
+    });
+    write_alignment(
+        output_format,
+        &final_sequences,
+        &final_headers,
+        annotations.as_ref(),
+        gap_chars,
+        &mut writer,
+    )?;
+    writer.finish()?;
+    if output.is_std() {
+        info!("Output written to stdout");
+    } else {
+        info!("Output written to {}", output.path().to_string_lossy());
+    }
+    Ok(())
+}
+
+/// Returns the name of the input file for the report.
+fn input_name(input: &Input) -> String {
+    if input.is_std() {
+        "<stdin>".to_string()
+    } else {
+        input.path().to_string_lossy().to_string()
+    }
+}
+
+/// Returns the name of the output file for the report.
+fn output_name(output: &Output) -> String {
+    if output.is_std() {
+        "<stdout>".to_string()
+    } else {
+        output.path().to_string_lossy().to_string()
+    }
+}
+
+/// Describes the sequences selected on the command line, for the report.
+fn describe_selection(names: &[String], file: Option<&String>, patterns: &[Regex]) -> Vec<String> {
+    names
+        .iter()
+        .cloned()
+        .chain(file.map(|path| format!("file {path}")))
+        .chain(patterns.iter().map(|pattern| format!("regex {pattern}")))
+        .collect()
+}
+
 fn main() -> ExitCode {
     let cli = Cli::parse();
     setup_logging(cli.verbosity);
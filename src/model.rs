@@ -0,0 +1,1072 @@
+//! Export of the reduced problem for external exact solvers, and import of
+//! their solutions.
+//!
+//! The weighted area is the product of the weight of the retained sequences and
+//! the weight of the gap-free columns. It is linearized with three kinds of
+//! binary variables:
+//!
+//! - `r<i>`: sequence `i` is retained;
+//! - `y<k>`: the columns with gap pattern `k` are gap-free, which requires
+//!   every sequence of the pattern to be excluded;
+//! - `z<k>_<i>`: both of the above, which counts the weight of sequence `i`
+//!   times the weight of the columns of pattern `k`.
+//!
+//! Only the sequences and patterns left by the reduction of the
+//! branch-and-bound refinement (see [`reduce_problem`](crate::optimize::reduce_problem))
+//! are modelled, so the model only holds solutions that reach the area it was
+//! reduced for. Other sequences are always retained. A limit on the number of
+//! excluded sequences, in total or in a group, becomes a cardinality constraint
+//! on the `r<i>` variables.
+
+use crate::alignment::ExclusionBudget;
+use crate::bitops::Bitset;
+use crate::compression::{Compression, create_file, open_file};
+use crate::error::{Error, Result};
+use crate::optimize::ReducedProblem;
+use crate::weights::Weights;
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Number of terms written per line in LP files.
+const LP_TERMS_PER_LINE: usize = 8;
+
+/// Supported model file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelFormat {
+    /// CPLEX LP format.
+    Lp,
+    /// Free MPS format. The objective is negated and minimized.
+    Mps,
+    /// Weighted partial MaxSAT in the classic DIMACS WCNF format.
+    Wcnf,
+}
+
+impl std::fmt::Display for ModelFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Lp => "lp",
+            Self::Mps => "mps",
+            Self::Wcnf => "wcnf",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl std::str::FromStr for ModelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lp" => Ok(Self::Lp),
+            "mps" => Ok(Self::Mps),
+            "wcnf" => Ok(Self::Wcnf),
+            _ => Err(format!(
+                "invalid model format '{s}': must be lp, mps, or wcnf"
+            )),
+        }
+    }
+}
+
+impl ModelFormat {
+    /// Guesses the format from a file extension, ignoring compression suffixes.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let path = if Compression::from_path(path) == Compression::None {
+            path.to_path_buf()
+        } else {
+            path.with_extension("")
+        };
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+/// A variable of the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Var {
+    /// The candidate at this position is retained.
+    Retained(usize),
+    /// The columns of this pattern are gap-free.
+    Free(usize),
+    /// The columns of the pattern are gap-free and the candidate is retained.
+    Both(usize, usize),
+}
+
+/// A constraint of the model.
+#[derive(Debug, Clone, Copy)]
+enum Constraint {
+    /// At most one of the variables is set.
+    Conflict(Var, Var),
+    /// The first variable requires the second one.
+    Implies(Var, Var),
+    /// The exclusion limit with this index holds, i.e., enough of its
+    /// candidates are retained.
+    MaxExcluded(usize),
+}
+
+/// The problem reduced by the branch-and-bound refinement, as a 0-1 program.
+#[derive(Debug, Clone)]
+pub struct ReducedModel {
+    /// Sequences that may be excluded, in increasing order.
+    pub candidates: Vec<usize>,
+    /// Weight of each candidate.
+    pub candidate_weights: Vec<usize>,
+    /// Gap patterns of the columns that may become gap-free, as positions in
+    /// `candidates`, with the total weight of their columns.
+    pub patterns: Vec<(Vec<usize>, usize)>,
+    /// Total weight of the sequences that are always retained.
+    pub fixed_weight: usize,
+    /// Total weight of the columns that are always gap-free.
+    pub gap_free_weight: usize,
+    /// Weighted area the problem was reduced for.
+    pub min_area: usize,
+    /// Limits on the excluded candidates: the positions in `candidates` of the
+    /// limited sequences, with the largest number of them that may be
+    /// excluded. Limits that can't bind are left out.
+    pub exclusion_limits: Vec<(Vec<usize>, usize)>,
+}
+
+impl ReducedModel {
+    /// Builds the model of a reduced problem. The columns that the reduction
+    /// kept are grouped by their gap pattern in `orig_sets`. The excluded
+    /// sequences of solutions fit in `budget`.
+    #[must_use]
+    pub fn new(
+        problem: &ReducedProblem,
+        orig_sets: &[Bitset],
+        orig_gaps: &[Bitset],
+        weights: &Weights,
+        column_weights: &Weights,
+        min_area: usize,
+        budget: &ExclusionBudget,
+    ) -> Self {
+        let mut columns = Bitset::new(orig_gaps.first().map_or(0, Bitset::width));
+        for gap in &problem.gaps {
+            columns.union_with(gap);
+        }
+
+        let mut pattern_weights: Vec<(&Bitset, usize)> = Vec::new();
+        let mut pattern_index: HashMap<&Bitset, usize> = HashMap::new();
+        for (set, gap) in orig_sets.iter().zip(orig_gaps) {
+            let weight = column_weights.of_set(gap);
+            if weight == 0 || !gap.is_subset(&columns) {
+                continue;
+            }
+            let idx = *pattern_index.entry(set).or_insert_with(|| {
+                pattern_weights.push((set, 0));
+                pattern_weights.len() - 1
+            });
+            pattern_weights[idx].1 += weight;
+        }
+
+        let mut candidate_set = Bitset::new(weights.len());
+        for (set, _) in &pattern_weights {
+            candidate_set.union_with(set);
+        }
+        let candidates: Vec<usize> = candidate_set.ones().collect();
+        let position: HashMap<usize, usize> = candidates
+            .iter()
+            .enumerate()
+            .map(|(pos, &idx)| (idx, pos))
+            .collect();
+        let exclusion_limits = budget
+            .max_excluded
+            .map(|max| ((0..candidates.len()).collect(), max))
+            .into_iter()
+            .chain(budget.groups.iter().map(|group| {
+                (
+                    (0..candidates.len())
+                        .filter(|&pos| group.members.contains(candidates[pos]))
+                        .collect(),
+                    group.max_excluded,
+                )
+            }))
+            .filter(|(positions, max): &(Vec<usize>, usize)| *max < positions.len())
+            .collect();
+
+        Self {
+            candidate_weights: candidates.iter().map(|&idx| weights.get(idx)).collect(),
+            patterns: pattern_weights
+                .into_iter()
+                .map(|(set, weight)| (set.ones().map(|idx| position[&idx]).collect(), weight))
+                .collect(),
+            fixed_weight: weights.total() - weights.of_set(&candidate_set),
+            gap_free_weight: problem.gap_free_weight,
+            min_area,
+            exclusion_limits,
+            candidates,
+        }
+    }
+
+    /// Returns the weighted area of the alignment without the given sequences,
+    /// counting only the columns of the model.
+    #[must_use]
+    pub fn area(&self, excluded: &HashSet<usize>) -> usize {
+        let is_excluded: Vec<bool> = self
+            .candidates
+            .iter()
+            .map(|idx| excluded.contains(idx))
+            .collect();
+        let retained_weight = self.fixed_weight
+            + self
+                .candidate_weights
+                .iter()
+                .zip(&is_excluded)
+                .filter(|&(_, &excluded)| !excluded)
+                .map(|(&weight, _)| weight)
+                .sum::<usize>();
+        let column_weight = self.gap_free_weight
+            + self
+                .patterns
+                .iter()
+                .filter(|(pattern, _)| pattern.iter().all(|&pos| is_excluded[pos]))
+                .map(|&(_, weight)| weight)
+                .sum::<usize>();
+        retained_weight * column_weight
+    }
+
+    /// Returns the constant term of the area, which the objective of the model
+    /// leaves out.
+    #[must_use]
+    pub const fn objective_offset(&self) -> usize {
+        self.gap_free_weight * self.fixed_weight
+    }
+
+    /// Returns the number of variables of the model.
+    #[must_use]
+    pub fn num_variables(&self) -> usize {
+        self.variables().len()
+    }
+
+    /// Returns the number of constraints of the model.
+    #[must_use]
+    pub fn num_constraints(&self) -> usize {
+        self.constraints().len()
+    }
+
+    /// Returns the variables in the order in which they are numbered, starting
+    /// with the retained sequences.
+    fn variables(&self) -> Vec<Var> {
+        let mut variables: Vec<Var> = (0..self.candidates.len()).map(Var::Retained).collect();
+        variables.extend((0..self.patterns.len()).map(Var::Free));
+        for (k, (pattern, _)) in self.patterns.iter().enumerate() {
+            variables.extend(
+                (0..self.candidates.len())
+                    .filter(|pos| !pattern.contains(pos) && self.candidate_weights[*pos] > 0)
+                    .map(|pos| Var::Both(k, pos)),
+            );
+        }
+        variables
+    }
+
+    /// Returns the nonzero objective coefficients, in variable order.
+    fn objective(&self) -> Vec<(Var, usize)> {
+        self.variables()
+            .into_iter()
+            .map(|var| {
+                let coef = match var {
+                    Var::Retained(pos) => self.gap_free_weight * self.candidate_weights[pos],
+                    Var::Free(k) => self.patterns[k].1 * self.fixed_weight,
+                    Var::Both(k, pos) => self.patterns[k].1 * self.candidate_weights[pos],
+                };
+                (var, coef)
+            })
+            .filter(|&(_, coef)| coef > 0)
+            .collect()
+    }
+
+    fn constraints(&self) -> Vec<Constraint> {
+        let mut constraints = Vec::new();
+        for (k, (pattern, _)) in self.patterns.iter().enumerate() {
+            constraints.extend(
+                pattern
+                    .iter()
+                    .map(|&pos| Constraint::Conflict(Var::Free(k), Var::Retained(pos))),
+            );
+        }
+        for var in self.variables() {
+            if let Var::Both(k, pos) = var {
+                constraints.push(Constraint::Implies(var, Var::Free(k)));
+                constraints.push(Constraint::Implies(var, Var::Retained(pos)));
+            }
+        }
+        constraints.extend((0..self.exclusion_limits.len()).map(Constraint::MaxExcluded));
+        constraints
+    }
+
+    /// Returns the number of candidates that must be retained under a
+    /// [`Constraint::MaxExcluded`] constraint.
+    fn min_retained(&self, limit: usize) -> usize {
+        let (positions, max) = &self.exclusion_limits[limit];
+        positions.len() - max
+    }
+
+    fn name(&self, var: Var) -> String {
+        match var {
+            Var::Retained(pos) => format!("r{}", self.candidates[pos]),
+            Var::Free(k) => format!("y{k}"),
+            Var::Both(k, pos) => format!("z{k}_{}", self.candidates[pos]),
+        }
+    }
+
+    /// Writes the model to a file in the given format, compressing it
+    /// according to its extension.
+    pub fn write(&self, path: impl AsRef<Path>, format: ModelFormat) -> Result<()> {
+        let path = path.as_ref();
+        let write_err = |e| Error::ModelWrite {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let mut writer = create_file(path).map_err(write_err)?;
+        match format {
+            ModelFormat::Lp => self.write_lp(&mut writer),
+            ModelFormat::Mps => self.write_mps(&mut writer),
+            ModelFormat::Wcnf => self.write_wcnf(&mut writer),
+        }
+        .map_err(write_err)?;
+        writer.finish().map_err(write_err)?;
+        Ok(())
+    }
+
+    fn write_lp(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "\\ Reduced MaxAlign problem: {} candidate sequences, {} column patterns",
+            self.candidates.len(),
+            self.patterns.len()
+        )?;
+        writeln!(
+            writer,
+            "\\ The weighted area is the objective value plus {}",
+            self.objective_offset()
+        )?;
+        writeln!(writer, "Maximize")?;
+        write!(writer, " obj:")?;
+        for (idx, (var, coef)) in self.objective().into_iter().enumerate() {
+            if idx > 0 && idx % LP_TERMS_PER_LINE == 0 {
+                write!(writer, "\n ")?;
+            }
+            let sign = if idx == 0 { "" } else { " +" };
+            write!(writer, "{sign} {coef} {}", self.name(var))?;
+        }
+        writeln!(writer)?;
+
+        writeln!(writer, "Subject To")?;
+        for (idx, constraint) in self.constraints().into_iter().enumerate() {
+            match constraint {
+                Constraint::Conflict(a, b) => {
+                    writeln!(writer, " c{idx}: {} + {} <= 1", self.name(a), self.name(b))?;
+                }
+                Constraint::Implies(a, b) => {
+                    writeln!(writer, " c{idx}: {} - {} <= 0", self.name(a), self.name(b))?;
+                }
+                Constraint::MaxExcluded(limit) => {
+                    write!(writer, " c{idx}:")?;
+                    for (term, &pos) in self.exclusion_limits[limit].0.iter().enumerate() {
+                        if term > 0 && term % LP_TERMS_PER_LINE == 0 {
+                            write!(writer, "\n ")?;
+                        }
+                        let sign = if term == 0 { "" } else { " +" };
+                        write!(writer, "{sign} {}", self.name(Var::Retained(pos)))?;
+                    }
+                    writeln!(writer, " >= {}", self.min_retained(limit))?;
+                }
+            }
+        }
+
+        writeln!(writer, "Binaries")?;
+        for chunk in &self.variables().into_iter().chunks(LP_TERMS_PER_LINE) {
+            writeln!(writer, " {}", chunk.map(|var| self.name(var)).join(" "))?;
+        }
+        writeln!(writer, "End")
+    }
+
+    fn write_mps(&self, writer: &mut impl Write) -> io::Result<()> {
+        let constraints = self.constraints();
+        let mut entries: HashMap<Var, Vec<(String, i64)>> = HashMap::new();
+        for (var, coef) in self.objective() {
+            #[allow(clippy::cast_possible_wrap)]
+            entries
+                .entry(var)
+                .or_default()
+                .push(("obj".to_string(), -(coef as i64)));
+        }
+        for (idx, constraint) in constraints.iter().enumerate() {
+            let (a, b, coef_b) = match *constraint {
+                Constraint::Conflict(a, b) => (a, b, 1),
+                Constraint::Implies(a, b) => (a, b, -1),
+                Constraint::MaxExcluded(limit) => {
+                    for &pos in &self.exclusion_limits[limit].0 {
+                        entries
+                            .entry(Var::Retained(pos))
+                            .or_default()
+                            .push((format!("c{idx}"), 1));
+                    }
+                    continue;
+                }
+            };
+            entries.entry(a).or_default().push((format!("c{idx}"), 1));
+            entries
+                .entry(b)
+                .or_default()
+                .push((format!("c{idx}"), coef_b));
+        }
+
+        writeln!(
+            writer,
+            "* Reduced MaxAlign problem: {} candidate sequences, {} column patterns",
+            self.candidates.len(),
+            self.patterns.len()
+        )?;
+        writeln!(
+            writer,
+            "* The weighted area is {} minus the objective value",
+            self.objective_offset()
+        )?;
+        writeln!(writer, "NAME maxalign")?;
+        writeln!(writer, "ROWS")?;
+        writeln!(writer, " N obj")?;
+        for (idx, constraint) in constraints.iter().enumerate() {
+            let kind = match constraint {
+                Constraint::MaxExcluded(_) => "G",
+                _ => "L",
+            };
+            writeln!(writer, " {kind} c{idx}")?;
+        }
+        writeln!(writer, "COLUMNS")?;
+        writeln!(writer, " MARKER 'MARKER' 'INTORG'")?;
+        let variables = self.variables();
+        for &var in &variables {
+            let name = self.name(var);
+            for (row, coef) in entries.get(&var).into_iter().flatten() {
+                writeln!(writer, " {name} {row} {coef}")?;
+            }
+        }
+        writeln!(writer, " MARKER 'MARKER' 'INTEND'")?;
+        writeln!(writer, "RHS")?;
+        for (idx, constraint) in constraints.iter().enumerate() {
+            match *constraint {
+                Constraint::Conflict(..) => writeln!(writer, " RHS c{idx} 1")?,
+                Constraint::MaxExcluded(limit) => {
+                    writeln!(writer, " RHS c{idx} {}", self.min_retained(limit))?;
+                }
+                Constraint::Implies(..) => {}
+            }
+        }
+        writeln!(writer, "BOUNDS")?;
+        for &var in &variables {
+            writeln!(writer, " BV BND {}", self.name(var))?;
+        }
+        writeln!(writer, "ENDATA")
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn write_wcnf(&self, writer: &mut impl Write) -> io::Result<()> {
+        let number: HashMap<Var, usize> = self
+            .variables()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, var)| (var, idx + 1))
+            .collect();
+        let objective = self.objective();
+        let mut next_var = number.len() + 1;
+        let mut hard: Vec<Vec<i64>> = Vec::new();
+        for constraint in self.constraints() {
+            let var = |var: Var| number[&var] as i64;
+            match constraint {
+                Constraint::Conflict(a, b) => hard.push(vec![-var(a), -var(b)]),
+                Constraint::Implies(a, b) => hard.push(vec![-var(a), var(b)]),
+                Constraint::MaxExcluded(limit) => {
+                    let (positions, max) = &self.exclusion_limits[limit];
+                    let excluded: Vec<i64> = positions
+                        .iter()
+                        .map(|&pos| -var(Var::Retained(pos)))
+                        .collect();
+                    hard.extend(at_most(&excluded, *max, &mut next_var));
+                }
+            }
+        }
+        let top = objective.iter().map(|&(_, coef)| coef).sum::<usize>() + 1;
+
+        writeln!(
+            writer,
+            "c Reduced MaxAlign problem: {} candidate sequences, {} column patterns",
+            self.candidates.len(),
+            self.patterns.len()
+        )?;
+        writeln!(
+            writer,
+            "c The weighted area is the weight of the satisfied soft clauses plus {}",
+            self.objective_offset()
+        )?;
+        writeln!(
+            writer,
+            "c Variables 1 to {} tell whether each candidate sequence is retained:",
+            self.candidates.len()
+        )?;
+        for (pos, &idx) in self.candidates.iter().enumerate() {
+            writeln!(writer, "c {} r{idx}", pos + 1)?;
+        }
+        if next_var > number.len() + 1 {
+            writeln!(
+                writer,
+                "c Variables {} to {} count the excluded sequences",
+                number.len() + 1,
+                next_var - 1
+            )?;
+        }
+        writeln!(
+            writer,
+            "p wcnf {} {} {top}",
+            next_var - 1,
+            objective.len() + hard.len()
+        )?;
+        for clause in hard {
+            writeln!(writer, "{top} {} 0", clause.iter().join(" "))?;
+        }
+        for (var, coef) in objective {
+            writeln!(writer, "{coef} {} 0", number[&var])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the sequences excluded by a solution of the model.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the solution has no value for the retained variable
+    /// of a candidate, if it excludes more candidates than a limit allows, or
+    /// if its area is below the area the model was reduced for.
+    pub fn excluded(&self, solution: &ModelSolution) -> Result<HashSet<usize>> {
+        let mut is_excluded = Vec::with_capacity(self.candidates.len());
+        for (pos, &idx) in self.candidates.iter().enumerate() {
+            let name = format!("r{idx}");
+            let retained = solution
+                .named
+                .get(&name)
+                .or_else(|| solution.numbered.get(&(pos + 1)))
+                .ok_or_else(|| {
+                    Error::ModelSolutionParse(format!(
+                        "no value for '{name}' (variable {})",
+                        pos + 1
+                    ))
+                })?;
+            is_excluded.push(!retained);
+        }
+        for (positions, max) in &self.exclusion_limits {
+            let count = positions.iter().filter(|&&pos| is_excluded[pos]).count();
+            if count > *max {
+                return Err(Error::ModelSolutionParse(format!(
+                    "the solution excludes {count} sequences of a limit of {max}"
+                )));
+            }
+        }
+        let excluded: HashSet<usize> = self
+            .candidates
+            .iter()
+            .zip(&is_excluded)
+            .filter(|&(_, &excluded)| excluded)
+            .map(|(&idx, _)| idx)
+            .collect();
+        let area = self.area(&excluded);
+        if area < self.min_area {
+            return Err(Error::ModelSolutionParse(format!(
+                "the solution has an area of {area}, below the area of {} the model was reduced for",
+                self.min_area
+            )));
+        }
+        Ok(excluded)
+    }
+
+    /// Finds an optimal solution of the model with a depth-first
+    /// branch-and-bound search over the candidates, and returns the excluded
+    /// sequences. Meant as a stand-in for an external solver on small
+    /// problems, as its running time grows exponentially with the number of
+    /// candidates.
+    #[must_use]
+    pub fn solve(&self) -> HashSet<usize> {
+        let count = self.candidates.len();
+        let patterns: Vec<(Bitset, usize)> = self
+            .patterns
+            .iter()
+            .map(|(pattern, weight)| {
+                (
+                    Bitset::from_indices(count, pattern.iter().copied()),
+                    *weight,
+                )
+            })
+            .collect();
+        let total_weight = self.fixed_weight + self.candidate_weights.iter().sum::<usize>();
+        let limits: Vec<(Bitset, usize)> = self
+            .exclusion_limits
+            .iter()
+            .map(|(positions, max)| (Bitset::from_indices(count, positions.iter().copied()), *max))
+            .collect();
+        let weight_of = |set: &Bitset| {
+            set.ones()
+                .map(|pos| self.candidate_weights[pos])
+                .sum::<usize>()
+        };
+
+        let mut best_area = self.gap_free_weight * total_weight;
+        let mut best = Bitset::new(count);
+        let mut stack = vec![(0, Bitset::new(count), Bitset::new(count))];
+        while let Some((depth, excluded, retained)) = stack.pop() {
+            let available_weight = total_weight - weight_of(&excluded);
+            let mut bound_weight = self.gap_free_weight;
+            let mut column_weight = self.gap_free_weight;
+            for (pattern, weight) in &patterns {
+                if pattern.is_disjoint(&retained) {
+                    bound_weight += weight;
+                }
+                if pattern.is_subset(&excluded) {
+                    column_weight += weight;
+                }
+            }
+            if available_weight * bound_weight <= best_area {
+                continue;
+            }
+            if available_weight * column_weight > best_area {
+                best_area = available_weight * column_weight;
+                best = excluded.clone();
+            }
+            if depth == count {
+                continue;
+            }
+            let mut with_retained = retained.clone();
+            with_retained.insert(depth);
+            if limits.iter().any(|(limited, max)| {
+                limited.contains(depth) && excluded.count_intersection(limited) >= *max
+            }) {
+                stack.push((depth + 1, excluded, with_retained));
+                continue;
+            }
+            stack.push((depth + 1, excluded.clone(), with_retained));
+            let mut with_excluded = excluded;
+            with_excluded.insert(depth);
+            stack.push((depth + 1, with_excluded, retained));
+        }
+        best.ones().map(|pos| self.candidates[pos]).collect()
+    }
+
+    /// Writes a solution of the model as lines of variable names and values,
+    /// which [`ModelSolution::read`] reads back.
+    pub fn write_solution(&self, path: impl AsRef<Path>, excluded: &HashSet<usize>) -> Result<()> {
+        let path = path.as_ref();
+        let write_err = |e| Error::ModelWrite {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let mut writer = create_file(path).map_err(write_err)?;
+        let is_excluded = |pos: usize| excluded.contains(&self.candidates[pos]);
+        let is_free = |k: usize| self.patterns[k].0.iter().all(|&pos| is_excluded(pos));
+        writeln!(
+            writer,
+            "# Objective value = {}",
+            self.area(excluded) - self.objective_offset()
+        )
+        .map_err(write_err)?;
+        for var in self.variables() {
+            let value = match var {
+                Var::Retained(pos) => !is_excluded(pos),
+                Var::Free(k) => is_free(k),
+                Var::Both(k, pos) => is_free(k) && !is_excluded(pos),
+            };
+            writeln!(writer, "{} {}", self.name(var), u8::from(value)).map_err(write_err)?;
+        }
+        writer.finish().map_err(write_err)?;
+        Ok(())
+    }
+}
+
+/// Values of the variables in the solution file of an external solver.
+///
+/// Variables are read by name from lines that hold a model variable followed
+/// by its value (as written by Gurobi, HiGHS, SCIP or CBC, among others) or
+/// from CPLEX XML solutions, and by number from the `v` lines of MaxSAT
+/// solvers, either as literals or as a string of 0s and 1s.
+#[derive(Debug, Clone, Default)]
+pub struct ModelSolution {
+    named: HashMap<String, bool>,
+    numbered: HashMap<usize, bool>,
+}
+
+impl ModelSolution {
+    /// Reads a solution file, decompressing it if needed.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let read_err = |e| Error::ModelSolutionRead {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let solution = Self::parse(open_file(path).map_err(read_err)?).map_err(read_err)?;
+        if solution.is_empty() {
+            return Err(Error::ModelSolutionParse(format!(
+                "no variable values found in '{}'",
+                path.display()
+            )));
+        }
+        Ok(solution)
+    }
+
+    /// Parses a solution from a reader.
+    pub fn parse(reader: impl BufRead) -> io::Result<Self> {
+        let mut solution = Self::default();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if let Some(values) = line.strip_prefix("v ") {
+                solution.parse_maxsat_values(values);
+            } else if line.contains("name=\"") {
+                if let (Some(name), Some(value)) =
+                    (xml_attribute(line, "name"), xml_attribute(line, "value"))
+                    && let Ok(value) = value.parse::<f64>()
+                {
+                    solution.named.insert(name.to_string(), value > 0.5);
+                }
+            } else if !line.starts_with(['#', 'c', 's', 'o']) {
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                if let Some(pos) = tokens.iter().position(|token| is_variable_name(token))
+                    && let Some(value) = tokens[pos + 1..]
+                        .iter()
+                        .find_map(|token| token.parse::<f64>().ok())
+                {
+                    solution.named.insert(tokens[pos].to_string(), value > 0.5);
+                }
+            }
+        }
+        Ok(solution)
+    }
+
+    fn parse_maxsat_values(&mut self, values: &str) {
+        let values = values.trim();
+        if !values.is_empty() && values.bytes().all(|b| b == b'0' || b == b'1') {
+            for (idx, b) in values.bytes().enumerate() {
+                self.numbered.insert(idx + 1, b == b'1');
+            }
+            return;
+        }
+        for literal in values
+            .split_whitespace()
+            .filter_map(|t| t.parse::<i64>().ok())
+        {
+            if literal != 0 {
+                self.numbered
+                    .insert(literal.unsigned_abs() as usize, literal > 0);
+            }
+        }
+    }
+
+    /// Returns `true` if no variable value was read.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.named.is_empty() && self.numbered.is_empty()
+    }
+}
+
+/// Encodes that at most `max` of `literals` are true as clauses, with the
+/// sequential counter of Sinz (2005). The auxiliary variable of the `j`-th
+/// count after the `i`-th literal is set if at least `j + 1` of the literals
+/// up to the `i`-th one are true. Auxiliary variables are numbered from
+/// `next_var`, which is advanced past them.
+#[allow(clippy::cast_possible_wrap)]
+fn at_most(literals: &[i64], max: usize, next_var: &mut usize) -> Vec<Vec<i64>> {
+    let n = literals.len();
+    if max == 0 {
+        return literals.iter().map(|&lit| vec![-lit]).collect();
+    }
+    if n <= max {
+        return Vec::new();
+    }
+    let first = *next_var as i64;
+    *next_var += (n - 1) * max;
+    let count = |i: usize, j: usize| first + (i * max + j) as i64;
+
+    let mut clauses = vec![vec![-literals[0], count(0, 0)]];
+    clauses.extend((1..max).map(|j| vec![-count(0, j)]));
+    for (i, &literal) in literals.iter().enumerate().take(n - 1).skip(1) {
+        clauses.push(vec![-literal, count(i, 0)]);
+        clauses.push(vec![-count(i - 1, 0), count(i, 0)]);
+        for j in 1..max {
+            clauses.push(vec![-literal, -count(i - 1, j - 1), count(i, j)]);
+            clauses.push(vec![-count(i - 1, j), count(i, j)]);
+        }
+        clauses.push(vec![-literal, -count(i - 1, max - 1)]);
+    }
+    clauses.push(vec![-literals[n - 1], -count(n - 2, max - 1)]);
+    clauses
+}
+
+/// Returns `true` for the names of the variables of a [`ReducedModel`].
+fn is_variable_name(token: &str) -> bool {
+    let Some(rest) = token.strip_prefix(['r', 'y', 'z']) else {
+        return false;
+    };
+    !rest.is_empty()
+        && rest.split('_').count() <= 2
+        && rest
+            .split('_')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Returns the value of an attribute of an XML element.
+fn xml_attribute<'a>(line: &'a str, attribute: &str) -> Option<&'a str> {
+    let start = line.find(&format!(" {attribute}=\""))? + attribute.len() + 3;
+    let end = line[start..].find('"')?;
+    Some(&line[start..start + end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg64;
+
+    fn parse(text: &str) -> ModelSolution {
+        ModelSolution::parse(text.as_bytes()).unwrap()
+    }
+
+    /// A model with three candidates (sequences 1, 4 and 6) and two patterns.
+    fn small_model() -> ReducedModel {
+        ReducedModel {
+            candidates: vec![1, 4, 6],
+            candidate_weights: vec![1, 1, 1],
+            patterns: vec![(vec![0], 3), (vec![1, 2], 2)],
+            fixed_weight: 2,
+            gap_free_weight: 4,
+            min_area: 0,
+            exclusion_limits: Vec::new(),
+        }
+    }
+
+    /// A random model with up to 10 candidates, some of them of zero weight,
+    /// and with exclusion limits if `limited` is set.
+    fn random_model(rng: &mut Pcg64, limited: bool) -> ReducedModel {
+        let count = rng.random_range(1..11);
+        let patterns = (0..rng.random_range(1..8))
+            .map(|_| {
+                let mut pattern: Vec<usize> = (0..count).filter(|_| rng.random_bool(0.3)).collect();
+                if pattern.is_empty() {
+                    pattern.push(rng.random_range(0..count));
+                }
+                (pattern, rng.random_range(1..20))
+            })
+            .collect();
+        let exclusion_limits = if limited {
+            (0..rng.random_range(1..3))
+                .map(|_| {
+                    let positions: Vec<usize> =
+                        (0..count).filter(|_| rng.random_bool(0.6)).collect();
+                    let max = rng.random_range(0..=positions.len());
+                    (positions, max)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        ReducedModel {
+            candidates: (0..count).map(|pos| 2 * pos + 1).collect(),
+            candidate_weights: (0..count).map(|_| rng.random_range(0..5)).collect(),
+            patterns,
+            fixed_weight: rng.random_range(1..10),
+            gap_free_weight: rng.random_range(0..10),
+            min_area: 0,
+            exclusion_limits,
+        }
+    }
+
+    #[test]
+    fn parses_name_value_solutions() {
+        // Gurobi and HiGHS.
+        let solution = parse("# Objective value = 12\nr1 1\nr4 0\ny0 0\nz1_1 1.0\n");
+        assert!(solution.named["r1"]);
+        assert!(!solution.named["r4"]);
+        assert!(solution.named["z1_1"]);
+        assert!(solution.numbered.is_empty());
+
+        // SCIP, with objective coefficients after the values.
+        let solution = parse(
+            "solution status: optimal solution found\nobjective value: 12\nr1 1 \t(obj:4)\nr6 0 \t(obj:4)\n",
+        );
+        assert!(solution.named["r1"]);
+        assert!(!solution.named["r6"]);
+        assert!(!solution.named.contains_key("objective"));
+
+        // CBC, with column numbers before the names.
+        let solution = parse(
+            "Optimal - objective value 12.00000000\n      0 r1    1    -4\n      3 y1    1e-10    -4\n",
+        );
+        assert!(solution.named["r1"]);
+        assert!(!solution.named["y1"]);
+        assert_eq!(solution.named.len(), 2);
+    }
+
+    #[test]
+    fn parses_cplex_xml_solutions() {
+        let solution = parse(
+            "<?xml version = \"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <CPLEXSolution version=\"1.2\">\n <variables>\n\
+             \x20 <variable name=\"r1\" index=\"0\" value=\"1\"/>\n\
+             \x20 <variable name=\"r4\" index=\"1\" value=\"-0\"/>\n\
+             \x20 <variable name=\"y0\" index=\"3\" value=\"0.9999999\"/>\n\
+             </variables>\n</CPLEXSolution>\n",
+        );
+        assert_eq!(solution.named.len(), 3);
+        assert!(solution.named["r1"]);
+        assert!(!solution.named["r4"]);
+        assert!(solution.named["y0"]);
+    }
+
+    #[test]
+    fn parses_maxsat_solutions() {
+        let solution = parse("c comment\ns OPTIMUM FOUND\no 7\nv 1 -2\nv 3 0\n");
+        assert!(solution.named.is_empty());
+        assert!(solution.numbered[&1]);
+        assert!(!solution.numbered[&2]);
+        assert!(solution.numbered[&3]);
+
+        let solution = parse("s OPTIMUM FOUND\nv 101\n");
+        assert_eq!(solution.numbered.len(), 3);
+        assert!(!solution.numbered[&2]);
+        assert!(solution.numbered[&3]);
+
+        assert!(parse("s UNKNOWN\no 3\n").is_empty());
+    }
+
+    #[test]
+    fn at_most_accepts_exactly_the_assignments_within_the_limit() {
+        for n in 1..=5_usize {
+            for max in 0..=n {
+                // Literals alternate signs, as excluded sequences are negated
+                // retained variables.
+                let literals: Vec<i64> = (1..=n as i64)
+                    .map(|var| if var % 2 == 0 { -var } else { var })
+                    .collect();
+                let mut next_var = n + 1;
+                let clauses = at_most(&literals, max, &mut next_var);
+                let num_vars = next_var - 1;
+                assert!(num_vars - n <= (n - 1) * max);
+                for assignment in 0..1_usize << n {
+                    let true_count = literals
+                        .iter()
+                        .filter(|&&lit| {
+                            (assignment >> (lit.unsigned_abs() - 1) & 1 == 1) == (lit > 0)
+                        })
+                        .count();
+                    let satisfiable = (0..1_usize << (num_vars - n)).any(|aux| {
+                        let value = |var: usize| {
+                            if var <= n {
+                                assignment >> (var - 1) & 1 == 1
+                            } else {
+                                aux >> (var - n - 1) & 1 == 1
+                            }
+                        };
+                        clauses.iter().all(|clause| {
+                            clause
+                                .iter()
+                                .any(|&lit| value(lit.unsigned_abs() as usize) == (lit > 0))
+                        })
+                    });
+                    assert_eq!(satisfiable, true_count <= max, "n {n}, max {max}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solve_finds_the_optimum() {
+        let mut rng = Pcg64::seed_from_u64(17);
+        for limited in [false, true] {
+            for _ in 0..100 {
+                let model = random_model(&mut rng, limited);
+                let count = model.candidates.len();
+                let within_limits = |excluded: &HashSet<usize>| {
+                    model.exclusion_limits.iter().all(|(positions, max)| {
+                        positions
+                            .iter()
+                            .filter(|&&pos| excluded.contains(&model.candidates[pos]))
+                            .count()
+                            <= *max
+                    })
+                };
+                let best_area = (0..1_usize << count)
+                    .map(|mask| {
+                        (0..count)
+                            .filter(|pos| mask >> pos & 1 == 1)
+                            .map(|pos| model.candidates[pos])
+                            .collect::<HashSet<usize>>()
+                    })
+                    .filter(|excluded| within_limits(excluded))
+                    .map(|excluded| model.area(&excluded))
+                    .max()
+                    .unwrap();
+                let solved = model.solve();
+                assert!(within_limits(&solved));
+                assert_eq!(model.area(&solved), best_area);
+            }
+        }
+    }
+
+    #[test]
+    fn excluded_reads_back_written_solutions() {
+        let mut rng = Pcg64::seed_from_u64(170);
+        let path = std::env::temp_dir().join(format!("maxalign-rs-{}.sol", std::process::id()));
+        for limited in [false, true] {
+            let mut model = random_model(&mut rng, limited);
+            let solved = model.solve();
+            model.min_area = model.area(&solved);
+            model.write_solution(&path, &solved).unwrap();
+            let solution = ModelSolution::read(&path).unwrap();
+            assert_eq!(model.excluded(&solution).unwrap(), solved);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn excluded_rejects_invalid_solutions() {
+        let model = small_model();
+        assert_eq!(
+            model.excluded(&parse("r1 0\nr4 1\nr6 1\n")).unwrap(),
+            HashSet::from([1])
+        );
+        // By number, as in MaxSAT solutions.
+        assert_eq!(
+            model.excluded(&parse("v -1 2 3\n")).unwrap(),
+            HashSet::from([1])
+        );
+
+        let error = model.excluded(&parse("r1 0\nr6 1\n")).unwrap_err();
+        assert!(
+            error.to_string().contains("no value for 'r4' (variable 2)"),
+            "{error}"
+        );
+
+        let limited = ReducedModel {
+            exclusion_limits: vec![(vec![0, 1, 2], 1)],
+            ..small_model()
+        };
+        let error = limited.excluded(&parse("r1 0\nr4 0\nr6 1\n")).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("excludes 2 sequences of a limit of 1"),
+            "{error}"
+        );
+
+        // Excluding sequence 1 gives (2 + 2) * (4 + 3) = 28, excluding nothing
+        // 5 * 4 = 20.
+        let reduced = ReducedModel {
+            min_area: 28,
+            ..small_model()
+        };
+        assert!(reduced.excluded(&parse("r1 0\nr4 1\nr6 1\n")).is_ok());
+        let error = reduced.excluded(&parse("r1 1\nr4 1\nr6 1\n")).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("area of 20, below the area of 28"),
+            "{error}"
+        );
+    }
+}
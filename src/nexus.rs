@@ -0,0 +1,233 @@
+//! NEXUS file parsing utilities.
+
+use crate::error::{Error, Result};
+use crate::fasta::{SequenceData, build_sequence_data};
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// Removes `[...]` comments from NEXUS text.
+fn strip_comments(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut depth = 0usize;
+    for c in text.chars() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth > 0 => depth -= 1,
+            _ if depth == 0 => result.push(c),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Returns the value of a `KEY=value` entry in a NEXUS `FORMAT` command. Blanks
+/// may surround the `=` sign and the value may be quoted.
+fn format_value(format: &str, key: &str) -> Option<u8> {
+    let spaced = format.replace('=', " = ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    tokens.windows(3).find_map(|window| match window {
+        [name, "=", value] if name.eq_ignore_ascii_case(key) => {
+            value.trim_matches(['\'', '"']).bytes().next()
+        }
+        _ => None,
+    })
+}
+
+/// Splits a matrix line into the taxon name and the sequence data.
+fn split_taxon(line: &str) -> Option<(String, &str)> {
+    let line = line.trim_start();
+    if let Some(rest) = line.strip_prefix('\'') {
+        let mut name = String::new();
+        let mut chars = rest.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c == '\'' {
+                if chars.peek().is_some_and(|&(_, next)| next == '\'') {
+                    name.push('\'');
+                    chars.next();
+                } else {
+                    return Some((name, &rest[i + 1..]));
+                }
+            } else {
+                name.push(c);
+            }
+        }
+        None
+    } else {
+        let end = line.find(char::is_whitespace).unwrap_or(line.len());
+        Some((line[..end].to_string(), &line[end..]))
+    }
+}
+
+/// Parses the `DATA` or `CHARACTERS` block of a NEXUS file and returns the
+/// sequence data. Sequences may be interleaved. The declared gap and missing
+/// characters are converted to `-` and `?`, and match characters are replaced
+/// by the residue of the first sequence.
+pub fn parse_nexus(mut reader: impl BufRead, keep_sequence: &[String]) -> Result<SequenceData> {
+    let mut raw = Vec::new();
+    reader
+        .read_to_end(&mut raw)
+        .map_err(|e| Error::NexusParse(e.to_string()))?;
+    let text = strip_comments(&String::from_utf8_lossy(&raw));
+    let lower = text.to_ascii_lowercase();
+
+    if !lower.trim_start().starts_with("#nexus") {
+        return Err(Error::NexusParse("missing '#NEXUS' header".to_string()));
+    }
+
+    let block_start = ["begin data;", "begin characters;"]
+        .iter()
+        .filter_map(|b| lower.find(b))
+        .min()
+        .ok_or_else(|| Error::NexusParse("no DATA or CHARACTERS block found".to_string()))?;
+    let block_end = lower[block_start..]
+        .find("end;")
+        .map_or(lower.len(), |end| block_start + end);
+    let block = &text[block_start..block_end];
+    let block_lower = &lower[block_start..block_end];
+
+    let (gap, missing, matchchar) =
+        block_lower
+            .find("format")
+            .map_or((None, None, None), |start| {
+                let end = block_lower[start..]
+                    .find(';')
+                    .map_or(block.len(), |end| start + end);
+                let format = &block[start..end];
+                (
+                    format_value(format, "gap"),
+                    format_value(format, "missing"),
+                    format_value(format, "matchchar"),
+                )
+            });
+
+    let matrix_start = block_lower
+        .find("matrix")
+        .ok_or_else(|| Error::NexusParse("no MATRIX command found".to_string()))?
+        + "matrix".len();
+    let matrix = &block[matrix_start..];
+    let matrix = &matrix[..matrix.find(';').unwrap_or(matrix.len())];
+
+    let mut headers: Vec<Vec<u8>> = Vec::new();
+    let mut sequences: Vec<Vec<u8>> = Vec::new();
+    let mut sequence_index: HashMap<String, usize> = HashMap::new();
+
+    for line in matrix.lines().filter(|l| !l.trim().is_empty()) {
+        let (name, data) = split_taxon(line).ok_or_else(|| {
+            Error::NexusParse(format!("unterminated quoted taxon name in line '{line}'"))
+        })?;
+        let data: Vec<u8> = data
+            .bytes()
+            .filter(|b| !b.is_ascii_whitespace())
+            .map(|b| {
+                if Some(b) == gap {
+                    b'-'
+                } else if Some(b) == missing {
+                    b'?'
+                } else {
+                    b
+                }
+            })
+            .collect();
+        if let Some(&idx) = sequence_index.get(&name) {
+            sequences[idx].extend_from_slice(&data);
+        } else {
+            sequence_index.insert(name.clone(), sequences.len());
+            // Blanks in quoted names become underscores, so that names remain a
+            // single word like FASTA accessions.
+            headers.push(name.replace(char::is_whitespace, "_").into_bytes());
+            sequences.push(data);
+        }
+    }
+
+    if let Some(matchchar) = matchchar
+        && let Some((first, rest)) = sequences.split_first_mut()
+    {
+        for seq in rest {
+            for (pos, byte) in seq.iter_mut().enumerate() {
+                if *byte == matchchar
+                    && let Some(&reference) = first.get(pos)
+                {
+                    *byte = reference;
+                }
+            }
+        }
+    }
+
+    build_sequence_data(headers, sequences, keep_sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::GapChars;
+    use crate::output::write_nexus;
+
+    fn parse(text: &str) -> Result<SequenceData> {
+        parse_nexus(text.as_bytes(), &[])
+    }
+
+    #[test]
+    fn reads_format_values_with_blanks_and_quotes() {
+        let format = "FORMAT DATATYPE = DNA GAP = ? MISSING=N MATCHCHAR = '.'";
+        assert_eq!(format_value(format, "gap"), Some(b'?'));
+        assert_eq!(format_value(format, "missing"), Some(b'N'));
+        assert_eq!(format_value(format, "matchchar"), Some(b'.'));
+        assert_eq!(format_value(format, "symbols"), None);
+        assert_eq!(format_value("format gap=-", "gap"), Some(b'-'));
+    }
+
+    #[test]
+    fn converts_gap_missing_and_match_characters() {
+        let text = "#NEXUS
+[comment]
+BEGIN DATA;
+  DIMENSIONS NTAX=3 NCHAR=6;
+  FORMAT DATATYPE = DNA GAP = ? MISSING = N MATCHCHAR = .;
+  MATRIX
+  a        ACGT?A
+  b        ..N.T?
+  'c d'    AC??AA
+  ;
+END;
+";
+        let data = parse(text).unwrap();
+        assert_eq!(
+            data.headers,
+            [b"a".to_vec(), b"b".to_vec(), b"c_d".to_vec()]
+        );
+        assert_eq!(
+            data.sequences,
+            [b"ACGT-A".to_vec(), b"AC?TT-".to_vec(), b"AC--AA".to_vec()]
+        );
+    }
+
+    #[test]
+    fn parses_interleaved_matrices() {
+        let text =
+            "#NEXUS\nbegin characters;\nmatrix\na ACG\n'b''s' AC-\n\na TT\n'b''s' T-\n;\nend;\n";
+        let data = parse(text).unwrap();
+        assert_eq!(data.headers, [b"a".to_vec(), b"b's".to_vec()]);
+        assert_eq!(data.sequences, [b"ACGTT".to_vec(), b"AC-T-".to_vec()]);
+    }
+
+    #[test]
+    fn round_trips_through_the_writer() {
+        let headers = vec![b"seq1 description".to_vec(), b"seq-2".to_vec()];
+        let sequences = vec![b"ACGT-?".to_vec(), b"AC-TTA".to_vec()];
+        let mut written = Vec::new();
+        write_nexus(&sequences, &headers, &GapChars::default(), &mut written).unwrap();
+        assert!(String::from_utf8_lossy(&written).contains("DATATYPE=DNA"));
+        let data = parse_nexus(written.as_slice(), &[]).unwrap();
+        assert_eq!(data.headers, [b"seq1".to_vec(), b"seq-2".to_vec()]);
+        assert_eq!(data.sequences, sequences);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let missing = |text: &str| matches!(parse(text), Err(Error::NexusParse(_)));
+        assert!(missing("BEGIN DATA; MATRIX a ACGT; END;"));
+        assert!(missing("#NEXUS\nBEGIN TREES;\nEND;\n"));
+        assert!(missing("#NEXUS\nBEGIN DATA;\nDIMENSIONS NTAX=1;\nEND;\n"));
+        assert!(missing("#NEXUS\nBEGIN DATA;\nMATRIX\n'a ACGT\n;\nEND;\n"));
+    }
+}
@@ -1,242 +1,854 @@
 //! Branch-and-bound optimization algorithm.
 
-use crate::alignment::AlignmentMetrics;
-use crate::bitops::{
-    bitwise_or, bitwise_or_assign, count_bits, count_bits_union, get_set_bit_indices,
-};
-use crate::heuristic::create_working_sets;
+use crate::alignment::{AlignmentMetrics, ColumnPatterns, ExclusionBudget, GapTolerance};
+use crate::bitops::Bitset;
+use crate::heuristic::{create_working_sets, gappiest_first};
+use crate::weights::Weights;
 use log::debug;
+use std::cmp::Reverse;
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
 
 const UNDECIDED: u8 = b'X';
 const EXCLUDED: u8 = b'1';
 const NOT_EXCLUDED: u8 = b'0';
 
+/// Number of nodes between two checks of the time limit.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
 /// Result of the branch-and-bound optimization.
 pub struct BranchAndBoundResult {
     pub metrics: AlignmentMetrics,
     pub excluded: HashSet<usize>,
+    pub status: RefinementStatus,
+    /// Solutions collected by the search (see [`SolutionPool`]), sorted by
+    /// decreasing weighted area, then by increasing number of excluded
+    /// sequences, then in depth-first order. The first one is the solution
+    /// held in `metrics` and `excluded`. Empty if the search found no solution
+    /// at least as good as the starting one.
+    pub solutions: Vec<Solution>,
+}
+
+/// A solution found by the branch-and-bound search.
+#[derive(Debug, Clone)]
+pub struct Solution {
+    /// Metrics of the alignment without the excluded sequences.
+    pub metrics: AlignmentMetrics,
+    /// Indices of the excluded sequences.
+    pub excluded: HashSet<usize>,
+}
+
+/// Solutions collected by a branch-and-bound search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SolutionPool {
+    /// Only the best solution. Among solutions that reach the same area, the
+    /// one that excludes the fewest sequences is kept.
+    #[default]
+    Best,
+    /// Every solution that reaches the best area.
+    CoOptimal,
+    /// The `count` solutions with the largest areas. Nodes are pruned against
+    /// the area of the `count`-th best solution found so far instead of the
+    /// best one, starting from `min_area`, which must not exceed the area of
+    /// the `count`-th best solution (e.g., the area of the `count`-th best
+    /// solution known). A `count` of 0 is treated as 1.
+    Top { count: usize, min_area: usize },
+}
+
+/// Solutions found so far by a search, keyed by their excluded sequences.
+/// Solutions with smaller keys come first among those with the same area.
+struct Solutions<K> {
+    pool: SolutionPool,
+    /// Whether solutions that only tie with the threshold are kept.
+    ties: bool,
+    /// Area a solution must reach to be kept.
+    threshold: usize,
+    /// Best area found so far, including the starting one.
+    best_area: usize,
+    found: Vec<(usize, K, Bitset)>,
+}
+
+impl<K: Ord> Solutions<K> {
+    fn new(pool: SolutionPool, initial_best_area: usize, ties: bool) -> Self {
+        let pool = match pool {
+            SolutionPool::Top { count, min_area } => SolutionPool::Top {
+                count: count.max(1),
+                min_area,
+            },
+            pool => pool,
+        };
+        let threshold = match pool {
+            SolutionPool::Top { min_area, .. } => min_area,
+            SolutionPool::Best | SolutionPool::CoOptimal => initial_best_area,
+        };
+        Self {
+            pool,
+            ties,
+            threshold,
+            best_area: initial_best_area,
+            found: Vec::new(),
+        }
+    }
+
+    /// Returns `true` if no solution with an area of at most `bound` is kept.
+    fn prunes(&self, bound: usize) -> bool {
+        bound < self.threshold || (!self.ties && bound == self.threshold)
+    }
+
+    /// Records a solution and returns `true` if it improves on the best area.
+    /// If the same sequences were already excluded by another solution, only
+    /// the one with the largest area, then the smallest key, is kept.
+    fn insert(&mut self, area: usize, key: K, excluded: Bitset) -> bool {
+        if self.prunes(area) {
+            return false;
+        }
+        let improved = area > self.best_area;
+        self.best_area = self.best_area.max(area);
+        if !matches!(self.pool, SolutionPool::Top { .. }) && area > self.threshold {
+            self.threshold = area;
+            self.found.clear();
+        }
+        if let Some(known) = self
+            .found
+            .iter_mut()
+            .find(|(_, _, known)| *known == excluded)
+        {
+            if area > known.0 || (area == known.0 && key < known.1) {
+                known.0 = area;
+                known.1 = key;
+            }
+        } else {
+            self.found.push((area, key, excluded));
+        }
+        if let SolutionPool::Top { count, .. } = self.pool {
+            self.found
+                .sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            // Solutions beyond the count can't come back, as those that
+            // remain have larger areas or smaller keys.
+            self.found.truncate(count);
+            if self.found.len() == count {
+                self.threshold = self.threshold.max(self.found[count - 1].0);
+            }
+        }
+        improved
+    }
+
+    /// Returns the areas and excluded sequences of the solutions, sorted by
+    /// decreasing area, then by increasing number of excluded sequences, then
+    /// by key.
+    fn into_sorted(self) -> Vec<(usize, Bitset)> {
+        let mut found = self.found;
+        found.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then_with(|| a.2.count_ones().cmp(&b.2.count_ones()))
+                .then_with(|| a.1.cmp(&b.1))
+        });
+        if self.pool == SolutionPool::Best {
+            found.truncate(1);
+        }
+        found
+            .into_iter()
+            .map(|(area, _, excluded)| (area, excluded))
+            .collect()
+    }
+}
+
+/// Limits on the branch-and-bound search. When a limit is reached, the search
+/// stops and returns the best solution found so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RefinementLimits {
+    /// Maximum time spent in the refinement.
+    pub time: Option<Duration>,
+    /// Maximum number of search nodes to explore.
+    pub nodes: Option<u64>,
+}
+
+/// Limit that stopped a branch-and-bound search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchLimit {
+    Time,
+    Nodes,
+}
+
+impl std::fmt::Display for SearchLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time => write!(f, "time"),
+            Self::Nodes => write!(f, "node"),
+        }
+    }
+}
+
+/// Outcome of a branch-and-bound search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefinementStatus {
+    /// Limit that stopped the search, if it didn't finish.
+    pub limit_reached: Option<SearchLimit>,
+    /// Number of search nodes explored.
+    pub nodes: u64,
+    /// Weighted area of the best solution known, including the starting one.
+    pub best_area: usize,
+    /// Upper bound on the weighted area of any solution: the best bound among
+    /// the nodes left unexplored, or `best_area` if the search finished.
+    pub upper_bound: usize,
+}
+
+impl RefinementStatus {
+    /// Returns `true` if the best solution is proven optimal.
+    #[must_use]
+    pub const fn is_optimal(&self) -> bool {
+        self.upper_bound <= self.best_area
+    }
+
+    /// Returns the remaining optimality gap, i.e., how much the upper bound
+    /// exceeds the best area, relative to the best area. The gap is infinite
+    /// if no area was found but the bound is positive.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn optimality_gap(&self) -> f64 {
+        if self.is_optimal() {
+            0.0
+        } else if self.best_area == 0 {
+            f64::INFINITY
+        } else {
+            (self.upper_bound - self.best_area) as f64 / self.best_area as f64
+        }
+    }
+}
+
+/// Counts the nodes of a search and tells it when to stop.
+struct Limiter {
+    limits: RefinementLimits,
+    start: Instant,
+    nodes: AtomicU64,
+    reached: OnceLock<SearchLimit>,
+    /// Best bound among the nodes left unexplored after stopping.
+    open_bound: AtomicUsize,
+}
+
+impl Limiter {
+    fn new(limits: RefinementLimits) -> Self {
+        Self {
+            limits,
+            start: Instant::now(),
+            nodes: AtomicU64::new(0),
+            reached: OnceLock::new(),
+            open_bound: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a node and returns `true` if the search must stop before
+    /// exploring it.
+    fn stop(&self) -> bool {
+        if self.reached.get().is_some() {
+            return true;
+        }
+        let nodes = self.nodes.fetch_add(1, Ordering::Relaxed);
+        if self.limits.nodes.is_some_and(|limit| nodes >= limit) {
+            let _ = self.reached.set(SearchLimit::Nodes);
+            return true;
+        }
+        if nodes.is_multiple_of(TIME_CHECK_INTERVAL)
+            && self
+                .limits
+                .time
+                .is_some_and(|limit| self.start.elapsed() >= limit)
+        {
+            let _ = self.reached.set(SearchLimit::Time);
+            return true;
+        }
+        false
+    }
+
+    /// Records the bound of a node that is left unexplored.
+    fn leave_open(&self, bound: usize) {
+        self.open_bound.fetch_max(bound, Ordering::Relaxed);
+    }
+
+    fn status(&self, best_area: usize) -> RefinementStatus {
+        let limit_reached = self.reached.get().copied();
+        RefinementStatus {
+            limit_reached,
+            nodes: self
+                .nodes
+                .load(Ordering::Relaxed)
+                .min(self.limits.nodes.unwrap_or(u64::MAX)),
+            best_area,
+            upper_bound: if limit_reached.is_some() {
+                best_area.max(self.open_bound.load(Ordering::Relaxed))
+            } else {
+                best_area
+            },
+        }
+    }
 }
 
-/// Runs the branch-and-bound algorithm to find the optimal solution.
+/// Runs the branch-and-bound algorithm to find the optimal solution, i.e., the
+/// one that maximizes the weighted alignment area. `metrics` holds the best
+/// solution known so far (usually the heuristic one). If a limit is reached,
+/// the best solution found so far is returned, and the status of the result
+/// holds the remaining optimality gap. Only solutions whose excluded
+/// sequences fit in `budget` are considered. The solutions listed in the
+/// result are collected according to `pool`.
 #[must_use]
+#[allow(clippy::too_many_arguments)]
 pub fn run_branch_and_bound(
-    orig_sets: &[Vec<u8>],
-    orig_gaps: &[Vec<u8>],
+    orig_sets: &[Bitset],
+    orig_gaps: &[Bitset],
     metrics: &AlignmentMetrics,
     keep_pattern: &[bool],
-    num_sequences: usize,
+    weights: &Weights,
+    column_weights: &Weights,
+    limits: RefinementLimits,
+    budget: &ExclusionBudget,
+    pool: SolutionPool,
 ) -> BranchAndBoundResult {
+    let limiter = Limiter::new(limits);
+    let seed_area = match pool {
+        SolutionPool::Top { min_area, .. } => min_area,
+        SolutionPool::Best | SolutionPool::CoOptimal => metrics.weighted_area,
+    };
     let kept_gaps = keep_pattern.iter().filter(|&&b| b).count();
     let gap_free_columns = metrics.alignment_length - orig_sets.len() - kept_gaps;
-
-    let bb_state_excluded = HashSet::new();
-    let bb_state_translation: Vec<usize> = (0..num_sequences).collect();
-
-    let (working_sets, working_gaps) = create_working_sets(
+    let problem = reduce_problem(
         orig_sets,
         orig_gaps,
-        &bb_state_excluded,
-        &bb_state_translation,
-        num_sequences,
-    );
-
-    let mut current_sets = working_sets;
-    let mut current_gaps = working_gaps;
-
-    crate::alignment::congruent_set_joining(
-        &mut current_sets,
-        &mut current_gaps,
-        metrics.alignment_area,
-        num_sequences,
-        metrics.alignment_length,
-    );
-
-    crate::alignment::subset_joining(&current_sets, &mut current_gaps);
-
-    let gap_columns = crate::alignment::set_elimination(
-        &mut current_sets,
-        &mut current_gaps,
-        metrics.alignment_area,
-        num_sequences,
-        metrics.alignment_length,
-        gap_free_columns,
+        seed_area,
+        keep_pattern,
+        weights,
+        column_weights,
     );
+    let gap_free_weight = problem.gap_free_weight;
 
     let dislikes = find_dislikes(
-        &current_sets,
-        metrics.alignment_area,
-        num_sequences,
-        metrics.alignment_length,
-        gap_columns,
+        &problem.sets,
+        seed_area,
+        weights,
+        column_weights,
+        problem.gap_weight,
     );
 
     let (ordered_sets, ordered_gaps, ordered_dislikes) =
-        reorder_sets_for_search(&current_sets, &current_gaps, &dislikes);
+        reorder_sets_for_search(&problem.sets, &problem.gaps, &dislikes);
 
     let (best_area, solutions) = branch_and_bound_search(
         &ordered_sets,
         &ordered_gaps,
         &ordered_dislikes,
-        metrics.alignment_area,
+        metrics.weighted_area,
+        gap_free_weight,
+        weights,
+        column_weights,
+        &limiter,
+        budget,
+        pool,
+    );
+
+    extract_solutions(
+        solutions,
+        limiter.status(best_area),
+        orig_sets,
+        orig_gaps,
         gap_free_columns,
+        gap_free_weight,
+        weights,
+        column_weights,
+        metrics,
+    )
+}
+
+/// Sets left after removing those that can't be part of a solution that
+/// reaches a given area.
+#[derive(Debug, Clone)]
+pub struct ReducedProblem {
+    /// Sets of sequences whose exclusion makes columns gap-free, one per gap
+    /// pattern.
+    pub sets: Vec<Bitset>,
+    /// Columns made gap-free by excluding each set, including those of its
+    /// subsets.
+    pub gaps: Vec<Bitset>,
+    /// Total weight of the columns that are gap-free in the input alignment,
+    /// not counting those in which a kept sequence has a gap.
+    pub gap_free_weight: usize,
+    /// Total weight of the columns that can't be gap-free in such a solution.
+    pub gap_weight: usize,
+}
+
+/// Reduces the problem to the sets that can be part of a solution that reaches
+/// `area`: identical sets are joined, sets too large to reach the area are
+/// removed ([`congruent_set_joining`](crate::alignment::congruent_set_joining)
+/// and [`set_elimination`](crate::alignment::set_elimination)), and the gaps of
+/// each set are joined with those of its subsets
+/// ([`subset_joining`](crate::alignment::subset_joining)).
+#[must_use]
+pub fn reduce_problem(
+    orig_sets: &[Bitset],
+    orig_gaps: &[Bitset],
+    area: usize,
+    keep_pattern: &[bool],
+    weights: &Weights,
+    column_weights: &Weights,
+) -> ReducedProblem {
+    let num_sequences = weights.len();
+    let gap_free_weight = column_weights.total()
+        - keep_pattern
+            .iter()
+            .enumerate()
+            .filter(|&(_, &kept)| kept)
+            .map(|(col, _)| column_weights.get(col))
+            .sum::<usize>()
+        - orig_gaps
+            .iter()
+            .map(|gap| column_weights.of_set(gap))
+            .sum::<usize>();
+
+    let bb_state_excluded = HashSet::new();
+    let bb_state_translation: Vec<usize> = (0..num_sequences).collect();
+
+    let (mut sets, mut gaps) = create_working_sets(
+        orig_sets,
+        orig_gaps,
+        &bb_state_excluded,
+        &bb_state_translation,
         num_sequences,
     );
 
-    extract_best_solution(solutions, best_area, num_sequences, metrics)
+    crate::alignment::congruent_set_joining(&mut sets, &mut gaps, area, weights, column_weights);
+
+    crate::alignment::subset_joining(&sets, &mut gaps);
+
+    let gap_weight = crate::alignment::set_elimination(
+        &mut sets,
+        &mut gaps,
+        area,
+        weights,
+        column_weights,
+        gap_free_weight,
+    );
+
+    ReducedProblem {
+        sets,
+        gaps,
+        gap_free_weight,
+        gap_weight,
+    }
 }
 
-/// Performs the actual branch-and-bound search.
+/// Number of branchings from the root within which the not-excluded branch is
+/// handed to the thread pool as a separate task instead of the local stack.
+const SPLIT_DEPTH: usize = 16;
+
+/// Performs the actual branch-and-bound search. `gap_free_columns` is the total
+/// weight of the gap-free columns. Returns the best area and the solutions
+/// collected according to `pool`, sorted as in [`BranchAndBoundResult`]. The
+/// best solution is always collected with all its ties.
+///
+/// If the current rayon thread pool has more than one thread, subtrees near the
+/// root are searched in parallel, sharing the pruning threshold so that every
+/// thread prunes against it. As ties are never pruned, the same solutions are
+/// found regardless of the number of threads.
+#[allow(clippy::too_many_arguments)]
 fn branch_and_bound_search(
-    ordered_sets: &[Vec<u8>],
-    ordered_gaps: &[Vec<u8>],
+    ordered_sets: &[Bitset],
+    ordered_gaps: &[Bitset],
     ordered_dislikes: &[Vec<usize>],
     initial_best_area: usize,
     gap_free_columns: usize,
-    num_sequences: usize,
-) -> (usize, Vec<Vec<u8>>) {
+    weights: &Weights,
+    column_weights: &Weights,
+    limiter: &Limiter,
+    budget: &ExclusionBudget,
+    pool: SolutionPool,
+) -> (usize, Vec<(usize, Bitset)>) {
+    let num_sequences = weights.len();
     let sets_count = ordered_sets.len();
-    let gap_vec_len = ordered_gaps.first().map_or(1, Vec::len);
+    let gap_width = ordered_gaps.first().map_or(0, Bitset::width);
 
-    let mut suffix_unions = vec![vec![0u8; gap_vec_len]; sets_count + 1];
+    let mut suffix_unions = vec![Bitset::new(gap_width); sets_count + 1];
     for i in (0..sets_count).rev() {
-        suffix_unions[i] = suffix_unions[i + 1].clone();
-        bitwise_or_assign(&mut suffix_unions[i], &ordered_gaps[i]);
+        suffix_unions[i] = suffix_unions[i + 1].union(&ordered_gaps[i]);
     }
 
-    let mut stack = vec![(
-        vec![UNDECIDED; sets_count],
-        0usize,
-        vec![0u8; num_sequences.div_ceil(8)],
-        vec![0u8; gap_vec_len],
-    )];
+    let solutions = Solutions::new(pool, initial_best_area, true);
+    let search = Search {
+        ordered_sets,
+        ordered_gaps,
+        ordered_dislikes,
+        suffix_unions,
+        gap_free_columns,
+        weights,
+        column_weights,
+        limiter,
+        budget,
+        parallel: rayon::current_num_threads() > 1,
+        threshold: AtomicUsize::new(solutions.threshold),
+        solutions: Mutex::new(solutions),
+    };
+    let root = Node {
+        decisions: vec![UNDECIDED; sets_count],
+        pointer: 0,
+        union_sets: Bitset::new(num_sequences),
+        union_gaps: Bitset::new(gap_width),
+        splits: 0,
+    };
+    rayon::scope(|scope| search.explore(root, scope));
 
-    let mut solutions = Vec::new();
-    let mut best_area = initial_best_area;
+    let solutions = search
+        .solutions
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    (solutions.best_area, solutions.into_sorted())
+}
 
-    let union_sets_count_bits = |union_sets: &[u8]| count_bits(union_sets);
+/// A node of the branch-and-bound search tree.
+struct Node {
+    decisions: Vec<u8>,
+    pointer: usize,
+    union_sets: Bitset,
+    union_gaps: Bitset,
+    /// Number of branchings between the root and this node.
+    splits: usize,
+}
 
-    while let Some((mut decisions, mut pointer, mut union_sets, mut union_gaps)) = stack.pop() {
-        loop {
-            let test_union_gaps_count = count_bits_union(&union_gaps, &suffix_unions[pointer]);
-            let test_score = (gap_free_columns + test_union_gaps_count)
-                * (num_sequences - union_sets_count_bits(&union_sets));
+/// State of a branch-and-bound search shared between threads.
+#[allow(clippy::type_complexity)]
+struct Search<'a> {
+    ordered_sets: &'a [Bitset],
+    ordered_gaps: &'a [Bitset],
+    ordered_dislikes: &'a [Vec<usize>],
+    suffix_unions: Vec<Bitset>,
+    gap_free_columns: usize,
+    weights: &'a Weights,
+    column_weights: &'a Weights,
+    limiter: &'a Limiter,
+    budget: &'a ExclusionBudget,
+    parallel: bool,
+    /// Area below which nodes are pruned, read without locking.
+    threshold: AtomicUsize,
+    /// Solutions found so far, keyed by their decisions. Excluded branches are
+    /// explored first, so depth-first order is the reverse order of the
+    /// decisions.
+    solutions: Mutex<Solutions<Reverse<Vec<u8>>>>,
+}
 
-            if test_score < best_area {
-                break;
-            }
+impl<'a> Search<'a> {
+    /// Searches the subtree of `node` depth-first, handing the not-excluded
+    /// branches close to the root to other threads.
+    fn explore<'scope>(&'scope self, node: Node, scope: &rayon::Scope<'scope>)
+    where
+        'a: 'scope,
+    {
+        let sets_count = self.ordered_sets.len();
+        let total_weight = self.weights.total();
+        let mut stack = vec![node];
 
-            while pointer < sets_count && decisions[pointer] != UNDECIDED {
-                pointer += 1;
-            }
+        while let Some(Node {
+            mut decisions,
+            mut pointer,
+            mut union_sets,
+            mut union_gaps,
+            mut splits,
+        }) = stack.pop()
+        {
+            loop {
+                let test_union_gaps_count = self
+                    .column_weights
+                    .of_union(&union_gaps, &self.suffix_unions[pointer]);
+                let test_score = (self.gap_free_columns + test_union_gaps_count)
+                    * (total_weight - self.weights.of_set(&union_sets));
 
-            if pointer < sets_count {
-                let set = &ordered_sets[pointer];
-                let union_and_set = bitwise_or(&union_sets, set);
+                if test_score < self.threshold.load(Ordering::Relaxed) {
+                    break;
+                }
+                if self.limiter.stop() {
+                    self.limiter.leave_open(test_score);
+                    break;
+                }
 
-                if union_and_set == union_sets {
-                    bitwise_or_assign(&mut union_gaps, &ordered_gaps[pointer]);
-                    decisions[pointer] = EXCLUDED;
+                while pointer < sets_count && decisions[pointer] != UNDECIDED {
                     pointer += 1;
-                    continue;
                 }
 
-                let mut decisions_not_excluded = decisions.clone();
-                decisions_not_excluded[pointer] = NOT_EXCLUDED;
-                stack.push((
-                    decisions_not_excluded,
-                    pointer + 1,
-                    union_sets.clone(),
-                    union_gaps.clone(),
-                ));
-
-                decisions[pointer] = EXCLUDED;
-                union_sets = union_and_set;
-                bitwise_or_assign(&mut union_gaps, &ordered_gaps[pointer]);
-
-                for &bad in &ordered_dislikes[pointer] {
-                    if bad > pointer {
-                        decisions[bad] = NOT_EXCLUDED;
+                if pointer < sets_count {
+                    let set = &self.ordered_sets[pointer];
+
+                    if set.is_subset(&union_sets) {
+                        union_gaps.union_with(&self.ordered_gaps[pointer]);
+                        decisions[pointer] = EXCLUDED;
+                        pointer += 1;
+                        continue;
+                    }
+
+                    if !self.budget.is_unlimited() && !self.budget.admits_union(&union_sets, set) {
+                        decisions[pointer] = NOT_EXCLUDED;
+                        pointer += 1;
+                        continue;
+                    }
+
+                    let mut decisions_not_excluded = decisions.clone();
+                    decisions_not_excluded[pointer] = NOT_EXCLUDED;
+                    splits += 1;
+                    let not_excluded = Node {
+                        decisions: decisions_not_excluded,
+                        pointer: pointer + 1,
+                        union_sets: union_sets.clone(),
+                        union_gaps: union_gaps.clone(),
+                        splits,
+                    };
+                    if self.parallel && splits <= SPLIT_DEPTH {
+                        scope.spawn(move |scope| self.explore(not_excluded, scope));
+                    } else {
+                        stack.push(not_excluded);
+                    }
+
+                    decisions[pointer] = EXCLUDED;
+                    union_sets.union_with(set);
+                    union_gaps.union_with(&self.ordered_gaps[pointer]);
+
+                    for &bad in &self.ordered_dislikes[pointer] {
+                        if bad > pointer {
+                            decisions[bad] = NOT_EXCLUDED;
+                        }
                     }
+                    pointer += 1;
+                    continue;
                 }
-                pointer += 1;
-                continue;
-            }
 
-            let score = (gap_free_columns + count_bits(&union_gaps))
-                * (num_sequences - count_bits(&union_sets));
-            if score > best_area {
-                best_area = score;
-                solutions = vec![union_sets.clone()];
-                debug!(
-                    "Refinement algorithm improved the alignment: the area increased to {} with {} sequences",
-                    best_area,
-                    num_sequences - count_bits(&union_sets)
-                );
-            } else if score == best_area {
-                solutions.push(union_sets.clone());
+                let score = (self.gap_free_columns + self.column_weights.of_set(&union_gaps))
+                    * (total_weight - self.weights.of_set(&union_sets));
+                self.record(score, decisions, union_sets);
+                break;
             }
-            break;
         }
     }
 
-    (best_area, solutions)
+    /// Records a solution if it reaches the pruning threshold.
+    fn record(&self, score: usize, decisions: Vec<u8>, union_sets: Bitset) {
+        if score < self.threshold.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut solutions = self
+            .solutions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let retained = self.weights.len() - union_sets.count_ones();
+        if solutions.insert(score, Reverse(decisions), union_sets) {
+            debug!(
+                "Refinement algorithm improved the alignment: the area increased to {score} with {retained} sequences"
+            );
+        }
+        self.threshold.store(solutions.threshold, Ordering::Relaxed);
+    }
 }
 
-fn extract_best_solution(
-    solutions: Vec<Vec<u8>>,
-    best_area: usize,
-    num_sequences: usize,
+/// Runs the branch-and-bound algorithm when columns may contain some gaps (see
+/// [`GapTolerance`]). Since a column can count without excluding all of its
+/// gapped sequences, the search branches on individual sequences, from the most
+/// to the least gapped. Each node is bounded by the weight of the sequences not
+/// yet excluded times the weight of the columns that can still count given the
+/// gaps of the sequences already retained. Sequences in `keep` and sequences
+/// without gaps are always retained, as excluding them can't increase the area.
+/// The excluded sequences always fit in `budget`. Solutions are collected
+/// according to `pool`; nodes are visited in depth-first order.
+#[must_use]
+#[allow(clippy::too_many_arguments)]
+pub fn run_tolerant_branch_and_bound(
+    patterns: &ColumnPatterns,
     metrics: &AlignmentMetrics,
+    keep: &HashSet<usize>,
+    weights: &Weights,
+    tolerance: GapTolerance,
+    limits: RefinementLimits,
+    budget: &ExclusionBudget,
+    pool: SolutionPool,
 ) -> BranchAndBoundResult {
-    if let Some(best_solution) = solutions.into_iter().min_by_key(|s| count_bits(s)) {
-        let excluded_indices = get_set_bit_indices(&best_solution, num_sequences);
-        let excluded: HashSet<usize> = excluded_indices.into_iter().collect();
+    let limiter = Limiter::new(limits);
+    let num_sequences = weights.len();
+    let total_weight = weights.total();
+    let rank = gappiest_first(patterns, weights);
+
+    let mut gapped = Bitset::new(num_sequences);
+    for set in &patterns.sets {
+        gapped.union_with(set);
+    }
+    let mut order: Vec<usize> = gapped.ones().filter(|idx| !keep.contains(idx)).collect();
+    order.sort_by_key(|&idx| rank[idx]);
+
+    let undecided = Bitset::from_indices(num_sequences, order.iter().copied());
+    let retained = Bitset::from_indices(
+        num_sequences,
+        (0..num_sequences).filter(|&idx| !undecided.contains(idx)),
+    );
 
-        let remaining_seqs = num_sequences - excluded.len();
-        if remaining_seqs > 0 {
-            return BranchAndBoundResult {
+    let mut stack = vec![(0usize, Bitset::new(num_sequences), retained)];
+    let mut solutions = Solutions::new(pool, metrics.weighted_area, pool != SolutionPool::Best);
+    let mut visited = 0usize;
+
+    while let Some((depth, excluded, retained)) = stack.pop() {
+        let remaining = num_sequences - excluded.count_ones();
+        let available_weight = total_weight - weights.of_set(&excluded);
+        let allowed = tolerance.allowed_gaps(remaining);
+        let bound_weight = patterns.gap_free_weight
+            + patterns
+                .sets
+                .iter()
+                .zip(&patterns.weights)
+                .filter(|(set, _)| set.count_intersection(&retained) <= allowed)
+                .map(|(_, &weight)| weight)
+                .sum::<usize>();
+        if solutions.prunes(available_weight * bound_weight) {
+            continue;
+        }
+        if limiter.stop() {
+            limiter.leave_open(available_weight * bound_weight);
+            continue;
+        }
+
+        let (_, column_weight) = patterns.counted_columns(&excluded, remaining, tolerance);
+        let area = available_weight * column_weight;
+        if !solutions.prunes(area) && solutions.insert(area, visited, excluded.clone()) {
+            debug!(
+                "Refinement algorithm improved the alignment: the area increased to {area} with {remaining} sequences"
+            );
+        }
+        visited += 1;
+
+        let Some(&idx) = order.get(depth) else {
+            continue;
+        };
+        let mut with_retained = retained.clone();
+        with_retained.insert(idx);
+        stack.push((depth + 1, excluded.clone(), with_retained));
+        if remaining > 1 {
+            let mut with_excluded = excluded;
+            with_excluded.insert(idx);
+            if budget.admits(&with_excluded) {
+                stack.push((depth + 1, with_excluded, retained));
+            }
+        }
+    }
+
+    let status = limiter.status(solutions.best_area);
+    let solutions: Vec<Solution> = solutions
+        .into_sorted()
+        .into_iter()
+        .map(|(area, excluded)| {
+            let remaining = num_sequences - excluded.count_ones();
+            let (columns, _) = patterns.counted_columns(&excluded, remaining, tolerance);
+            Solution {
                 metrics: AlignmentMetrics::new(
-                    remaining_seqs,
-                    best_area / remaining_seqs,
-                    best_area,
+                    remaining,
+                    columns,
+                    columns * remaining,
                     metrics.alignment_length,
-                ),
-                excluded,
-            };
-        }
+                )
+                .with_weighted_area(area),
+                excluded: excluded.ones().collect(),
+            }
+        })
+        .collect();
+    let (metrics, excluded) = solutions.first().map_or_else(
+        || (metrics.clone(), HashSet::new()),
+        |best| (best.metrics.clone(), best.excluded.clone()),
+    );
+    BranchAndBoundResult {
+        metrics,
+        excluded,
+        status,
+        solutions,
     }
+}
+
+/// Builds the result from the solutions collected by the search, the first of
+/// which is the best one. The gap-free columns of a solution are the initially
+/// gap-free ones plus those whose gap set is contained in the excluded set.
+/// Solutions that exclude every sequence are dropped.
+#[allow(clippy::too_many_arguments)]
+fn extract_solutions(
+    solutions: Vec<(usize, Bitset)>,
+    status: RefinementStatus,
+    orig_sets: &[Bitset],
+    orig_gaps: &[Bitset],
+    gap_free_columns: usize,
+    gap_free_weight: usize,
+    weights: &Weights,
+    column_weights: &Weights,
+    metrics: &AlignmentMetrics,
+) -> BranchAndBoundResult {
+    let solutions: Vec<Solution> = solutions
+        .into_iter()
+        .filter_map(|(_, excluded)| {
+            let remaining_seqs = weights.len() - excluded.count_ones();
+            (remaining_seqs > 0).then(|| {
+                let (columns, column_weight) = orig_sets
+                    .iter()
+                    .zip(orig_gaps)
+                    .filter(|(set, _)| set.is_subset(&excluded))
+                    .fold(
+                        (gap_free_columns, gap_free_weight),
+                        |(columns, weight), (_, gap)| {
+                            (columns + 1, weight + column_weights.of_set(gap))
+                        },
+                    );
+                Solution {
+                    metrics: AlignmentMetrics::new(
+                        remaining_seqs,
+                        columns,
+                        columns * remaining_seqs,
+                        metrics.alignment_length,
+                    )
+                    .with_weighted_area(
+                        (weights.total() - weights.of_set(&excluded)) * column_weight,
+                    ),
+                    excluded: excluded.ones().collect(),
+                }
+            })
+        })
+        .collect();
 
+    let (metrics, excluded) = solutions.first().map_or_else(
+        || (metrics.clone(), HashSet::new()),
+        |best| (best.metrics.clone(), best.excluded.clone()),
+    );
     BranchAndBoundResult {
-        metrics: metrics.clone(),
-        excluded: HashSet::new(),
+        metrics,
+        excluded,
+        status,
+        solutions,
     }
 }
 
 /// Finds pairs of sets that "dislike" each other (one is subset of other, or
 /// their union would be too large to improve alignment area).
 fn find_dislikes(
-    sets: &[Vec<u8>],
+    sets: &[Bitset],
     alignment_area: usize,
-    sequence_count: usize,
-    alignment_length: usize,
+    weights: &Weights,
+    column_weights: &Weights,
     gap_columns: usize,
 ) -> Vec<Vec<usize>> {
+    let total_weight = weights.total();
+    let total_column_weight = column_weights.total();
     let mut dislikes = vec![Vec::new(); sets.len()];
     let sets_count = sets.len();
     for i in 0..sets_count {
         let set_i = &sets[i];
-        let set_i_bits = count_bits(set_i);
+        let set_i_bits = set_i.count_ones();
         for j in i + 1..sets_count {
             let set_j = &sets[j];
-            let union_size = count_bits_union(set_i, set_j);
-            if union_size == set_i_bits || union_size == count_bits(set_j) {
+            let union_size = set_i.count_union(set_j);
+            if union_size == set_i_bits || union_size == set_j.count_ones() {
                 dislikes[i].push(j);
                 dislikes[j].push(i);
                 continue;
             }
 
-            if alignment_area > (alignment_length - gap_columns) * (sequence_count - union_size) {
+            if alignment_area
+                > (total_column_weight - gap_columns)
+                    * (total_weight - weights.of_union(set_i, set_j))
+            {
                 dislikes[i].push(j);
                 dislikes[j].push(i);
             }
@@ -247,22 +859,22 @@ fn find_dislikes(
 
 #[allow(clippy::type_complexity)]
 fn reorder_sets_for_search(
-    sets: &[Vec<u8>],
-    gaps: &[Vec<u8>],
+    sets: &[Bitset],
+    gaps: &[Bitset],
     dislikes: &[Vec<usize>],
-) -> (Vec<Vec<u8>>, Vec<Vec<u8>>, Vec<Vec<usize>>) {
+) -> (Vec<Bitset>, Vec<Bitset>, Vec<Vec<usize>>) {
     let mut indices: Vec<usize> = (0..sets.len()).collect();
 
     indices.sort_by(|&a, &b| {
         dislikes[b]
             .len()
             .cmp(&dislikes[a].len())
-            .then_with(|| count_bits(&sets[b]).cmp(&count_bits(&sets[a])))
-            .then_with(|| count_bits(&gaps[b]).cmp(&count_bits(&gaps[a])))
+            .then_with(|| sets[b].count_ones().cmp(&sets[a].count_ones()))
+            .then_with(|| gaps[b].count_ones().cmp(&gaps[a].count_ones()))
     });
 
-    let ordered_sets: Vec<Vec<u8>> = indices.iter().map(|&idx| sets[idx].clone()).collect();
-    let ordered_gaps: Vec<Vec<u8>> = indices.iter().map(|&idx| gaps[idx].clone()).collect();
+    let ordered_sets: Vec<Bitset> = indices.iter().map(|&idx| sets[idx].clone()).collect();
+    let ordered_gaps: Vec<Bitset> = indices.iter().map(|&idx| gaps[idx].clone()).collect();
 
     let ordered_dislikes: Vec<Vec<usize>> = indices
         .iter()
@@ -276,3 +888,207 @@ fn reorder_sets_for_search(
 
     (ordered_sets, ordered_gaps, ordered_dislikes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::create_sets;
+    use rand::{Rng, SeedableRng};
+    use rand_pcg::Pcg64;
+
+    /// A random instance: gap matrix, kept sequences, sequence weights and
+    /// column weights. Some weights are zero, and some sequences repeat the
+    /// gaps of an earlier one.
+    fn random_instance(rng: &mut Pcg64) -> (Vec<Vec<bool>>, HashSet<usize>, Weights, Weights) {
+        let num_seqs = rng.random_range(1..13);
+        let length = rng.random_range(1..30);
+        let mut gap_matrix: Vec<Vec<bool>> = Vec::with_capacity(num_seqs);
+        for _ in 0..num_seqs {
+            if !gap_matrix.is_empty() && rng.random_bool(0.2) {
+                let copied = rng.random_range(0..gap_matrix.len());
+                gap_matrix.push(gap_matrix[copied].clone());
+            } else {
+                let density = rng.random_range(0.0..0.4);
+                gap_matrix.push((0..length).map(|_| rng.random_bool(density)).collect());
+            }
+        }
+        let keep = (0..num_seqs).filter(|_| rng.random_bool(0.1)).collect();
+        let mut random_weights = |count: usize| {
+            if rng.random_bool(0.5) {
+                Weights::uniform(count)
+            } else {
+                let weights: Vec<f64> = (0..count)
+                    .map(|_| {
+                        if rng.random_bool(0.1) {
+                            0.0
+                        } else {
+                            rng.random_range(0.0..2.0)
+                        }
+                    })
+                    .collect();
+                Weights::new(&weights)
+            }
+        };
+        let weights = random_weights(num_seqs);
+        let column_weights = random_weights(length);
+        (gap_matrix, keep, weights, column_weights)
+    }
+
+    /// Weighted area of the alignment without the `excluded` sequences.
+    fn weighted_area(
+        gap_matrix: &[Vec<bool>],
+        excluded: &HashSet<usize>,
+        weights: &Weights,
+        column_weights: &Weights,
+    ) -> usize {
+        let retained_weight: usize = (0..gap_matrix.len())
+            .filter(|idx| !excluded.contains(idx))
+            .map(|idx| weights.get(idx))
+            .sum();
+        let column_weight: usize = (0..column_weights.len())
+            .filter(|&col| {
+                gap_matrix
+                    .iter()
+                    .enumerate()
+                    .all(|(idx, row)| !row[col] || excluded.contains(&idx))
+            })
+            .map(|col| column_weights.get(col))
+            .sum();
+        retained_weight * column_weight
+    }
+
+    /// The largest weighted area over all exclusions that retain a sequence and
+    /// no kept sequence, and the fewest sequences excluded to reach it.
+    fn brute_force_optimum(
+        gap_matrix: &[Vec<bool>],
+        keep: &HashSet<usize>,
+        weights: &Weights,
+        column_weights: &Weights,
+    ) -> (usize, usize) {
+        let num_seqs = gap_matrix.len();
+        (0..(1_usize << num_seqs) - 1)
+            .map(|mask| {
+                (0..num_seqs)
+                    .filter(|idx| mask >> idx & 1 == 1)
+                    .collect::<HashSet<usize>>()
+            })
+            .filter(|excluded| excluded.is_disjoint(keep))
+            .map(|excluded| {
+                (
+                    weighted_area(gap_matrix, &excluded, weights, column_weights),
+                    Reverse(excluded.len()),
+                )
+            })
+            .max()
+            .map(|(area, Reverse(count))| (area, count))
+            .unwrap_or_default()
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn branch_and_bound(
+        gap_matrix: &[Vec<bool>],
+        keep: &HashSet<usize>,
+        weights: &Weights,
+        column_weights: &Weights,
+        pool: SolutionPool,
+    ) -> (usize, Vec<usize>, Vec<(usize, Vec<usize>)>) {
+        let num_seqs = gap_matrix.len();
+        let length = column_weights.len();
+        let (sets, gaps, keep_pattern) = create_sets(gap_matrix, keep, length);
+        let gap_free_columns = length - sets.len() - keep_pattern.iter().filter(|&&b| b).count();
+        let metrics = AlignmentMetrics::new(
+            num_seqs,
+            gap_free_columns,
+            gap_free_columns * num_seqs,
+            length,
+        )
+        .with_weighted_area(weighted_area(
+            gap_matrix,
+            &HashSet::new(),
+            weights,
+            column_weights,
+        ));
+        let result = run_branch_and_bound(
+            &sets,
+            &gaps,
+            &metrics,
+            &keep_pattern,
+            weights,
+            column_weights,
+            RefinementLimits::default(),
+            &ExclusionBudget::default(),
+            pool,
+        );
+        let sorted = |excluded: &HashSet<usize>| {
+            let mut excluded: Vec<usize> = excluded.iter().copied().collect();
+            excluded.sort_unstable();
+            excluded
+        };
+        (
+            result.metrics.weighted_area,
+            sorted(&result.excluded),
+            result
+                .solutions
+                .iter()
+                .map(|solution| (solution.metrics.weighted_area, sorted(&solution.excluded)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn branch_and_bound_finds_the_optimum_with_any_number_of_threads() {
+        let pools: Vec<rayon::ThreadPool> = [1, 3]
+            .into_iter()
+            .map(|threads| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+        let mut rng = Pcg64::seed_from_u64(11);
+        for _ in 0..60 {
+            let (gap_matrix, keep, weights, column_weights) = random_instance(&mut rng);
+            let (best_area, fewest_excluded) =
+                brute_force_optimum(&gap_matrix, &keep, &weights, &column_weights);
+            for solution_pool in [
+                SolutionPool::Best,
+                SolutionPool::CoOptimal,
+                SolutionPool::Top {
+                    count: 4,
+                    min_area: 0,
+                },
+            ] {
+                let results: Vec<_> = pools
+                    .iter()
+                    .map(|pool| {
+                        pool.install(|| {
+                            branch_and_bound(
+                                &gap_matrix,
+                                &keep,
+                                &weights,
+                                &column_weights,
+                                solution_pool,
+                            )
+                        })
+                    })
+                    .collect();
+                let (area, excluded, _) = &results[0];
+                assert_eq!(*area, best_area, "{solution_pool:?}");
+                assert_eq!(
+                    weighted_area(
+                        &gap_matrix,
+                        &excluded.iter().copied().collect(),
+                        &weights,
+                        &column_weights
+                    ),
+                    best_area
+                );
+                if solution_pool == SolutionPool::Best {
+                    assert_eq!(excluded.len(), fewest_excluded);
+                }
+                assert!(results.iter().all(|result| result == &results[0]));
+            }
+        }
+    }
+}
@@ -1,17 +1,59 @@
-//! Output utilities for writing FASTA files and header lists.
+//! Output utilities for writing alignment files, header lists and solution
+//! listings.
 
+use crate::a3m::to_a3m;
+use crate::alignment::GapChars;
+use crate::compression::{Compression, create_file};
 use crate::error::{Error, Result};
 use crate::fasta::get_record_accession_string;
-use clio::Output;
+use crate::format::AlignmentFormat;
+use crate::optimize::Solution;
+use crate::pareto::ParetoPoint;
+use crate::phylip::PHYLIP_NAME_WIDTH;
+use crate::stockholm::StockholmAnnotations;
 use itertools::Itertools;
+use log::warn;
 use std::collections::HashSet;
-use std::io::{BufWriter, Write};
+use std::io::Write;
 use std::path::Path;
 
 const FASTA_LINE_WIDTH: usize = 80;
+const CLUSTAL_LINE_WIDTH: usize = 60;
+
+/// Writes sequences in the given format to the output. Stockholm annotations
+/// are only used when writing Stockholm files, and the gap characters when
+/// writing Clustal, PHYLIP and NEXUS files.
+pub fn write_alignment(
+    format: AlignmentFormat,
+    sequences: &[Vec<u8>],
+    headers: &[Vec<u8>],
+    annotations: Option<&StockholmAnnotations>,
+    gap_chars: &GapChars,
+    output: &mut impl Write,
+) -> Result<()> {
+    match format {
+        AlignmentFormat::Fasta => write_fasta(sequences, headers, output),
+        AlignmentFormat::Stockholm => write_stockholm(sequences, headers, annotations, output),
+        AlignmentFormat::Clustal => write_clustal(sequences, headers, gap_chars, output),
+        AlignmentFormat::Phylip => write_phylip(sequences, headers, true, gap_chars, output),
+        AlignmentFormat::PhylipRelaxed => {
+            write_phylip(sequences, headers, false, gap_chars, output)
+        }
+        AlignmentFormat::Nexus => write_nexus(sequences, headers, gap_chars, output),
+        AlignmentFormat::A2m => write_fasta(sequences, headers, output),
+        AlignmentFormat::A3m => {
+            let sequences: Vec<Vec<u8>> = sequences.iter().map(|seq| to_a3m(seq)).collect();
+            write_fasta(&sequences, headers, output)
+        }
+    }
+}
 
 /// Writes sequences in FASTA format to the given output.
-pub fn write_fasta(sequences: &[Vec<u8>], headers: &[Vec<u8>], output: &mut Output) -> Result<()> {
+pub fn write_fasta(
+    sequences: &[Vec<u8>],
+    headers: &[Vec<u8>],
+    output: &mut impl Write,
+) -> Result<()> {
     if sequences.is_empty() {
         return Ok(());
     }
@@ -26,6 +68,330 @@ pub fn write_fasta(sequences: &[Vec<u8>], headers: &[Vec<u8>], output: &mut Outp
     Ok(())
 }
 
+/// Writes sequences in Stockholm format to the given output.
+///
+/// Sequence names are the header accessions. If no annotations are given,
+/// header descriptions are written as `#=GS <name> DE` lines.
+pub fn write_stockholm(
+    sequences: &[Vec<u8>],
+    headers: &[Vec<u8>],
+    annotations: Option<&StockholmAnnotations>,
+    output: &mut impl Write,
+) -> Result<()> {
+    if sequences.is_empty() {
+        return Ok(());
+    }
+
+    let names: Vec<String> = headers
+        .iter()
+        .map(|header| get_record_accession_string(header).unwrap_or_default())
+        .collect();
+
+    writeln!(output, "# STOCKHOLM 1.0")?;
+
+    let Some(annotations) = annotations else {
+        let descriptions: Vec<(&String, String)> = names
+            .iter()
+            .zip(headers)
+            .filter_map(|(name, header)| {
+                let description = String::from_utf8_lossy(header)
+                    .get(name.len()..)?
+                    .trim()
+                    .to_string();
+                (!description.is_empty()).then_some((name, description))
+            })
+            .collect();
+        if !descriptions.is_empty() {
+            let width = descriptions
+                .iter()
+                .map(|(name, _)| name.len())
+                .max()
+                .unwrap_or(0);
+            for (name, description) in descriptions {
+                writeln!(output, "#=GS {name:<width$} DE {description}")?;
+            }
+        }
+        writeln!(output)?;
+        let width = names.iter().map(String::len).max().unwrap_or(0);
+        for (name, seq) in names.iter().zip_eq(sequences) {
+            writeln!(output, "{name:<width$} {}", String::from_utf8_lossy(seq))?;
+        }
+        writeln!(output, "//")?;
+        return Ok(());
+    };
+
+    for gf in &annotations.file {
+        writeln!(
+            output,
+            "#=GF {} {}",
+            String::from_utf8_lossy(&gf.feature),
+            String::from_utf8_lossy(&gf.text)
+        )?;
+    }
+
+    if !annotations.sequence.is_empty() {
+        let name_width = annotations
+            .sequence
+            .iter()
+            .map(|gs| gs.name.len())
+            .max()
+            .unwrap_or(0);
+        let feature_width = annotations
+            .sequence
+            .iter()
+            .map(|gs| gs.feature.len())
+            .max()
+            .unwrap_or(0);
+        for gs in &annotations.sequence {
+            writeln!(
+                output,
+                "#=GS {:<name_width$} {:<feature_width$} {}",
+                String::from_utf8_lossy(&gs.name),
+                String::from_utf8_lossy(&gs.feature),
+                String::from_utf8_lossy(&gs.text)
+            )?;
+        }
+    }
+
+    if !annotations.file.is_empty() || !annotations.sequence.is_empty() {
+        writeln!(output)?;
+    }
+
+    let residue_labels: Vec<String> = annotations
+        .residue
+        .iter()
+        .map(|gr| {
+            format!(
+                "#=GR {} {}",
+                String::from_utf8_lossy(&gr.name),
+                String::from_utf8_lossy(&gr.feature)
+            )
+        })
+        .collect();
+    let column_labels: Vec<String> = annotations
+        .column
+        .iter()
+        .map(|gc| format!("#=GC {}", String::from_utf8_lossy(&gc.feature)))
+        .collect();
+    let width = names
+        .iter()
+        .chain(&residue_labels)
+        .chain(&column_labels)
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
+
+    for (name, seq) in names.iter().zip_eq(sequences) {
+        writeln!(output, "{name:<width$} {}", String::from_utf8_lossy(seq))?;
+        for (label, gr) in residue_labels.iter().zip(&annotations.residue) {
+            if gr.name == name.as_bytes() {
+                writeln!(
+                    output,
+                    "{label:<width$} {}",
+                    String::from_utf8_lossy(&gr.data)
+                )?;
+            }
+        }
+    }
+
+    for (label, gc) in column_labels.iter().zip(&annotations.column) {
+        writeln!(
+            output,
+            "{label:<width$} {}",
+            String::from_utf8_lossy(&gc.data)
+        )?;
+    }
+
+    writeln!(output, "//")?;
+
+    Ok(())
+}
+
+/// Writes sequences in Clustal format to the given output. Gap characters
+/// are left out of the residue counts and conservation line.
+pub fn write_clustal(
+    sequences: &[Vec<u8>],
+    headers: &[Vec<u8>],
+    gap_chars: &GapChars,
+    output: &mut impl Write,
+) -> Result<()> {
+    if sequences.is_empty() {
+        return Ok(());
+    }
+
+    let names: Vec<String> = headers
+        .iter()
+        .map(|header| get_record_accession_string(header).unwrap_or_default())
+        .collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0) + 6;
+    let alignment_length = sequences.iter().map(Vec::len).max().unwrap_or(0);
+
+    writeln!(output, "CLUSTAL W multiple sequence alignment\n")?;
+
+    let mut residue_counts = vec![0usize; sequences.len()];
+    for start in (0..alignment_length).step_by(CLUSTAL_LINE_WIDTH) {
+        let end = (start + CLUSTAL_LINE_WIDTH).min(alignment_length);
+        writeln!(output)?;
+        for ((name, seq), count) in names.iter().zip_eq(sequences).zip(&mut residue_counts) {
+            let chunk = seq.get(start..end.min(seq.len())).unwrap_or_default();
+            *count += chunk.iter().filter(|&&b| !gap_chars.contains(b)).count();
+            writeln!(
+                output,
+                "{name:<width$}{} {count}",
+                String::from_utf8_lossy(chunk)
+            )?;
+        }
+        let conservation: String = (start..end)
+            .map(|pos| {
+                let mut column = sequences.iter().map(|seq| seq.get(pos).copied());
+                let first = column.next().flatten();
+                match first {
+                    Some(first)
+                        if !gap_chars.contains(first)
+                            && column
+                                .all(|b| b.is_some_and(|b| b.eq_ignore_ascii_case(&first))) =>
+                    {
+                        '*'
+                    }
+                    _ => ' ',
+                }
+            })
+            .collect();
+        writeln!(output, "{:width$}{conservation}", "")?;
+    }
+
+    Ok(())
+}
+
+/// Writes sequences in sequential PHYLIP format to the given output. In strict
+/// mode, names are truncated or padded to 10 characters. Gaps and missing data
+/// are written as `-` and `?` (see [`standard_gaps`]).
+pub fn write_phylip(
+    sequences: &[Vec<u8>],
+    headers: &[Vec<u8>],
+    strict: bool,
+    gap_chars: &GapChars,
+    output: &mut impl Write,
+) -> Result<()> {
+    if sequences.is_empty() {
+        return Ok(());
+    }
+
+    let alignment_length = sequences.iter().map(Vec::len).max().unwrap_or(0);
+    let mut names: Vec<String> = headers
+        .iter()
+        .map(|header| get_record_accession_string(header).unwrap_or_default())
+        .collect();
+
+    if strict {
+        for name in &mut names {
+            if name.len() > PHYLIP_NAME_WIDTH {
+                let truncated: String = name.chars().take(PHYLIP_NAME_WIDTH).collect();
+                warn!("Sequence name '{name}' was truncated to '{truncated}' in the PHYLIP output");
+                *name = truncated;
+            }
+        }
+        if names.iter().duplicates().next().is_some() {
+            warn!("The PHYLIP output contains duplicated sequence names");
+        }
+    }
+
+    let width = if strict {
+        PHYLIP_NAME_WIDTH
+    } else {
+        names.iter().map(String::len).max().unwrap_or(0) + 1
+    };
+
+    writeln!(output, "{} {alignment_length}", sequences.len())?;
+    for (name, seq) in names.iter().zip_eq(sequences) {
+        writeln!(
+            output,
+            "{name:<width$}{}",
+            String::from_utf8_lossy(&standard_gaps(seq, gap_chars))
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns the sequence with missing data (`?` and `~`) written as `?` and
+/// the other gap characters as `-`, the only symbols PHYLIP readers and the
+/// NEXUS `FORMAT` written by [`write_nexus`] accept for them. Letters treated
+/// as gaps (e.g., `N`) are valid residue codes and are kept.
+fn standard_gaps(seq: &[u8], gap_chars: &GapChars) -> Vec<u8> {
+    seq.iter()
+        .map(|&b| match b {
+            b'?' | b'~' => b'?',
+            _ if gap_chars.contains(b) && !b.is_ascii_alphabetic() => b'-',
+            _ => b,
+        })
+        .collect()
+}
+
+/// Returns `true` if the sequences only contain nucleotide codes and gaps.
+fn is_nucleotide_alignment(sequences: &[Vec<u8>], gap_chars: &GapChars) -> bool {
+    sequences
+        .iter()
+        .flatten()
+        .all(|&b| gap_chars.contains(b) || b"ACGTUNRYKMSWBDHV?~".contains(&b.to_ascii_uppercase()))
+}
+
+/// Quotes a NEXUS taxon name if it contains punctuation or whitespace.
+fn nexus_taxon_name(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "''"))
+    }
+}
+
+/// Writes sequences in NEXUS format to the given output. The data type is
+/// DNA if the sequences only contain nucleotide codes and gap characters, and
+/// gaps and missing data are written as `-` and `?` (see [`standard_gaps`]).
+pub fn write_nexus(
+    sequences: &[Vec<u8>],
+    headers: &[Vec<u8>],
+    gap_chars: &GapChars,
+    output: &mut impl Write,
+) -> Result<()> {
+    if sequences.is_empty() {
+        return Ok(());
+    }
+
+    let alignment_length = sequences.iter().map(Vec::len).max().unwrap_or(0);
+    let names: Vec<String> = headers
+        .iter()
+        .map(|header| nexus_taxon_name(&get_record_accession_string(header).unwrap_or_default()))
+        .collect();
+    let width = names.iter().map(String::len).max().unwrap_or(0) + 1;
+    let datatype = if is_nucleotide_alignment(sequences, gap_chars) {
+        "DNA"
+    } else {
+        "PROTEIN"
+    };
+
+    writeln!(output, "#NEXUS\n")?;
+    writeln!(output, "BEGIN DATA;")?;
+    writeln!(
+        output,
+        "\tDIMENSIONS NTAX={} NCHAR={alignment_length};",
+        sequences.len()
+    )?;
+    writeln!(output, "\tFORMAT DATATYPE={datatype} MISSING=? GAP=-;")?;
+    writeln!(output, "\tMATRIX")?;
+    for (name, seq) in names.iter().zip_eq(sequences) {
+        writeln!(
+            output,
+            "\t{name:<width$}{}",
+            String::from_utf8_lossy(&standard_gaps(seq, gap_chars))
+        )?;
+    }
+    writeln!(output, "\t;")?;
+    writeln!(output, "END;")?;
+
+    Ok(())
+}
+
 /// Writes a list of headers to a file (included or excluded based on the flag).
 pub fn write_headers_list(
     path: impl AsRef<Path>,
@@ -34,11 +400,10 @@ pub fn write_headers_list(
     included: bool,
 ) -> Result<()> {
     let path = path.as_ref();
-    let file = std::fs::File::create(path).map_err(|e| Error::HeadersListWrite {
+    let mut writer = create_file(path).map_err(|e| Error::HeadersListWrite {
         path: path.to_path_buf(),
         source: e,
     })?;
-    let mut writer = BufWriter::new(file);
 
     for (idx, header) in headers.iter().enumerate() {
         if excluded.contains(&idx) != included {
@@ -50,10 +415,209 @@ pub fn write_headers_list(
         }
     }
 
-    writer.flush().map_err(|e| Error::HeadersListWrite {
+    writer.finish().map_err(|e| Error::HeadersListWrite {
         path: path.to_path_buf(),
         source: e,
     })?;
 
     Ok(())
 }
+
+/// Writes the Pareto front as a tab-separated table with one solution per row:
+/// the number of retained and excluded sequences, the number of gap-free
+/// columns, the alignment area, the weighted area, the stage that found the
+/// solution and the accessions of the excluded sequences. If sequences or
+/// columns are weighted, `weighted_area_scale` is the number of integer units
+/// per unit of weighted area.
+#[allow(clippy::cast_precision_loss)]
+pub fn write_pareto_front(
+    path: impl AsRef<Path>,
+    front: &[ParetoPoint],
+    headers: &[Vec<u8>],
+    weighted_area_scale: Option<usize>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let write_err = |e| Error::ParetoWrite {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let mut writer = create_file(path).map_err(write_err)?;
+
+    writeln!(
+        writer,
+        "retained\texcluded\tgap_free_columns\talignment_area\tweighted_area\tfound_by\texcluded_sequences"
+    )
+    .map_err(write_err)?;
+    for point in front {
+        let metrics = &point.metrics;
+        let weighted_area = weighted_area_scale.map_or_else(
+            || metrics.weighted_area.to_string(),
+            |scale| format!("{:.3}", metrics.weighted_area as f64 / scale as f64),
+        );
+        let excluded = point
+            .excluded
+            .iter()
+            .sorted_unstable()
+            .map(|&idx| get_record_accession_string(&headers[idx]).unwrap_or_default())
+            .join(",");
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            metrics.sequence_count,
+            point.excluded.len(),
+            metrics.gap_free_columns,
+            metrics.alignment_area,
+            weighted_area,
+            point.source,
+            excluded
+        )
+        .map_err(write_err)?;
+    }
+
+    writer.finish().map_err(write_err)?;
+    Ok(())
+}
+
+/// Writes the solutions of the refinement to a file, numbered from 1: as JSON
+/// if the file has a `.json` extension (before any compression suffix), and as
+/// tab-separated values otherwise.
+#[allow(clippy::cast_precision_loss)]
+pub fn write_solutions(
+    path: impl AsRef<Path>,
+    solutions: &[Solution],
+    headers: &[Vec<u8>],
+    weighted_area_scale: Option<usize>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let write_err = |e| Error::SolutionsWrite {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let mut writer = create_file(path).map_err(write_err)?;
+
+    let records = solutions.iter().map(|solution| {
+        let excluded: Vec<String> = solution
+            .excluded
+            .iter()
+            .sorted_unstable()
+            .map(|&idx| get_record_accession_string(&headers[idx]).unwrap_or_default())
+            .collect();
+        (solution, excluded)
+    });
+
+    if is_json_path(path) {
+        let records: Vec<serde_json::Value> = records
+            .enumerate()
+            .map(|(idx, (solution, excluded))| {
+                let metrics = &solution.metrics;
+                let weighted_area = weighted_area_scale.map_or_else(
+                    || serde_json::Value::from(metrics.weighted_area),
+                    |scale| serde_json::Value::from(metrics.weighted_area as f64 / scale as f64),
+                );
+                serde_json::json!({
+                    "index": idx + 1,
+                    "retained": metrics.sequence_count,
+                    "excluded": solution.excluded.len(),
+                    "gap_free_columns": metrics.gap_free_columns,
+                    "alignment_area": metrics.alignment_area,
+                    "weighted_area": weighted_area,
+                    "excluded_sequences": excluded,
+                })
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut writer, &records).map_err(|e| write_err(e.into()))?;
+        writeln!(writer).map_err(write_err)?;
+    } else {
+        writeln!(
+            writer,
+            "index\tretained\texcluded\tgap_free_columns\talignment_area\tweighted_area\texcluded_sequences"
+        )
+        .map_err(write_err)?;
+        for (idx, (solution, excluded)) in records.enumerate() {
+            let metrics = &solution.metrics;
+            let weighted_area = weighted_area_scale.map_or_else(
+                || metrics.weighted_area.to_string(),
+                |scale| format!("{:.3}", metrics.weighted_area as f64 / scale as f64),
+            );
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                idx + 1,
+                metrics.sequence_count,
+                solution.excluded.len(),
+                metrics.gap_free_columns,
+                metrics.alignment_area,
+                weighted_area,
+                excluded.join(",")
+            )
+            .map_err(write_err)?;
+        }
+    }
+
+    writer.finish().map_err(write_err)?;
+    Ok(())
+}
+
+/// Returns `true` if the file has a `.json` extension, ignoring compression
+/// suffixes.
+fn is_json_path(path: &Path) -> bool {
+    let path = if Compression::from_path(path) == Compression::None {
+        path.to_path_buf()
+    } else {
+        path.with_extension("")
+    };
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> Vec<Vec<u8>> {
+        vec![b"a".to_vec(), b"b".to_vec()]
+    }
+
+    #[test]
+    fn writes_standard_gaps_in_nexus() {
+        let sequences = vec![b"AC.GT".to_vec(), b"A~-G?".to_vec()];
+        let mut written = Vec::new();
+        write_nexus(&sequences, &headers(), &GapChars::default(), &mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("FORMAT DATATYPE=DNA MISSING=? GAP=-;"));
+        assert!(written.contains("\ta AC-GT\n"));
+        assert!(written.contains("\tb A?-G?\n"));
+    }
+
+    #[test]
+    fn writes_standard_gaps_in_phylip() {
+        let sequences = vec![b"AC.GT".to_vec(), b"A~_G?".to_vec()];
+        let gap_chars = GapChars::new(b"-._~");
+        for strict in [true, false] {
+            let mut written = Vec::new();
+            write_phylip(&sequences, &headers(), strict, &gap_chars, &mut written).unwrap();
+            let rows: Vec<String> = String::from_utf8(written)
+                .unwrap()
+                .lines()
+                .skip(1)
+                .map(|line| line.split_whitespace().last().unwrap().to_string())
+                .collect();
+            assert_eq!(rows, ["AC-GT", "A?-G?"]);
+        }
+    }
+
+    #[test]
+    fn keeps_letters_treated_as_gaps() {
+        let sequences = vec![b"ACN.T".to_vec()];
+        let mut written = Vec::new();
+        write_phylip(
+            &sequences,
+            &headers()[..1],
+            false,
+            &GapChars::new(b"-.Nn"),
+            &mut written,
+        )
+        .unwrap();
+        assert_eq!(written, b"1 5\na ACN-T\n");
+    }
+}
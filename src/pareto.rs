@@ -0,0 +1,152 @@
+//! Trade-off between the number of retained sequences and the alignment area.
+//!
+//! Maximizing the area often excludes more sequences than can be afforded.
+//! The Pareto front lists, for each number of retained sequences, the best
+//! area found by a run, so that a smaller exclusion can be chosen instead.
+
+use crate::alignment::AlignmentMetrics;
+use crate::polish::PolishMove;
+use std::collections::HashSet;
+
+/// Stage of a run that found a solution of the Pareto front.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParetoSource {
+    /// The initial alignment or the state after a heuristic iteration.
+    Heuristic,
+    /// A solution found by polishing the heuristic solution.
+    Polishing,
+    /// A solution of the branch-and-bound refinement.
+    Refinement,
+}
+
+impl std::fmt::Display for ParetoSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Heuristic => "heuristic",
+            Self::Polishing => "polishing",
+            Self::Refinement => "refinement",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A solution of the Pareto front.
+#[derive(Debug, Clone)]
+pub struct ParetoPoint {
+    /// Metrics of the alignment without the excluded sequences.
+    pub metrics: AlignmentMetrics,
+    /// Indices of the excluded sequences.
+    pub excluded: HashSet<usize>,
+    /// Stage of the run that found the solution.
+    pub source: ParetoSource,
+}
+
+/// Returns the solutions that are not dominated by another one, i.e., for
+/// which no other solution retains at least as many sequences with a larger
+/// weighted area, or more sequences with the same weighted area. The front is
+/// sorted by decreasing number of retained sequences, and so by increasing
+/// area. Among equal solutions, the first one is kept.
+#[must_use]
+pub fn pareto_front(mut points: Vec<ParetoPoint>) -> Vec<ParetoPoint> {
+    points.sort_by_key(|point| std::cmp::Reverse(point.metrics.sequence_count));
+    let mut front: Vec<ParetoPoint> = Vec::with_capacity(points.len());
+    for point in points {
+        let Some(last) = front.last() else {
+            front.push(point);
+            continue;
+        };
+        if point.metrics.weighted_area > last.metrics.weighted_area {
+            if point.metrics.sequence_count == last.metrics.sequence_count {
+                front.pop();
+            }
+            front.push(point);
+        }
+    }
+    front
+}
+
+/// Returns the solution of the front with the largest area among those that
+/// retain at least `min_retained` sequences, or the one that retains the most
+/// sequences if none does.
+#[must_use]
+pub fn select(front: &[ParetoPoint], min_retained: usize) -> Option<&ParetoPoint> {
+    front
+        .iter()
+        .rev()
+        .find(|point| point.metrics.sequence_count >= min_retained)
+        .or_else(|| front.first())
+}
+
+/// Returns the states visited by the heuristic: the initial alignment, the
+/// alignment after each iteration of `iteration_data` and after each move of
+/// the polishing. `metrics_of` computes the metrics of the alignment without
+/// the given sequences.
+pub fn heuristic_points(
+    iteration_data: &[(Vec<usize>, usize)],
+    polish_moves: &[PolishMove],
+    metrics_of: impl Fn(&HashSet<usize>) -> AlignmentMetrics,
+) -> Vec<ParetoPoint> {
+    let mut excluded = HashSet::new();
+    let mut points = Vec::with_capacity(iteration_data.len() + polish_moves.len() + 1);
+    points.push(ParetoPoint {
+        metrics: metrics_of(&excluded),
+        excluded: excluded.clone(),
+        source: ParetoSource::Heuristic,
+    });
+    for (exseq, _) in iteration_data {
+        excluded.extend(exseq);
+        points.push(ParetoPoint {
+            metrics: metrics_of(&excluded),
+            excluded: excluded.clone(),
+            source: ParetoSource::Heuristic,
+        });
+    }
+    for polish_move in polish_moves {
+        excluded.retain(|idx| !polish_move.reinserted.contains(idx));
+        excluded.extend(&polish_move.excluded);
+        points.push(ParetoPoint {
+            metrics: metrics_of(&excluded),
+            excluded: excluded.clone(),
+            source: ParetoSource::Polishing,
+        });
+    }
+    points
+}
+
+/// Tightens the front with a constrained refinement. For each number of
+/// retained sequences `k` above that of the largest-area solution, `refine` is
+/// called with the maximum number of sequences that may be excluded
+/// (`num_sequences - k`) and the metrics of the best solution known that
+/// retains at least `k` sequences, and returns a solution with a larger area
+/// if it finds one. Values of `k` that can't improve the front are skipped.
+pub fn refine_front(
+    points: Vec<ParetoPoint>,
+    num_sequences: usize,
+    mut refine: impl FnMut(usize, &AlignmentMetrics) -> Option<(AlignmentMetrics, HashSet<usize>)>,
+) -> Vec<ParetoPoint> {
+    let mut front = pareto_front(points);
+    let Some(last) = front.last() else {
+        return front;
+    };
+    let mut min_retained = last.metrics.sequence_count + 1;
+    while min_retained < num_sequences {
+        let Some(known) = select(&front, min_retained) else {
+            break;
+        };
+        let known_retained = known.metrics.sequence_count;
+        min_retained = match refine(num_sequences - min_retained, &known.metrics) {
+            Some((metrics, excluded)) => {
+                let retained = metrics.sequence_count;
+                front.push(ParetoPoint {
+                    metrics,
+                    excluded,
+                    source: ParetoSource::Refinement,
+                });
+                front = pareto_front(front);
+                retained + 1
+            }
+            None => known_retained.max(min_retained) + 1,
+        };
+    }
+    front
+}
@@ -0,0 +1,244 @@
+//! PHYLIP file parsing utilities.
+
+use crate::error::{Error, Result};
+use crate::fasta::{SequenceData, build_sequence_data};
+use crate::stockholm::split_field;
+use std::io::BufRead;
+
+/// Width of the sequence name field in strict PHYLIP files.
+pub const PHYLIP_NAME_WIDTH: usize = 10;
+
+/// Parses a sequential or interleaved PHYLIP file and returns the sequence data.
+///
+/// If `strict` is `Some(true)`, sequence names are the first 10 characters of
+/// each line; if `Some(false)`, they are delimited by whitespace. If `None`,
+/// the file is read as strict PHYLIP if both layouts give the same names and
+/// sequences or only the strict layout fits, and as relaxed PHYLIP otherwise.
+/// Returns the sequence data and whether names were read in the strict layout.
+pub fn parse_phylip(
+    reader: impl BufRead,
+    keep_sequence: &[String],
+    strict: Option<bool>,
+) -> Result<(SequenceData, bool)> {
+    let mut lines = Vec::new();
+    for line in reader.split(b'\n') {
+        let line = line.map_err(|e| Error::PhylipParse(e.to_string()))?;
+        if !line.trim_ascii().is_empty() {
+            lines.push(line.trim_ascii_end().to_vec());
+        }
+    }
+
+    let Some((header, body)) = lines.split_first() else {
+        return Err(Error::EmptyInput);
+    };
+
+    let fields: Vec<usize> = header
+        .split(u8::is_ascii_whitespace)
+        .filter(|f| !f.is_empty())
+        .map(|f| String::from_utf8_lossy(f).parse::<usize>())
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|_| Error::PhylipParse("invalid header line".to_string()))?;
+    let &[num_sequences, alignment_length] = fields.as_slice() else {
+        return Err(Error::PhylipParse(
+            "header line must contain the number of sequences and the alignment length".to_string(),
+        ));
+    };
+
+    let ((headers, sequences), strict) = match strict {
+        Some(strict) => (
+            parse_phylip_body(body, num_sequences, alignment_length, strict)?,
+            strict,
+        ),
+        None => {
+            let strict_body = parse_phylip_body(body, num_sequences, alignment_length, true);
+            match parse_phylip_body(body, num_sequences, alignment_length, false) {
+                Ok(relaxed) if strict_body.as_ref().is_ok_and(|strict| *strict == relaxed) => {
+                    (relaxed, true)
+                }
+                Ok(relaxed) => (relaxed, false),
+                Err(_) => (strict_body?, true),
+            }
+        }
+    };
+
+    Ok((
+        build_sequence_data(headers, sequences, keep_sequence)?,
+        strict,
+    ))
+}
+
+fn split_name(line: &[u8], strict: bool) -> (Vec<u8>, Vec<u8>) {
+    let (name, data) = if strict {
+        let split = line.len().min(PHYLIP_NAME_WIDTH);
+        (line[..split].trim_ascii(), &line[split..])
+    } else {
+        split_field(line)
+    };
+    (name.to_vec(), strip_whitespace(data))
+}
+
+fn strip_whitespace(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect()
+}
+
+/// Parses the sequence lines, trying the sequential layout first and the
+/// interleaved layout if the lines don't add up.
+#[allow(clippy::type_complexity)]
+fn parse_phylip_body(
+    body: &[Vec<u8>],
+    num_sequences: usize,
+    alignment_length: usize,
+    strict: bool,
+) -> Result<(Vec<Vec<u8>>, Vec<Vec<u8>>)> {
+    // Sequential: each sequence starts with its name and may continue on the
+    // following lines until the alignment length is reached.
+    let mut headers = Vec::with_capacity(num_sequences);
+    let mut sequences: Vec<Vec<u8>> = Vec::with_capacity(num_sequences);
+    let mut sequential_ok = true;
+    for line in body {
+        match sequences.last_mut() {
+            Some(seq) if seq.len() < alignment_length => {
+                seq.extend(strip_whitespace(line));
+                if seq.len() > alignment_length {
+                    sequential_ok = false;
+                    break;
+                }
+            }
+            _ => {
+                let (name, data) = split_name(line, strict);
+                if data.len() > alignment_length {
+                    sequential_ok = false;
+                    break;
+                }
+                headers.push(name);
+                sequences.push(data);
+            }
+        }
+    }
+    if sequential_ok
+        && sequences.len() == num_sequences
+        && sequences.iter().all(|s| s.len() == alignment_length)
+    {
+        return Ok((headers, sequences));
+    }
+
+    // Interleaved: the first block holds the names and each following block
+    // continues the sequences in the same order.
+    if body.len() < num_sequences || num_sequences == 0 {
+        return Err(Error::PhylipParse(format!(
+            "expected {num_sequences} sequences, found {}",
+            body.len()
+        )));
+    }
+    let (headers, mut sequences): (Vec<_>, Vec<_>) = body[..num_sequences]
+        .iter()
+        .map(|line| split_name(line, strict))
+        .unzip();
+    for (i, line) in body[num_sequences..].iter().enumerate() {
+        sequences[i % num_sequences].extend(strip_whitespace(line));
+    }
+
+    if let Some(seq) = sequences.iter().find(|s| s.len() != alignment_length) {
+        return Err(Error::PhylipParse(format!(
+            "expected sequences of length {alignment_length}, found one of length {}",
+            seq.len()
+        )));
+    }
+
+    Ok((headers, sequences))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::GapChars;
+    use crate::output::write_phylip;
+
+    fn parse(text: &str, strict: Option<bool>) -> Result<(SequenceData, bool)> {
+        parse_phylip(text.as_bytes(), &[], strict)
+    }
+
+    #[test]
+    fn parses_sequential_relaxed_names() {
+        let text = "2 8\nlong_name_1 ACGT ACGT\nb ACGTAC\n--\n";
+        let (data, strict) = parse(text, None).unwrap();
+        assert!(!strict);
+        assert_eq!(data.headers, [b"long_name_1".to_vec(), b"b".to_vec()]);
+        assert_eq!(data.sequences, [b"ACGTACGT".to_vec(), b"ACGTAC--".to_vec()]);
+    }
+
+    #[test]
+    fn parses_interleaved_blocks() {
+        let text =
+            "3 10\nseq1      ACGTA\nseq2      AC-TA\nseq3      ACGTT\n\nCGTAC\nCG-AC\nCGTTT\n";
+        let (data, strict) = parse(text, None).unwrap();
+        assert!(strict);
+        assert_eq!(
+            data.sequences,
+            [
+                b"ACGTACGTAC".to_vec(),
+                b"AC-TACG-AC".to_vec(),
+                b"ACGTTCGTTT".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_strict_names_with_blanks() {
+        let text = "2 4\nmy seq 1  ACGT\nmy seq 2  AC-T\n";
+        let (data, strict) = parse(text, None).unwrap();
+        assert!(strict);
+        assert_eq!(data.headers, [b"my seq 1".to_vec(), b"my seq 2".to_vec()]);
+        assert!(parse(text, Some(false)).is_err());
+    }
+
+    #[test]
+    fn round_trips_both_flavours() {
+        let headers = vec![b"a_long_sequence_name".to_vec(), b"short".to_vec()];
+        let sequences = vec![b"ACGT-".to_vec(), b"AC-TT".to_vec()];
+        for strict in [true, false] {
+            let mut written = Vec::new();
+            write_phylip(
+                &sequences,
+                &headers,
+                strict,
+                &GapChars::default(),
+                &mut written,
+            )
+            .unwrap();
+            let (data, detected) = parse_phylip(written.as_slice(), &[], None).unwrap();
+            assert_eq!(detected, strict);
+            assert_eq!(data.sequences, sequences);
+            let expected: &[u8] = if strict {
+                b"a_long_seq"
+            } else {
+                b"a_long_sequence_name"
+            };
+            assert_eq!(data.headers[0], expected);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(matches!(
+            parse("2\nseq1 ACGT\nseq2 ACGT\n", None),
+            Err(Error::PhylipParse(_))
+        ));
+        assert!(matches!(
+            parse("two 4\nseq1 ACGT\nseq2 ACGT\n", None),
+            Err(Error::PhylipParse(_))
+        ));
+        assert!(matches!(
+            parse("3 4\nseq1 ACGT\nseq2 ACGT\n", None),
+            Err(Error::PhylipParse(_))
+        ));
+        assert!(matches!(
+            parse("2 5\nseq1 ACGT\nseq2 ACGT\n", None),
+            Err(Error::PhylipParse(_))
+        ));
+        assert!(matches!(parse("", None), Err(Error::EmptyInput)));
+    }
+}
@@ -0,0 +1,449 @@
+//! Local-search polishing of the heuristic solution.
+//!
+//! The greedy heuristic never revisits its decisions: a sequence excluded in an
+//! early iteration stays excluded even if putting it back would enlarge the
+//! area once other sequences are gone. Polishing searches around the solution
+//! of the heuristic with three moves on the partition into retained and
+//! excluded sequences:
+//!
+//! - re-insertion (1-in): an excluded sequence is retained again;
+//! - exclusion (1-out): a retained sequence is excluded;
+//! - swap (1-out/1-in): an excluded sequence is retained in place of a retained
+//!   one.
+//!
+//! Each round applies the move that enlarges the weighted area the most, until
+//! no move enlarges it. Optionally, a simulated annealing walk, which also
+//! accepts moves that shrink the area, then starts from this local optimum to
+//! escape it, and the hill climbing resumes from the best solution it visited.
+
+use crate::alignment::{ExclusionBudget, GapTolerance};
+use crate::weights::Weights;
+use itertools::{EitherOrBoth, Itertools};
+use log::info;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use rayon::prelude::*;
+use std::collections::HashSet;
+
+/// Fraction of the area whose loss is accepted with probability 1/e at the
+/// start of the simulated annealing.
+const INITIAL_TEMPERATURE: f64 = 0.01;
+
+/// Ratio between the final and the initial temperatures of the simulated
+/// annealing.
+const FINAL_TEMPERATURE_RATIO: f64 = 1e-3;
+
+/// Configuration of the polishing stage.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PolishConfig {
+    /// Number of simulated annealing steps to run after the hill climbing
+    /// (none by default).
+    pub anneal_steps: u64,
+    /// Seed of the random number generator of the simulated annealing.
+    pub seed: u64,
+}
+
+/// A change of the best solution found by the polishing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolishMove {
+    /// Sequences excluded by the move.
+    pub excluded: Vec<usize>,
+    /// Sequences retained again by the move.
+    pub reinserted: Vec<usize>,
+    /// Number of excluded sequences after the move.
+    pub total_excluded: usize,
+    /// Number of columns that count towards the area after the move.
+    pub counted_columns: usize,
+    /// Alignment area after the move.
+    pub alignment_area: usize,
+    /// Weighted alignment area after the move.
+    pub weighted_area: usize,
+}
+
+/// Area of the alignment after a move.
+#[derive(Debug, Clone, Copy, Default)]
+struct Score {
+    columns: usize,
+    area: usize,
+    weighted_area: usize,
+}
+
+/// Partition of the sequences into retained and excluded ones, with the number
+/// of gaps of each column among the retained sequences.
+struct Partition<'a> {
+    /// Columns in which each sequence has a gap, in increasing order.
+    gaps: Vec<Vec<usize>>,
+    weights: &'a Weights,
+    column_weights: &'a Weights,
+    tolerance: GapTolerance,
+    /// Limits on the excluded sequences.
+    budget: &'a ExclusionBudget,
+    /// Limited groups of each sequence, as indices into `budget.groups`.
+    groups: Vec<Vec<usize>>,
+    /// Number of excluded sequences of each limited group.
+    group_excluded: Vec<usize>,
+    excluded: Vec<bool>,
+    retained: usize,
+    retained_weight: usize,
+    /// Number of gaps of each column among the retained sequences.
+    counts: Vec<usize>,
+    /// Number and total weight of the columns with at most a given number of
+    /// gaps.
+    cumulative_columns: Vec<usize>,
+    cumulative_weights: Vec<usize>,
+}
+
+impl<'a> Partition<'a> {
+    fn new(
+        gap_matrix: &[Vec<bool>],
+        excluded: &[bool],
+        weights: &'a Weights,
+        column_weights: &'a Weights,
+        tolerance: GapTolerance,
+        budget: &'a ExclusionBudget,
+    ) -> Self {
+        let gaps: Vec<Vec<usize>> = gap_matrix
+            .iter()
+            .map(|row| (0..row.len()).filter(|&col| row[col]).collect())
+            .collect();
+        let mut counts = vec![0; column_weights.len()];
+        for cols in gaps
+            .iter()
+            .zip(excluded)
+            .filter(|&(_, &ex)| !ex)
+            .map(|(cols, _)| cols)
+        {
+            for &col in cols {
+                counts[col] += 1;
+            }
+        }
+        let groups: Vec<Vec<usize>> = (0..excluded.len())
+            .map(|idx| {
+                (0..budget.groups.len())
+                    .filter(|&group| budget.groups[group].members.contains(idx))
+                    .collect()
+            })
+            .collect();
+        let mut group_excluded = vec![0; budget.groups.len()];
+        for (sequence_groups, _) in groups.iter().zip(excluded).filter(|&(_, &ex)| ex) {
+            for &group in sequence_groups {
+                group_excluded[group] += 1;
+            }
+        }
+        let mut partition = Self {
+            gaps,
+            weights,
+            column_weights,
+            tolerance,
+            budget,
+            groups,
+            group_excluded,
+            excluded: excluded.to_vec(),
+            retained: excluded.iter().filter(|&&ex| !ex).count(),
+            retained_weight: (0..excluded.len())
+                .filter(|&idx| !excluded[idx])
+                .map(|idx| weights.get(idx))
+                .sum(),
+            counts,
+            cumulative_columns: Vec::new(),
+            cumulative_weights: Vec::new(),
+        };
+        partition.accumulate();
+        partition
+    }
+
+    /// Recomputes the cumulative column counts and weights.
+    fn accumulate(&mut self) {
+        let mut columns = vec![0; self.excluded.len() + 1];
+        let mut weights = vec![0; self.excluded.len() + 1];
+        for (col, &count) in self.counts.iter().enumerate() {
+            columns[count] += 1;
+            weights[count] += self.column_weights.get(col);
+        }
+        for count in 1..columns.len() {
+            columns[count] += columns[count - 1];
+            weights[count] += weights[count - 1];
+        }
+        self.cumulative_columns = columns;
+        self.cumulative_weights = weights;
+    }
+
+    /// Returns the columns whose number of gaps changes if `out` is excluded
+    /// and `back` is retained again, with the change.
+    fn changes(
+        &self,
+        out: Option<usize>,
+        back: Option<usize>,
+    ) -> impl Iterator<Item = (usize, isize)> {
+        let out_gaps = out.map_or(&[][..], |idx| &self.gaps[idx]);
+        let back_gaps = back.map_or(&[][..], |idx| &self.gaps[idx]);
+        out_gaps
+            .iter()
+            .merge_join_by(back_gaps, |a, b| a.cmp(b))
+            .filter_map(|change| match change {
+                EitherOrBoth::Left(&col) => Some((col, -1)),
+                EitherOrBoth::Right(&col) => Some((col, 1)),
+                EitherOrBoth::Both(..) => None,
+            })
+    }
+
+    fn score(&self) -> Score {
+        self.score_move(None, None)
+    }
+
+    /// Returns `true` if `out` may be excluded as `back` is retained again,
+    /// without exceeding the budget.
+    fn allows(&self, out: Option<usize>, back: Option<usize>) -> bool {
+        let Some(out) = out else {
+            return true;
+        };
+        (back.is_some()
+            || self
+                .budget
+                .max_excluded
+                .is_none_or(|max| self.excluded.len() - self.retained < max))
+            && self.groups[out].iter().all(|&group| {
+                back.is_some_and(|back| self.groups[back].contains(&group))
+                    || self.group_excluded[group] < self.budget.groups[group].max_excluded
+            })
+    }
+
+    /// Returns the area of the alignment if `out` is excluded and `back` is
+    /// retained again.
+    fn score_move(&self, out: Option<usize>, back: Option<usize>) -> Score {
+        let retained = self.retained + usize::from(back.is_some()) - usize::from(out.is_some());
+        if retained == 0 {
+            return Score::default();
+        }
+        let retained_weight = self.retained_weight + back.map_or(0, |idx| self.weights.get(idx))
+            - out.map_or(0, |idx| self.weights.get(idx));
+        let limit = self.tolerance.allowed_gaps(retained).min(retained - 1);
+        let mut columns = self.cumulative_columns[limit];
+        let mut weight = self.cumulative_weights[limit];
+        for (col, change) in self.changes(out, back) {
+            let old = self.counts[col];
+            let new = old.saturating_add_signed(change);
+            if old <= limit && new > limit {
+                columns -= 1;
+                weight -= self.column_weights.get(col);
+            } else if old > limit && new <= limit {
+                columns += 1;
+                weight += self.column_weights.get(col);
+            }
+        }
+        Score {
+            columns,
+            area: columns * retained,
+            weighted_area: weight * retained_weight,
+        }
+    }
+
+    fn apply(&mut self, out: Option<usize>, back: Option<usize>) {
+        let changes: Vec<(usize, isize)> = self.changes(out, back).collect();
+        for (col, change) in changes {
+            self.counts[col] = self.counts[col].saturating_add_signed(change);
+        }
+        if let Some(idx) = out {
+            self.excluded[idx] = true;
+            self.retained -= 1;
+            self.retained_weight -= self.weights.get(idx);
+            for &group in &self.groups[idx] {
+                self.group_excluded[group] += 1;
+            }
+        }
+        if let Some(idx) = back {
+            self.excluded[idx] = false;
+            self.retained += 1;
+            self.retained_weight += self.weights.get(idx);
+            for &group in &self.groups[idx] {
+                self.group_excluded[group] -= 1;
+            }
+        }
+        self.accumulate();
+    }
+}
+
+/// Polishes a solution by local search (see the [module documentation](self)),
+/// updating `excluded` in place. Sequences in `keep` are never excluded, and
+/// moves that would exceed `budget` are never made. Returns the changes of the
+/// best solution, each of which enlarges the weighted area.
+#[must_use]
+#[allow(clippy::too_many_arguments)]
+pub fn polish(
+    gap_matrix: &[Vec<bool>],
+    excluded: &mut HashSet<usize>,
+    keep: &HashSet<usize>,
+    weights: &Weights,
+    column_weights: &Weights,
+    tolerance: GapTolerance,
+    budget: &ExclusionBudget,
+    config: &PolishConfig,
+) -> Vec<PolishMove> {
+    let num_sequences = gap_matrix.len();
+    let flags: Vec<bool> = (0..num_sequences)
+        .map(|idx| excluded.contains(&idx))
+        .collect();
+    let movable: Vec<usize> = (0..num_sequences)
+        .filter(|idx| !keep.contains(idx))
+        .collect();
+    let mut partition = Partition::new(
+        gap_matrix,
+        &flags,
+        weights,
+        column_weights,
+        tolerance,
+        budget,
+    );
+    let mut best = Best {
+        score: partition.score(),
+        excluded: flags,
+        moves: Vec::new(),
+    };
+
+    info!("Polishing the solution with re-insertion, exclusion and swap moves");
+    hill_climb(&mut partition, &movable, &mut best);
+
+    if config.anneal_steps > 0 && !movable.is_empty() {
+        info!(
+            "Polishing the solution with {} simulated annealing steps (seed: {})",
+            config.anneal_steps, config.seed
+        );
+        let improvements = best.moves.len();
+        anneal(&mut partition, &movable, &mut best, config);
+        if best.moves.len() > improvements {
+            partition = Partition::new(
+                gap_matrix,
+                &best.excluded,
+                weights,
+                column_weights,
+                tolerance,
+                budget,
+            );
+            hill_climb(&mut partition, &movable, &mut best);
+        }
+    }
+
+    *excluded = (0..num_sequences)
+        .filter(|&idx| best.excluded[idx])
+        .collect();
+    best.moves
+}
+
+/// Best solution found by the polishing, and the moves that led to it.
+struct Best {
+    score: Score,
+    excluded: Vec<bool>,
+    moves: Vec<PolishMove>,
+}
+
+impl Best {
+    /// Records the state of `partition`, whose area is `score`, as the best
+    /// solution.
+    fn update(&mut self, partition: &Partition<'_>, score: Score) {
+        self.moves
+            .push(record(&self.excluded, &partition.excluded, score));
+        self.excluded.clone_from(&partition.excluded);
+        self.score = score;
+    }
+}
+
+/// Applies the move that enlarges the area the most until none does, starting
+/// from the best solution.
+fn hill_climb(partition: &mut Partition<'_>, movable: &[usize], best: &mut Best) {
+    loop {
+        let retained: Vec<usize> = movable
+            .iter()
+            .copied()
+            .filter(|&idx| !partition.excluded[idx])
+            .collect();
+        let outside: Vec<usize> = (0..partition.excluded.len())
+            .filter(|&idx| partition.excluded[idx])
+            .collect();
+        // Re-insertions come first so that, among equal moves, those that
+        // retain more sequences are preferred.
+        let candidates: Vec<(Option<usize>, Option<usize>)> = outside
+            .iter()
+            .map(|&back| (None, Some(back)))
+            .chain(
+                retained
+                    .iter()
+                    .cartesian_product(&outside)
+                    .map(|(&out, &back)| (Some(out), Some(back))),
+            )
+            .chain(retained.iter().map(|&out| (Some(out), None)))
+            .filter(|&(out, back)| partition.allows(out, back))
+            .collect();
+        let Some((idx, score)) = candidates
+            .par_iter()
+            .enumerate()
+            .map(|(idx, &(out, back))| (idx, partition.score_move(out, back)))
+            .filter(|(_, score)| score.weighted_area > best.score.weighted_area)
+            .max_by(|(a_idx, a), (b_idx, b)| {
+                a.weighted_area.cmp(&b.weighted_area).then(b_idx.cmp(a_idx))
+            })
+        else {
+            return;
+        };
+        let (out, back) = candidates[idx];
+        partition.apply(out, back);
+        best.update(partition, score);
+    }
+}
+
+/// Runs a simulated annealing walk from the state of `partition`. Each step
+/// toggles a random sequence, or swaps it with another one, and the move is
+/// accepted if it doesn't shrink the area or, otherwise, with a probability
+/// that decreases with the loss and over the walk.
+fn anneal(
+    partition: &mut Partition<'_>,
+    movable: &[usize],
+    best: &mut Best,
+    config: &PolishConfig,
+) {
+    let mut rng = Pcg64Mcg::seed_from_u64(config.seed);
+    let mut current = partition.score();
+    #[allow(clippy::cast_precision_loss)]
+    let mut temperature = (current.weighted_area as f64 * INITIAL_TEMPERATURE).max(1.0);
+    #[allow(clippy::cast_precision_loss)]
+    let cooling = FINAL_TEMPERATURE_RATIO.powf(1.0 / config.anneal_steps as f64);
+    for _ in 0..config.anneal_steps {
+        let a = movable[rng.random_range(0..movable.len())];
+        let b = movable[rng.random_range(0..movable.len())];
+        let (out, back) = match (partition.excluded[a], partition.excluded[b]) {
+            (false, true) if a != b => (Some(a), Some(b)),
+            (true, false) if a != b => (Some(b), Some(a)),
+            (false, _) => (Some(a), None),
+            (true, _) => (None, Some(a)),
+        };
+        // Moves beyond the limits are skipped.
+        if partition.allows(out, back) {
+            let score = partition.score_move(out, back);
+            #[allow(clippy::cast_precision_loss)]
+            let delta = score.weighted_area as f64 - current.weighted_area as f64;
+            if delta >= 0.0 || rng.random::<f64>() < (delta / temperature).exp() {
+                partition.apply(out, back);
+                current = score;
+                if score.weighted_area > best.score.weighted_area {
+                    best.update(partition, score);
+                }
+            }
+        }
+        temperature *= cooling;
+    }
+}
+
+/// Describes the change from the `before` to the `after` exclusion.
+fn record(before: &[bool], after: &[bool], score: Score) -> PolishMove {
+    PolishMove {
+        excluded: (0..after.len())
+            .filter(|&idx| after[idx] && !before[idx])
+            .collect(),
+        reinserted: (0..after.len())
+            .filter(|&idx| before[idx] && !after[idx])
+            .collect(),
+        total_excluded: after.iter().filter(|&&ex| ex).count(),
+        counted_columns: score.columns,
+        alignment_area: score.area,
+        weighted_area: score.weighted_area,
+    }
+}
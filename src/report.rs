@@ -1,13 +1,20 @@
 //! Report generation for `MaxAlign` results.
 
-use crate::alignment::AlignmentMetrics;
+use crate::alignment::{AlignmentMetrics, GapChars, GapTolerance};
+use crate::compression::create_file;
 use crate::error::{Error, Result};
 use crate::fasta::get_record_accession_string;
+use crate::format::AlignmentFormat;
+use crate::groups::GroupSummary;
 use crate::heuristic::HeuristicMethod;
+use crate::model::ReducedModel;
+use crate::optimize::{RefinementStatus, Solution};
+use crate::pareto::ParetoPoint;
+use crate::polish::{PolishConfig, PolishMove};
 use itertools::Itertools;
 use markdown_tables::{MarkdownTableRow, as_table};
 use std::collections::HashSet;
-use std::io::{BufWriter, Write};
+use std::io::Write;
 use std::path::Path;
 
 struct RunOption {
@@ -47,6 +54,74 @@ impl MarkdownTableRow for Statistic {
     }
 }
 
+struct ParetoRecord {
+    retained: usize,
+    ungapped_columns: usize,
+    area: String,
+    found_by: String,
+}
+
+impl MarkdownTableRow for ParetoRecord {
+    fn column_names() -> Vec<&'static str> {
+        vec!["Retained sequences", "Ungapped columns", "Area", "Found by"]
+    }
+
+    fn column_values(&self) -> Vec<String> {
+        vec![
+            self.retained.to_string(),
+            self.ungapped_columns.to_string(),
+            self.area.clone(),
+            self.found_by.clone(),
+        ]
+    }
+}
+
+struct SolutionRecord {
+    index: usize,
+    retained: usize,
+    ungapped_columns: usize,
+    area: String,
+}
+
+impl MarkdownTableRow for SolutionRecord {
+    fn column_names() -> Vec<&'static str> {
+        vec!["Index", "Retained sequences", "Ungapped columns", "Area"]
+    }
+
+    fn column_values(&self) -> Vec<String> {
+        vec![
+            self.index.to_string(),
+            self.retained.to_string(),
+            self.ungapped_columns.to_string(),
+            self.area.clone(),
+        ]
+    }
+}
+
+struct GroupRecord {
+    name: String,
+    size: usize,
+    required: usize,
+    retained: usize,
+    excluded: usize,
+}
+
+impl MarkdownTableRow for GroupRecord {
+    fn column_names() -> Vec<&'static str> {
+        vec!["Group", "Sequences", "Required", "Retained", "Excluded"]
+    }
+
+    fn column_values(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.size.to_string(),
+            self.required.to_string(),
+            self.retained.to_string(),
+            self.excluded.to_string(),
+        ]
+    }
+}
+
 struct IterationRecord {
     number: usize,
     excluded_this_round: usize,
@@ -77,19 +152,78 @@ impl MarkdownTableRow for IterationRecord {
     }
 }
 
+struct PolishRecord {
+    number: usize,
+    excluded: String,
+    reinserted: String,
+    total_excluded: usize,
+    ungapped_columns: usize,
+    alignment_area: usize,
+}
+
+impl MarkdownTableRow for PolishRecord {
+    fn column_names() -> Vec<&'static str> {
+        vec![
+            "Move",
+            "Excluded",
+            "Re-inserted",
+            "Total excluded",
+            "Ungapped columns",
+            "Alignment area",
+        ]
+    }
+
+    fn column_values(&self) -> Vec<String> {
+        vec![
+            self.number.to_string(),
+            self.excluded.clone(),
+            self.reinserted.clone(),
+            self.total_excluded.to_string(),
+            self.ungapped_columns.to_string(),
+            self.alignment_area.to_string(),
+        ]
+    }
+}
+
 /// Configuration for generating a report.
 #[derive(Debug)]
 pub struct ReportConfig<'a> {
     pub input_path: String,
     pub output_path: String,
+    pub input_format: AlignmentFormat,
+    pub output_format: AlignmentFormat,
+    pub gap_chars: &'a GapChars,
     pub heuristic_method: HeuristicMethod,
     pub max_iterations: u32,
     pub improvement_threshold: f64,
     pub excluded_seqs_threshold: f64,
+    /// Largest number of excluded sequences allowed, if limited.
+    pub max_excluded: Option<usize>,
+    /// Groups file and quotas, if sequences are grouped.
+    pub groups: Option<String>,
+    pub beam_width: usize,
+    pub polish: Option<PolishConfig>,
     pub refinement: bool,
-    pub keep_sequence: &'a [String],
+    pub refinement_time_limit: Option<f64>,
+    pub refinement_node_limit: Option<u64>,
+    pub sequence_weights: Option<String>,
+    pub column_weights: Option<String>,
+    pub gap_tolerance: GapTolerance,
+    /// Sequences, files and regular expressions of the sequences to retain
+    /// and of those to exclude in advance.
+    pub keep_sequence: Vec<String>,
+    pub exclude_sequence: Vec<String>,
+    pub match_description: bool,
     pub retained_sequences: Option<String>,
     pub excluded_sequences: Option<String>,
+    pub pareto_front: Option<String>,
+    pub pareto_select: Option<usize>,
+    pub solutions: Option<String>,
+    pub top_solutions: Option<usize>,
+    pub solution_index: Option<usize>,
+    pub export_model: Option<String>,
+    pub import_solution: Option<String>,
+    pub solve_model: Option<String>,
 }
 
 /// Data for generating a report.
@@ -98,8 +232,26 @@ pub struct ReportData<'a> {
     pub heuristic_metrics: &'a AlignmentMetrics,
     pub final_metrics: &'a AlignmentMetrics,
     pub iteration_data: &'a [(Vec<usize>, usize)],
+    /// Moves of the polishing, if enabled.
+    pub polish_moves: &'a [PolishMove],
     pub headers: &'a [Vec<u8>],
     pub excluded: &'a HashSet<usize>,
+    /// Accessions of the sequences excluded before the optimization.
+    pub pre_excluded: &'a [String],
+    /// Number of integer units per unit of weighted area, if sequences or
+    /// columns are weighted.
+    pub weighted_area_scale: Option<usize>,
+    /// Outcome of the branch-and-bound refinement, if enabled.
+    pub refinement_status: Option<&'a RefinementStatus>,
+    /// Solutions of the Pareto front, if it was computed.
+    pub pareto_front: &'a [ParetoPoint],
+    /// Solutions of the refinement, if they were listed.
+    pub solutions: &'a [Solution],
+    /// Model of the reduced problem, if it was built.
+    pub model: Option<&'a ReducedModel>,
+    /// Retained and excluded sequences of each group, if sequences are
+    /// grouped.
+    pub groups: &'a [GroupSummary],
 }
 
 /// Writes a detailed report of `MaxAlign` results.
@@ -110,26 +262,77 @@ pub fn write_report(
     data: &ReportData<'_>,
 ) -> Result<()> {
     let path = path.as_ref();
-    let file = std::fs::File::create(path).map_err(|e| Error::ReportWrite {
+    let mut writer = create_file(path).map_err(|e| Error::ReportWrite {
         path: path.to_path_buf(),
         source: e,
     })?;
-    let mut writer = BufWriter::new(file);
 
     write_header(&mut writer, path)?;
     write_options_section(&mut writer, config, path)?;
-    write_statistics_section(&mut writer, data.initial_metrics, data.final_metrics, path)?;
+    write_statistics_section(
+        &mut writer,
+        data.initial_metrics,
+        data.final_metrics,
+        data.weighted_area_scale,
+        path,
+    )?;
     write_iterations_section(&mut writer, data.iteration_data, data.initial_metrics, path)?;
+    if config.polish.is_some() {
+        write_polish_section(&mut writer, data.polish_moves, data.headers, path)?;
+    }
+    // With a selection, the final solution may not be the refined one.
+    let refined_metrics = data
+        .solutions
+        .first()
+        .map(|solution| &solution.metrics)
+        .or_else(|| data.pareto_front.last().map(|point| &point.metrics))
+        .unwrap_or(data.final_metrics);
     write_refinement_section(
         &mut writer,
         config,
         data.heuristic_metrics,
+        refined_metrics,
+        data.refinement_status,
+        data.weighted_area_scale,
+        path,
+    )?;
+    write_pareto_section(
+        &mut writer,
+        data.pareto_front,
+        config.pareto_select,
         data.final_metrics,
+        data.weighted_area_scale,
+        path,
+    )?;
+    write_solutions_section(
+        &mut writer,
+        data.solutions,
+        config,
+        data.weighted_area_scale,
+        path,
+    )?;
+    if let Some(model) = data.model {
+        write_model_section(
+            &mut writer,
+            model,
+            config,
+            data.final_metrics,
+            data.weighted_area_scale,
+            path,
+        )?;
+    }
+    if !data.groups.is_empty() {
+        write_groups_section(&mut writer, data.groups, path)?;
+    }
+    write_excluded_section(
+        &mut writer,
+        data.headers,
+        data.excluded,
+        data.pre_excluded,
         path,
     )?;
-    write_excluded_section(&mut writer, data.headers, data.excluded, path)?;
 
-    writer.flush().map_err(|e| Error::ReportWrite {
+    writer.finish().map_err(|e| Error::ReportWrite {
         path: path.to_path_buf(),
         source: e,
     })?;
@@ -137,6 +340,11 @@ pub fn write_report(
     Ok(())
 }
 
+#[allow(clippy::cast_precision_loss)]
+fn to_weighted_area(metrics: &AlignmentMetrics, scale: usize) -> f64 {
+    metrics.weighted_area as f64 / scale as f64
+}
+
 macro_rules! write_err {
     ($path:expr) => {
         |e| Error::ReportWrite {
@@ -146,6 +354,93 @@ macro_rules! write_err {
     };
 }
 
+/// Configuration for generating the report of a selection of sequences
+/// applied to an alignment.
+#[derive(Debug)]
+pub struct ApplyReportConfig<'a> {
+    pub input_path: String,
+    pub output_path: String,
+    pub input_format: AlignmentFormat,
+    pub output_format: AlignmentFormat,
+    pub gap_chars: &'a GapChars,
+    pub gap_tolerance: GapTolerance,
+    /// File listing the sequences to retain or to exclude.
+    pub list_path: String,
+    /// Whether the list file contains the retained sequences rather than the
+    /// excluded ones.
+    pub list_retained: bool,
+}
+
+/// Writes a report of a selection of sequences applied to an alignment,
+/// without any optimization.
+pub fn write_apply_report(
+    path: impl AsRef<Path>,
+    config: &ApplyReportConfig<'_>,
+    initial_metrics: &AlignmentMetrics,
+    final_metrics: &AlignmentMetrics,
+    headers: &[Vec<u8>],
+    excluded: &HashSet<usize>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut writer = create_file(path).map_err(|e| Error::ReportWrite {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+
+    write_header(&mut writer, path)?;
+    writeln!(writer, "## Run options\n").map_err(write_err!(path))?;
+    let options = vec![
+        RunOption {
+            option: "Input file".to_string(),
+            value: config.input_path.clone(),
+        },
+        RunOption {
+            option: "Output file".to_string(),
+            value: config.output_path.clone(),
+        },
+        RunOption {
+            option: "Input format".to_string(),
+            value: config.input_format.to_string(),
+        },
+        RunOption {
+            option: "Output format".to_string(),
+            value: config.output_format.to_string(),
+        },
+        RunOption {
+            option: "Gap characters".to_string(),
+            value: format!("`{}`", config.gap_chars),
+        },
+        RunOption {
+            option: "Column gap tolerance".to_string(),
+            value: config.gap_tolerance.to_string(),
+        },
+        RunOption {
+            option: if config.list_retained {
+                "Retained sequences list"
+            } else {
+                "Excluded sequences list"
+            }
+            .to_string(),
+            value: config.list_path.clone(),
+        },
+        RunOption {
+            option: "Report file".to_string(),
+            value: path.display().to_string(),
+        },
+    ];
+    writeln!(writer, "{}", as_table(&options)).map_err(write_err!(path))?;
+
+    write_statistics_section(&mut writer, initial_metrics, final_metrics, None, path)?;
+    write_excluded_section(&mut writer, headers, excluded, &[], path)?;
+
+    writer.finish().map_err(|e| Error::ReportWrite {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+
+    Ok(())
+}
+
 fn write_header(writer: &mut impl Write, path: &Path) -> Result<()> {
     writeln!(writer, "# MaxAlign Results\n").map_err(write_err!(path))
 }
@@ -172,6 +467,18 @@ fn write_options_section(
             option: "Output file".to_string(),
             value: config.output_path.clone(),
         },
+        RunOption {
+            option: "Input format".to_string(),
+            value: config.input_format.to_string(),
+        },
+        RunOption {
+            option: "Output format".to_string(),
+            value: config.output_format.to_string(),
+        },
+        RunOption {
+            option: "Gap characters".to_string(),
+            value: format!("`{}`", config.gap_chars),
+        },
         RunOption {
             option: "Heuristic method".to_string(),
             value: config.heuristic_method.to_string(),
@@ -192,6 +499,36 @@ fn write_options_section(
             option: "Refinement".to_string(),
             value: config.refinement.to_string(),
         },
+        RunOption {
+            option: "Refinement time limit".to_string(),
+            value: config
+                .refinement_time_limit
+                .map_or_else(|| "none".to_string(), |limit| format!("{limit} s")),
+        },
+        RunOption {
+            option: "Refinement node limit".to_string(),
+            value: config
+                .refinement_node_limit
+                .map_or_else(|| "none".to_string(), |limit| limit.to_string()),
+        },
+        RunOption {
+            option: "Sequence weights".to_string(),
+            value: config
+                .sequence_weights
+                .clone()
+                .unwrap_or_else(|| "none".to_string()),
+        },
+        RunOption {
+            option: "Column weights".to_string(),
+            value: config
+                .column_weights
+                .clone()
+                .unwrap_or_else(|| "none".to_string()),
+        },
+        RunOption {
+            option: "Column gap tolerance".to_string(),
+            value: config.gap_tolerance.to_string(),
+        },
         RunOption {
             option: "Keep sequences".to_string(),
             value: if config.keep_sequence.is_empty() {
@@ -202,6 +539,55 @@ fn write_options_section(
         },
     ];
 
+    if !config.exclude_sequence.is_empty() {
+        options.push(RunOption {
+            option: "Excluded in advance".to_string(),
+            value: config.exclude_sequence.join(", "),
+        });
+    }
+
+    if config.match_description {
+        options.push(RunOption {
+            option: "Match description lines".to_string(),
+            value: "yes".to_string(),
+        });
+    }
+
+    if let Some(max_excluded) = config.max_excluded {
+        options.push(RunOption {
+            option: "Maximum excluded sequences".to_string(),
+            value: max_excluded.to_string(),
+        });
+    }
+
+    if let Some(ref groups) = config.groups {
+        options.push(RunOption {
+            option: "Sequence groups".to_string(),
+            value: groups.clone(),
+        });
+    }
+
+    if config.beam_width > 1 {
+        options.push(RunOption {
+            option: "Beam width".to_string(),
+            value: config.beam_width.to_string(),
+        });
+    }
+
+    if let Some(polish) = config.polish {
+        options.push(RunOption {
+            option: "Polishing".to_string(),
+            value: if polish.anneal_steps > 0 {
+                format!(
+                    "hill climbing with {} simulated annealing steps (seed {})",
+                    polish.anneal_steps, polish.seed
+                )
+            } else {
+                "hill climbing".to_string()
+            },
+        });
+    }
+
     if let Some(ref retained) = config.retained_sequences {
         options.push(RunOption {
             option: "Retained sequences file".to_string(),
@@ -216,6 +602,62 @@ fn write_options_section(
         });
     }
 
+    if let Some(ref pareto_front) = config.pareto_front {
+        options.push(RunOption {
+            option: "Pareto front file".to_string(),
+            value: pareto_front.clone(),
+        });
+    }
+
+    if let Some(min_retained) = config.pareto_select {
+        options.push(RunOption {
+            option: "Pareto selection".to_string(),
+            value: format!("at least {min_retained} retained sequences"),
+        });
+    }
+
+    if let Some(ref solutions) = config.solutions {
+        options.push(RunOption {
+            option: "Solutions file".to_string(),
+            value: solutions.clone(),
+        });
+    }
+
+    if let Some(count) = config.top_solutions {
+        options.push(RunOption {
+            option: "Top solutions".to_string(),
+            value: count.to_string(),
+        });
+    }
+
+    if let Some(index) = config.solution_index {
+        options.push(RunOption {
+            option: "Solution index".to_string(),
+            value: index.to_string(),
+        });
+    }
+
+    if let Some(ref model) = config.export_model {
+        options.push(RunOption {
+            option: "Model file".to_string(),
+            value: model.clone(),
+        });
+    }
+
+    if let Some(ref solution) = config.import_solution {
+        options.push(RunOption {
+            option: "Model solution file".to_string(),
+            value: solution.clone(),
+        });
+    }
+
+    if let Some(ref solution) = config.solve_model {
+        options.push(RunOption {
+            option: "Bundled model solver".to_string(),
+            value: format!("solution written to {solution}"),
+        });
+    }
+
     options.push(RunOption {
         option: "Report file".to_string(),
         value: report_path.display().to_string(),
@@ -224,51 +666,52 @@ fn write_options_section(
     writeln!(writer, "{}", as_table(&options)).map_err(write_err!(report_path))
 }
 
+/// Returns the name and value of each metric of the statistics table.
+pub(crate) const fn metric_rows(metrics: &AlignmentMetrics) -> [(&'static str, usize); 4] {
+    [
+        ("Number of sequences", metrics.sequence_count),
+        ("Alignment area", metrics.alignment_area),
+        ("Ungapped columns", metrics.gap_free_columns),
+        ("Total columns", metrics.alignment_length),
+    ]
+}
+
 #[allow(clippy::cast_possible_wrap)]
 fn write_statistics_section(
     writer: &mut impl Write,
     initial_metrics: &AlignmentMetrics,
     final_metrics: &AlignmentMetrics,
+    weighted_area_scale: Option<usize>,
     path: &Path,
 ) -> Result<()> {
     writeln!(writer, "## Statistics\n").map_err(write_err!(path))?;
 
-    let sequences_change =
-        final_metrics.sequence_count as i64 - initial_metrics.sequence_count as i64;
-    let area_change = final_metrics.alignment_area as i64 - initial_metrics.alignment_area as i64;
-    let freecols_change =
-        final_metrics.gap_free_columns as i64 - initial_metrics.gap_free_columns as i64;
-    let totalcols_change =
-        final_metrics.alignment_length as i64 - initial_metrics.alignment_length as i64;
-
-    let statistics = vec![
-        Statistic {
-            metric: "Number of sequences".to_string(),
-            before: initial_metrics.sequence_count,
-            after: final_metrics.sequence_count,
-            change: sequences_change,
-        },
-        Statistic {
-            metric: "Alignment area".to_string(),
-            before: initial_metrics.alignment_area,
-            after: final_metrics.alignment_area,
-            change: area_change,
-        },
-        Statistic {
-            metric: "Ungapped columns".to_string(),
-            before: initial_metrics.gap_free_columns,
-            after: final_metrics.gap_free_columns,
-            change: freecols_change,
-        },
-        Statistic {
-            metric: "Total columns".to_string(),
-            before: initial_metrics.alignment_length,
-            after: final_metrics.alignment_length,
-            change: totalcols_change,
-        },
-    ];
+    let statistics: Vec<Statistic> = metric_rows(initial_metrics)
+        .into_iter()
+        .zip(metric_rows(final_metrics))
+        .map(|((metric, before), (_, after))| Statistic {
+            metric: metric.to_string(),
+            before,
+            after,
+            change: after as i64 - before as i64,
+        })
+        .collect();
+
+    writeln!(writer, "{}", as_table(&statistics)).map_err(write_err!(path))?;
 
-    writeln!(writer, "{}", as_table(&statistics)).map_err(write_err!(path))
+    if let Some(scale) = weighted_area_scale {
+        writeln!(
+            writer,
+            "The weighted alignment area (total weight of the retained sequences \
+             multiplied by the total weight of the ungapped columns) changed from \
+             {:.3} to {:.3}.\n",
+            to_weighted_area(initial_metrics, scale),
+            to_weighted_area(final_metrics, scale)
+        )
+        .map_err(write_err!(path))?;
+    }
+
+    Ok(())
 }
 
 fn write_iterations_section(
@@ -291,11 +734,7 @@ fn write_iterations_section(
         for (i, (excluded_seqs, align_area)) in iteration_data.iter().enumerate() {
             cumulative_excluded += excluded_seqs.len();
             let remaining_seqs = initial_metrics.sequence_count - cumulative_excluded;
-            let freecols = if remaining_seqs > 0 {
-                align_area / remaining_seqs
-            } else {
-                0
-            };
+            let freecols = align_area.checked_div(remaining_seqs).unwrap_or(0);
             iterations.push(IterationRecord {
                 number: i + 1,
                 excluded_this_round: excluded_seqs.len(),
@@ -308,11 +747,52 @@ fn write_iterations_section(
     }
 }
 
+fn write_polish_section(
+    writer: &mut impl Write,
+    polish_moves: &[PolishMove],
+    headers: &[Vec<u8>],
+    path: &Path,
+) -> Result<()> {
+    writeln!(writer, "## Polishing\n").map_err(write_err!(path))?;
+
+    if polish_moves.is_empty() {
+        return writeln!(
+            writer,
+            "No re-insertion, exclusion or swap of sequences enlarged the area of the \
+             heuristic solution.\n"
+        )
+        .map_err(write_err!(path));
+    }
+
+    let names = |indices: &[usize]| {
+        indices
+            .iter()
+            .map(|&idx| get_record_accession_string(&headers[idx]).unwrap_or_default())
+            .join(", ")
+    };
+    let moves: Vec<PolishRecord> = polish_moves
+        .iter()
+        .enumerate()
+        .map(|(i, polish_move)| PolishRecord {
+            number: i + 1,
+            excluded: names(&polish_move.excluded),
+            reinserted: names(&polish_move.reinserted),
+            total_excluded: polish_move.total_excluded,
+            ungapped_columns: polish_move.counted_columns,
+            alignment_area: polish_move.alignment_area,
+        })
+        .collect();
+    writeln!(writer, "{}", as_table(&moves)).map_err(write_err!(path))
+}
+
+#[allow(clippy::cast_precision_loss)]
 fn write_refinement_section(
     writer: &mut impl Write,
     config: &ReportConfig<'_>,
     heuristic_metrics: &AlignmentMetrics,
     final_metrics: &AlignmentMetrics,
+    status: Option<&RefinementStatus>,
+    weighted_area_scale: Option<usize>,
     path: &Path,
 ) -> Result<()> {
     if !config.refinement {
@@ -321,6 +801,64 @@ fn write_refinement_section(
 
     writeln!(writer, "## Refinement\n").map_err(write_err!(path))?;
 
+    if let Some(status) = status.filter(|status| !status.is_optimal()) {
+        let format_area = |units: usize| {
+            weighted_area_scale.map_or_else(
+                || units.to_string(),
+                |scale| format!("{:.3}", units as f64 / scale as f64),
+            )
+        };
+        let area_name = if weighted_area_scale.is_some() {
+            "weighted alignment area"
+        } else {
+            "alignment area"
+        };
+        let outcome = if heuristic_metrics.weighted_area == final_metrics.weighted_area {
+            "the heuristic solution was not improved".to_string()
+        } else {
+            format!(
+                "the heuristic solution was improved from {}",
+                format_area(heuristic_metrics.weighted_area)
+            )
+        };
+        return writeln!(
+            writer,
+            "The branch-and-bound search stopped at its {} limit after exploring {} nodes, \
+             so the result was not proven optimal. The best {area_name} found is {} \
+             ({outcome}), and no solution can exceed {} (optimality gap: {:.2}%).\n",
+            status
+                .limit_reached
+                .map_or_else(String::new, |limit| limit.to_string()),
+            status.nodes,
+            format_area(status.best_area),
+            format_area(status.upper_bound),
+            status.optimality_gap() * 100.0
+        )
+        .map_err(write_err!(path));
+    }
+
+    if let Some(scale) = weighted_area_scale {
+        let (before, after) = (
+            to_weighted_area(heuristic_metrics, scale),
+            to_weighted_area(final_metrics, scale),
+        );
+        return if heuristic_metrics.weighted_area == final_metrics.weighted_area {
+            writeln!(
+                writer,
+                "The solution found with the heuristic method is optimal, as \
+                 determined by the branch-and-bound algorithm. The weighted \
+                 alignment area remains {before:.3}.\n"
+            )
+        } else {
+            writeln!(
+                writer,
+                "The heuristic solution was improved by the branch-and-bound algorithm. \
+                 The weighted alignment area increased from {before:.3} to {after:.3}.\n"
+            )
+        }
+        .map_err(write_err!(path));
+    }
+
     if heuristic_metrics.alignment_area == final_metrics.alignment_area {
         writeln!(
             writer,
@@ -341,17 +879,213 @@ fn write_refinement_section(
     }
 }
 
+#[allow(clippy::cast_precision_loss)]
+fn write_pareto_section(
+    writer: &mut impl Write,
+    front: &[ParetoPoint],
+    pareto_select: Option<usize>,
+    final_metrics: &AlignmentMetrics,
+    weighted_area_scale: Option<usize>,
+    path: &Path,
+) -> Result<()> {
+    if front.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(
+        writer,
+        "## Pareto front
+"
+    )
+    .map_err(write_err!(path))?;
+
+    let area_name = if weighted_area_scale.is_some() {
+        "weighted alignment area"
+    } else {
+        "alignment area"
+    };
+    writeln!(
+        writer,
+        "Solutions for which no other solution found retains at least as many \
+         sequences with a larger {area_name}.\n"
+    )
+    .map_err(write_err!(path))?;
+
+    let records: Vec<ParetoRecord> = front
+        .iter()
+        .map(|point| ParetoRecord {
+            retained: point.metrics.sequence_count,
+            ungapped_columns: point.metrics.gap_free_columns,
+            area: weighted_area_scale.map_or_else(
+                || point.metrics.alignment_area.to_string(),
+                |scale| format!("{:.3}", to_weighted_area(&point.metrics, scale)),
+            ),
+            found_by: point.source.to_string(),
+        })
+        .collect();
+    writeln!(writer, "{}", as_table(&records)).map_err(write_err!(path))?;
+
+    if let Some(min_retained) = pareto_select {
+        writeln!(
+            writer,
+            "The output alignment is the solution with the largest {area_name} among \
+             those that retain at least {min_retained} sequences ({} sequences retained).\n",
+            final_metrics.sequence_count
+        )
+        .map_err(write_err!(path))?;
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn write_solutions_section(
+    writer: &mut impl Write,
+    solutions: &[Solution],
+    config: &ReportConfig<'_>,
+    weighted_area_scale: Option<usize>,
+    path: &Path,
+) -> Result<()> {
+    if solutions.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(writer, "## Refinement solutions\n").map_err(write_err!(path))?;
+
+    let area_name = if weighted_area_scale.is_some() {
+        "weighted alignment area"
+    } else {
+        "alignment area"
+    };
+    // Fewer solutions than requested may exist.
+    let description = match config.top_solutions {
+        Some(_) => format!(
+            "The {} solutions with the largest {area_name} found by the \
+             branch-and-bound algorithm.",
+            solutions.len()
+        ),
+        None => format!(
+            "Solutions found by the branch-and-bound algorithm that reach the \
+             largest {area_name}."
+        ),
+    };
+    writeln!(
+        writer,
+        "{description} Solution 1 is the one returned by default.\n"
+    )
+    .map_err(write_err!(path))?;
+
+    let records: Vec<SolutionRecord> = solutions
+        .iter()
+        .enumerate()
+        .map(|(idx, solution)| SolutionRecord {
+            index: idx + 1,
+            retained: solution.metrics.sequence_count,
+            ungapped_columns: solution.metrics.gap_free_columns,
+            area: weighted_area_scale.map_or_else(
+                || solution.metrics.alignment_area.to_string(),
+                |scale| format!("{:.3}", to_weighted_area(&solution.metrics, scale)),
+            ),
+        })
+        .collect();
+    writeln!(writer, "{}", as_table(&records)).map_err(write_err!(path))?;
+
+    if let Some(index) = config.solution_index {
+        writeln!(writer, "The output alignment is solution {index}.\n")
+            .map_err(write_err!(path))?;
+    }
+
+    Ok(())
+}
+
+fn write_model_section(
+    writer: &mut impl Write,
+    model: &ReducedModel,
+    config: &ReportConfig<'_>,
+    final_metrics: &AlignmentMetrics,
+    weighted_area_scale: Option<usize>,
+    path: &Path,
+) -> Result<()> {
+    writeln!(writer, "## Reduced model\n").map_err(write_err!(path))?;
+
+    let area = |area: usize| {
+        weighted_area_scale.map_or_else(
+            || area.to_string(),
+            |scale| {
+                #[allow(clippy::cast_precision_loss)]
+                let area = area as f64 / scale as f64;
+                format!("{area:.3}")
+            },
+        )
+    };
+    writeln!(
+        writer,
+        "The problem reduced for an area of {} has {} candidate sequences for exclusion \
+         and {} column patterns. Its model has {} variables and {} constraints.\n",
+        area(model.min_area),
+        model.candidates.len(),
+        model.patterns.len(),
+        model.num_variables(),
+        model.num_constraints()
+    )
+    .map_err(write_err!(path))?;
+
+    let source = config
+        .import_solution
+        .as_ref()
+        .map(|solution| format!("the solution read from {solution}"))
+        .or_else(|| {
+            config
+                .solve_model
+                .as_ref()
+                .map(|_| "the optimal solution found by the bundled solver".to_string())
+        });
+    if let Some(source) = source {
+        writeln!(
+            writer,
+            "The output alignment is {source}, with an area of {}.\n",
+            area(final_metrics.weighted_area)
+        )
+        .map_err(write_err!(path))?;
+    }
+
+    Ok(())
+}
+
+fn write_groups_section(
+    writer: &mut impl Write,
+    groups: &[GroupSummary],
+    path: &Path,
+) -> Result<()> {
+    writeln!(writer, "## Groups\n").map_err(write_err!(path))?;
+    let records: Vec<GroupRecord> = groups
+        .iter()
+        .map(|group| GroupRecord {
+            name: group.name.clone(),
+            size: group.size,
+            required: group.required,
+            retained: group.retained,
+            excluded: group.excluded,
+        })
+        .collect();
+    writeln!(writer, "{}", as_table(&records)).map_err(write_err!(path))
+}
+
 fn write_excluded_section(
     writer: &mut impl Write,
     headers: &[Vec<u8>],
     excluded: &HashSet<usize>,
+    pre_excluded: &[String],
     path: &Path,
 ) -> Result<()> {
     writeln!(writer, "## Excluded sequences\n").map_err(write_err!(path))?;
 
-    if excluded.is_empty() {
+    if excluded.is_empty() && pre_excluded.is_empty() {
         writeln!(writer, "No sequences were excluded.").map_err(write_err!(path))
     } else {
+        for name in pre_excluded {
+            writeln!(writer, "- {name} (excluded in advance)").map_err(write_err!(path))?;
+        }
         // Write excluded sequences as a simple bullet list (no indices).
         for name in excluded
             .iter()
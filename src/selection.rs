@@ -0,0 +1,98 @@
+//! Selection of sequences by accession, description line or regular
+//! expression, used to keep sequences or to exclude them in advance.
+
+use crate::error::{Error, Result};
+use crate::fasta::get_record_accession_string;
+use log::warn;
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Sequences selected by name or by regular expression.
+///
+/// Names and patterns are matched against the accession of each sequence (the
+/// first word of its header) or, with `match_description`, against its whole
+/// description line. Names must match exactly, while patterns only need to
+/// match part of the accession or description.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceSelection {
+    pub names: Vec<String>,
+    pub patterns: Vec<Regex>,
+    pub match_description: bool,
+}
+
+impl SequenceSelection {
+    /// Returns `true` if the selection can't match any sequence.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Returns the indices of the selected sequences. Names and patterns that
+    /// match no sequence are reported as warnings, introduced by `role` (such
+    /// as "Must-retain").
+    #[must_use]
+    pub fn select(&self, headers: &[Vec<u8>], role: &str) -> HashSet<usize> {
+        let names: HashSet<&str> = self.names.iter().map(String::as_str).collect();
+        let mut found_names: HashSet<String> = HashSet::new();
+        let mut found_patterns = vec![false; self.patterns.len()];
+        let mut selected = HashSet::new();
+
+        for (idx, header) in headers.iter().enumerate() {
+            let Some(key) = (if self.match_description {
+                Some(String::from_utf8_lossy(header).into_owned())
+            } else {
+                get_record_accession_string(header)
+            }) else {
+                continue;
+            };
+            if names.contains(key.as_str()) {
+                selected.insert(idx);
+                found_names.insert(key.clone());
+            }
+            for (pattern, found) in self.patterns.iter().zip(&mut found_patterns) {
+                if pattern.is_match(&key) {
+                    selected.insert(idx);
+                    *found = true;
+                }
+            }
+        }
+
+        for name in &self.names {
+            if !found_names.contains(name) {
+                warn!("{role} sequence '{name}' was not found in the input alignment");
+            }
+        }
+        for (pattern, _) in self
+            .patterns
+            .iter()
+            .zip(&found_patterns)
+            .filter(|&(_, &found)| !found)
+        {
+            warn!("{role} pattern '{pattern}' matched no sequence in the input alignment");
+        }
+
+        selected
+    }
+}
+
+/// Reads sequence names from a file with one name per line, such as the lists
+/// written by [`write_headers_list`](crate::output::write_headers_list). Empty
+/// lines and lines starting with `#` are ignored.
+pub fn read_names(path: &Path) -> Result<Vec<String>> {
+    let read_err = |e| Error::NamesRead {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let reader = crate::compression::open_file(path).map_err(read_err)?;
+    let mut names = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(read_err)?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            names.push(line.to_string());
+        }
+    }
+    Ok(names)
+}
@@ -0,0 +1,464 @@
+//! Statistics on the gap structure of an alignment, computed without running
+//! any optimization, to judge whether an alignment is worth optimizing.
+
+use crate::alignment::{
+    AlignmentMetrics, ColumnPatterns, GapChars, GapTolerance, create_gap_matrix,
+};
+use crate::bitops::Bitset;
+use crate::compression::Compression;
+use crate::error::Result;
+use crate::fasta::get_record_accession_string;
+use crate::report::metric_rows;
+use crate::weights::Weights;
+use markdown_tables::{MarkdownTableRow, as_table};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+/// Supported formats of the statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    /// Markdown tables, readable in a terminal.
+    Table,
+    /// Tab-separated tables, each introduced by a `#` comment line.
+    Tsv,
+    /// A single JSON object.
+    Json,
+}
+
+impl std::fmt::Display for StatsFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Table => "table",
+            Self::Tsv => "tsv",
+            Self::Json => "json",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl std::str::FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "table" => Ok(Self::Table),
+            "tsv" => Ok(Self::Tsv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "invalid statistics format '{s}': must be table, tsv, or json"
+            )),
+        }
+    }
+}
+
+impl StatsFormat {
+    /// Guesses the format from a file extension (`.tsv` or `.json`), ignoring
+    /// compression suffixes.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let path = if Compression::from_path(path) == Compression::None {
+            path.to_path_buf()
+        } else {
+            path.with_extension("")
+        };
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "tsv" | "tab" => Some(Self::Tsv),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Distinct gap patterns with the same number of sequences with gaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatternSize {
+    /// Number of sequences with a gap in the columns of these patterns.
+    pub sequences: usize,
+    /// Number of distinct patterns.
+    pub patterns: usize,
+    /// Number of columns with one of these patterns.
+    pub columns: usize,
+}
+
+/// Gap structure of an alignment.
+#[derive(Debug, Clone)]
+pub struct AlignmentStats {
+    /// Metrics of the whole alignment, as in the statistics of a report.
+    pub metrics: AlignmentMetrics,
+    /// Distinct gap patterns of the columns with gaps, by number of sequences
+    /// with gaps, in increasing order.
+    pub pattern_sizes: Vec<PatternSize>,
+    /// Number of gaps of each sequence.
+    pub sequence_gaps: Vec<usize>,
+    /// Number of sequences with a residue in each column.
+    pub column_residues: Vec<usize>,
+    /// Upper bound on the area of any selection of sequences, assuming that
+    /// every column counts as soon as enough of the retained sequences have a
+    /// residue in it.
+    pub area_upper_bound: usize,
+    /// Number of retained sequences for which the upper bound is reached (the
+    /// largest one in case of ties).
+    pub upper_bound_retained: usize,
+}
+
+impl AlignmentStats {
+    /// Computes the statistics of an alignment. Sequences shorter than the
+    /// longest one are treated as if they were padded with gaps.
+    #[must_use]
+    pub fn new(sequences: &[Vec<u8>], gap_chars: &GapChars, tolerance: GapTolerance) -> Self {
+        let num_sequences = sequences.len();
+        let alignment_length = sequences.iter().map(Vec::len).max().unwrap_or(0);
+        let gap_matrix = create_gap_matrix(sequences, alignment_length, gap_chars);
+
+        let patterns = ColumnPatterns::new(
+            &gap_matrix,
+            alignment_length,
+            &Weights::uniform(alignment_length),
+        );
+        let (columns, _) =
+            patterns.counted_columns(&Bitset::new(num_sequences), num_sequences, tolerance);
+        let metrics = AlignmentMetrics::new(
+            num_sequences,
+            columns,
+            columns * num_sequences,
+            alignment_length,
+        );
+
+        let mut sizes: BTreeMap<usize, PatternSize> = BTreeMap::new();
+        for (set, &count) in patterns.sets.iter().zip(&patterns.counts) {
+            let sequences = set.count_ones();
+            let size = sizes.entry(sequences).or_insert(PatternSize {
+                sequences,
+                patterns: 0,
+                columns: 0,
+            });
+            size.patterns += 1;
+            size.columns += count;
+        }
+
+        let sequence_gaps = gap_matrix
+            .iter()
+            .map(|row| row.iter().filter(|&&is_gap| is_gap).count())
+            .collect();
+        let mut column_residues = vec![num_sequences; alignment_length];
+        for row in &gap_matrix {
+            for (residues, &is_gap) in column_residues.iter_mut().zip(row) {
+                *residues -= usize::from(is_gap);
+            }
+        }
+
+        // A column can count with `retained` sequences only if enough of them
+        // have a residue in it, so the columns with at least that many residues
+        // bound the area.
+        let mut with_residues = vec![0; num_sequences + 2];
+        for &residues in &column_residues {
+            with_residues[residues] += 1;
+        }
+        for residues in (0..=num_sequences).rev() {
+            with_residues[residues] += with_residues[residues + 1];
+        }
+        let (area_upper_bound, upper_bound_retained) = (1..=num_sequences)
+            .map(|retained| {
+                let min_residues = retained
+                    .saturating_sub(tolerance.allowed_gaps(retained))
+                    .max(1);
+                (retained * with_residues[min_residues], retained)
+            })
+            .max()
+            .unwrap_or((0, 0));
+
+        Self {
+            metrics,
+            pattern_sizes: sizes.into_values().collect(),
+            sequence_gaps,
+            column_residues,
+            area_upper_bound,
+            upper_bound_retained,
+        }
+    }
+
+    /// Returns the number of distinct gap patterns of the columns with gaps.
+    #[must_use]
+    pub fn pattern_count(&self) -> usize {
+        self.pattern_sizes.iter().map(|size| size.patterns).sum()
+    }
+
+    /// Returns the fraction of the columns in which each sequence has a gap.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn sequence_gap_fractions(&self) -> Vec<f64> {
+        let length = self.metrics.alignment_length.max(1) as f64;
+        self.sequence_gaps
+            .iter()
+            .map(|&gaps| gaps as f64 / length)
+            .collect()
+    }
+
+    /// Returns the fraction of the sequences with a residue in each column.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn column_occupancy(&self) -> Vec<f64> {
+        let num_sequences = self.metrics.sequence_count.max(1) as f64;
+        self.column_residues
+            .iter()
+            .map(|&residues| residues as f64 / num_sequences)
+            .collect()
+    }
+}
+
+struct StatRecord {
+    name: String,
+    value: String,
+}
+
+impl MarkdownTableRow for StatRecord {
+    fn column_names() -> Vec<&'static str> {
+        vec!["Metric", "Value"]
+    }
+
+    fn column_values(&self) -> Vec<String> {
+        vec![self.name.clone(), self.value.clone()]
+    }
+}
+
+struct PatternRecord(PatternSize);
+
+impl MarkdownTableRow for PatternRecord {
+    fn column_names() -> Vec<&'static str> {
+        vec!["Sequences with gaps", "Patterns", "Columns"]
+    }
+
+    fn column_values(&self) -> Vec<String> {
+        vec![
+            self.0.sequences.to_string(),
+            self.0.patterns.to_string(),
+            self.0.columns.to_string(),
+        ]
+    }
+}
+
+struct SequenceRecord {
+    name: String,
+    gaps: usize,
+    gap_fraction: f64,
+}
+
+impl MarkdownTableRow for SequenceRecord {
+    fn column_names() -> Vec<&'static str> {
+        vec!["Sequence", "Gaps", "Gap fraction"]
+    }
+
+    fn column_values(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.gaps.to_string(),
+            format!("{:.3}", self.gap_fraction),
+        ]
+    }
+}
+
+struct ColumnRecord {
+    column: usize,
+    residues: usize,
+    occupancy: f64,
+}
+
+impl MarkdownTableRow for ColumnRecord {
+    fn column_names() -> Vec<&'static str> {
+        vec!["Column", "Residues", "Occupancy"]
+    }
+
+    fn column_values(&self) -> Vec<String> {
+        vec![
+            self.column.to_string(),
+            self.residues.to_string(),
+            format!("{:.3}", self.occupancy),
+        ]
+    }
+}
+
+/// Writes the statistics of an alignment in the given format. Sequences are
+/// named by the accessions in `headers`, and columns are numbered from 1.
+pub fn write_stats(
+    writer: &mut impl Write,
+    stats: &AlignmentStats,
+    headers: &[Vec<u8>],
+    format: StatsFormat,
+) -> Result<()> {
+    let names: Vec<String> = headers
+        .iter()
+        .map(|header| get_record_accession_string(header).unwrap_or_default())
+        .collect();
+    match format {
+        StatsFormat::Table => write_tables(writer, stats, &names),
+        StatsFormat::Tsv => write_tsv(writer, stats, &names),
+        StatsFormat::Json => write_json(writer, stats, &names),
+    }
+}
+
+fn write_tables(writer: &mut impl Write, stats: &AlignmentStats, names: &[String]) -> Result<()> {
+    let mut records: Vec<StatRecord> = metric_rows(&stats.metrics)
+        .into_iter()
+        .map(|(name, value)| StatRecord {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+        .collect();
+    records.push(StatRecord {
+        name: "Gap patterns".to_string(),
+        value: stats.pattern_count().to_string(),
+    });
+    records.push(StatRecord {
+        name: "Area upper bound".to_string(),
+        value: format!(
+            "{} ({} sequences)",
+            stats.area_upper_bound, stats.upper_bound_retained
+        ),
+    });
+    writeln!(writer, "## Statistics\n")?;
+    writeln!(writer, "{}", as_table(&records))?;
+
+    writeln!(writer, "## Gap patterns\n")?;
+    if stats.pattern_sizes.is_empty() {
+        writeln!(writer, "No column has gaps.\n")?;
+    } else {
+        let records: Vec<PatternRecord> = stats
+            .pattern_sizes
+            .iter()
+            .copied()
+            .map(PatternRecord)
+            .collect();
+        writeln!(writer, "{}", as_table(&records))?;
+    }
+
+    writeln!(writer, "## Sequences\n")?;
+    let records: Vec<SequenceRecord> = names
+        .iter()
+        .zip(&stats.sequence_gaps)
+        .zip(stats.sequence_gap_fractions())
+        .map(|((name, &gaps), gap_fraction)| SequenceRecord {
+            name: name.clone(),
+            gaps,
+            gap_fraction,
+        })
+        .collect();
+    writeln!(writer, "{}", as_table(&records))?;
+
+    if !stats.column_residues.is_empty() {
+        writeln!(writer, "## Columns\n")?;
+        let records: Vec<ColumnRecord> = stats
+            .column_residues
+            .iter()
+            .zip(stats.column_occupancy())
+            .enumerate()
+            .map(|(idx, (&residues, occupancy))| ColumnRecord {
+                column: idx + 1,
+                residues,
+                occupancy,
+            })
+            .collect();
+        write!(writer, "{}", as_table(&records))?;
+    }
+    Ok(())
+}
+
+fn write_tsv(writer: &mut impl Write, stats: &AlignmentStats, names: &[String]) -> Result<()> {
+    let metrics = &stats.metrics;
+    writeln!(writer, "# statistics\nmetric\tvalue")?;
+    for (metric, value) in [
+        ("sequences", metrics.sequence_count),
+        ("alignment_area", metrics.alignment_area),
+        ("gap_free_columns", metrics.gap_free_columns),
+        ("total_columns", metrics.alignment_length),
+        ("gap_patterns", stats.pattern_count()),
+        ("area_upper_bound", stats.area_upper_bound),
+        ("upper_bound_retained", stats.upper_bound_retained),
+    ] {
+        writeln!(writer, "{metric}\t{value}")?;
+    }
+
+    writeln!(
+        writer,
+        "\n# gap_patterns\nsequences_with_gaps\tpatterns\tcolumns"
+    )?;
+    for size in &stats.pattern_sizes {
+        writeln!(
+            writer,
+            "{}\t{}\t{}",
+            size.sequences, size.patterns, size.columns
+        )?;
+    }
+
+    writeln!(writer, "\n# sequences\nsequence\tgaps\tgap_fraction")?;
+    for ((name, gaps), fraction) in names
+        .iter()
+        .zip(&stats.sequence_gaps)
+        .zip(stats.sequence_gap_fractions())
+    {
+        writeln!(writer, "{name}\t{gaps}\t{fraction:.4}")?;
+    }
+
+    writeln!(writer, "\n# columns\ncolumn\tresidues\toccupancy")?;
+    for (idx, (residues, occupancy)) in stats
+        .column_residues
+        .iter()
+        .zip(stats.column_occupancy())
+        .enumerate()
+    {
+        writeln!(writer, "{}\t{residues}\t{occupancy:.4}", idx + 1)?;
+    }
+    Ok(())
+}
+
+fn write_json(writer: &mut impl Write, stats: &AlignmentStats, names: &[String]) -> Result<()> {
+    let metrics = &stats.metrics;
+    let value = serde_json::json!({
+        "sequences": metrics.sequence_count,
+        "alignment_area": metrics.alignment_area,
+        "gap_free_columns": metrics.gap_free_columns,
+        "total_columns": metrics.alignment_length,
+        "gap_patterns": stats.pattern_count(),
+        "area_upper_bound": stats.area_upper_bound,
+        "upper_bound_retained": stats.upper_bound_retained,
+        "pattern_sizes": stats
+            .pattern_sizes
+            .iter()
+            .map(|size| {
+                serde_json::json!({
+                    "sequences_with_gaps": size.sequences,
+                    "patterns": size.patterns,
+                    "columns": size.columns,
+                })
+            })
+            .collect::<Vec<_>>(),
+        "sequence_gaps": names
+            .iter()
+            .zip(&stats.sequence_gaps)
+            .zip(stats.sequence_gap_fractions())
+            .map(|((name, gaps), fraction)| {
+                serde_json::json!({
+                    "sequence": name,
+                    "gaps": gaps,
+                    "gap_fraction": fraction,
+                })
+            })
+            .collect::<Vec<_>>(),
+        "column_occupancy": stats
+            .column_residues
+            .iter()
+            .zip(stats.column_occupancy())
+            .enumerate()
+            .map(|(idx, (residues, occupancy))| {
+                serde_json::json!({
+                    "column": idx + 1,
+                    "residues": residues,
+                    "occupancy": occupancy,
+                })
+            })
+            .collect::<Vec<_>>(),
+    });
+    serde_json::to_writer_pretty(&mut *writer, &value).map_err(std::io::Error::from)?;
+    writeln!(writer)?;
+    Ok(())
+}
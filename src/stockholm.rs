@@ -0,0 +1,317 @@
+//! Stockholm file parsing utilities.
+//!
+//! Besides the aligned sequences, Stockholm files carry `#=GF` (per-file),
+//! `#=GS` (per-sequence), `#=GR` (per-residue) and `#=GC` (per-column)
+//! annotation lines. These are kept in [`StockholmAnnotations`] so that they
+//! can be written back after sequences and columns are removed.
+
+use crate::error::{Error, Result};
+use crate::fasta::{SequenceData, build_sequence_data};
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+/// The header line that opens every Stockholm alignment.
+pub const STOCKHOLM_HEADER: &[u8] = b"# STOCKHOLM";
+
+/// A `#=GF <feature> <text>` line.
+#[derive(Debug, Clone)]
+pub struct FileAnnotation {
+    pub feature: Vec<u8>,
+    pub text: Vec<u8>,
+}
+
+/// A `#=GS <seqname> <feature> <text>` line.
+#[derive(Debug, Clone)]
+pub struct SequenceAnnotation {
+    pub name: Vec<u8>,
+    pub feature: Vec<u8>,
+    pub text: Vec<u8>,
+}
+
+/// A `#=GR <seqname> <feature> <per-residue data>` line.
+#[derive(Debug, Clone)]
+pub struct ResidueAnnotation {
+    pub name: Vec<u8>,
+    pub feature: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// A `#=GC <feature> <per-column data>` line.
+#[derive(Debug, Clone)]
+pub struct ColumnAnnotation {
+    pub feature: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// Annotation lines of a Stockholm alignment, in input order. Per-residue and
+/// per-column annotations split across several blocks are concatenated.
+#[derive(Debug, Clone, Default)]
+pub struct StockholmAnnotations {
+    pub file: Vec<FileAnnotation>,
+    pub sequence: Vec<SequenceAnnotation>,
+    pub residue: Vec<ResidueAnnotation>,
+    pub column: Vec<ColumnAnnotation>,
+}
+
+impl StockholmAnnotations {
+    /// Returns the annotations that remain after filtering the alignment.
+    ///
+    /// `#=GS` and `#=GR` lines are only kept for sequences in `retained_names`,
+    /// and per-residue and per-column data is restricted to the columns for
+    /// which `kept_columns` is `true`.
+    #[must_use]
+    pub fn filter(&self, retained_names: &HashSet<&[u8]>, kept_columns: &[bool]) -> Self {
+        let filter_columns = |data: &[u8]| -> Vec<u8> {
+            data.iter()
+                .zip(kept_columns)
+                .filter(|&(_, &keep)| keep)
+                .map(|(&byte, _)| byte)
+                .collect()
+        };
+
+        Self {
+            file: self.file.clone(),
+            sequence: self
+                .sequence
+                .iter()
+                .filter(|gs| retained_names.contains(gs.name.as_slice()))
+                .cloned()
+                .collect(),
+            residue: self
+                .residue
+                .iter()
+                .filter(|gr| retained_names.contains(gr.name.as_slice()))
+                .map(|gr| ResidueAnnotation {
+                    name: gr.name.clone(),
+                    feature: gr.feature.clone(),
+                    data: filter_columns(&gr.data),
+                })
+                .collect(),
+            column: self
+                .column
+                .iter()
+                .map(|gc| ColumnAnnotation {
+                    feature: gc.feature.clone(),
+                    data: filter_columns(&gc.data),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Splits off the first whitespace-delimited field of a line, returning the
+/// field and the remainder with leading whitespace removed.
+pub(crate) fn split_field(line: &[u8]) -> (&[u8], &[u8]) {
+    let line = line.trim_ascii_start();
+    let end = line
+        .iter()
+        .position(u8::is_ascii_whitespace)
+        .unwrap_or(line.len());
+    (&line[..end], line[end..].trim_ascii_start())
+}
+
+/// Parses the first alignment of a Stockholm file and returns the sequence data.
+pub fn parse_stockholm(mut reader: impl BufRead, keep_sequence: &[String]) -> Result<SequenceData> {
+    let mut headers: Vec<Vec<u8>> = Vec::new();
+    let mut sequences: Vec<Vec<u8>> = Vec::new();
+    let mut sequence_index: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut residue_index: HashMap<(Vec<u8>, Vec<u8>), usize> = HashMap::new();
+    let mut column_index: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut annotations = StockholmAnnotations::default();
+
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    let mut seen_header = false;
+
+    loop {
+        line.clear();
+        let bytes_read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| Error::StockholmParse(e.to_string()))?;
+        if bytes_read == 0 {
+            break;
+        }
+        line_number += 1;
+
+        let trimmed = line.trim_ascii_end();
+        if trimmed.trim_ascii_start().is_empty() {
+            continue;
+        }
+
+        if !seen_header {
+            if !trimmed.starts_with(STOCKHOLM_HEADER) {
+                return Err(Error::StockholmParse(format!(
+                    "line {line_number}: expected a '# STOCKHOLM' header line"
+                )));
+            }
+            seen_header = true;
+            continue;
+        }
+
+        if trimmed == b"//" {
+            break;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix(b"#=GF") {
+            let (feature, text) = split_field(rest);
+            annotations.file.push(FileAnnotation {
+                feature: feature.to_vec(),
+                text: text.to_vec(),
+            });
+        } else if let Some(rest) = trimmed.strip_prefix(b"#=GS") {
+            let (name, rest) = split_field(rest);
+            let (feature, text) = split_field(rest);
+            annotations.sequence.push(SequenceAnnotation {
+                name: name.to_vec(),
+                feature: feature.to_vec(),
+                text: text.to_vec(),
+            });
+        } else if let Some(rest) = trimmed.strip_prefix(b"#=GR") {
+            let (name, rest) = split_field(rest);
+            let (feature, data) = split_field(rest);
+            let key = (name.to_vec(), feature.to_vec());
+            if let Some(&idx) = residue_index.get(&key) {
+                annotations.residue[idx].data.extend_from_slice(data);
+            } else {
+                residue_index.insert(key, annotations.residue.len());
+                annotations.residue.push(ResidueAnnotation {
+                    name: name.to_vec(),
+                    feature: feature.to_vec(),
+                    data: data.to_vec(),
+                });
+            }
+        } else if let Some(rest) = trimmed.strip_prefix(b"#=GC") {
+            let (feature, data) = split_field(rest);
+            if let Some(&idx) = column_index.get(feature) {
+                annotations.column[idx].data.extend_from_slice(data);
+            } else {
+                column_index.insert(feature.to_vec(), annotations.column.len());
+                annotations.column.push(ColumnAnnotation {
+                    feature: feature.to_vec(),
+                    data: data.to_vec(),
+                });
+            }
+        } else if trimmed.starts_with(b"#") {
+            continue;
+        } else {
+            let (name, data) = split_field(trimmed);
+            let mut data = data.to_vec();
+            data.retain(|&b| !b.is_ascii_whitespace());
+            if let Some(&idx) = sequence_index.get(name) {
+                sequences[idx].extend_from_slice(&data);
+            } else {
+                sequence_index.insert(name.to_vec(), sequences.len());
+                headers.push(name.to_vec());
+                sequences.push(data);
+            }
+        }
+    }
+
+    if !seen_header {
+        return Err(Error::EmptyInput);
+    }
+
+    let mut sequence_data = build_sequence_data(headers, sequences, keep_sequence)?;
+    sequence_data.annotations = Some(annotations);
+    Ok(sequence_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::write_stockholm;
+
+    const ALIGNMENT: &str = "# STOCKHOLM 1.0
+#=GF ID test
+#=GS seq1 DE first sequence
+
+seq1         AC-GT
+#=GR seq1 SS HH-EE
+seq2         ACTG-
+#=GC SS_cons HHHEE
+
+seq1         TT
+#=GR seq1 SS CC
+seq2         T-
+#=GC SS_cons CC
+//
+";
+
+    fn parse(text: &str) -> Result<SequenceData> {
+        parse_stockholm(text.as_bytes(), &[])
+    }
+
+    #[test]
+    fn parses_blocks_and_annotations() {
+        let data = parse(ALIGNMENT).unwrap();
+        assert_eq!(data.headers, [b"seq1".to_vec(), b"seq2".to_vec()]);
+        assert_eq!(data.sequences, [b"AC-GTTT".to_vec(), b"ACTG-T-".to_vec()]);
+        assert_eq!(data.longest_length, 7);
+
+        let annotations = data.annotations.unwrap();
+        assert_eq!(annotations.file[0].feature, b"ID");
+        assert_eq!(annotations.file[0].text, b"test");
+        assert_eq!(annotations.sequence[0].text, b"first sequence");
+        assert_eq!(annotations.residue.len(), 1);
+        assert_eq!(annotations.residue[0].data, b"HH-EECC");
+        assert_eq!(annotations.column[0].data, b"HHHEECC");
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_first_alignment() {
+        let text = format!("{ALIGNMENT}# STOCKHOLM 1.0\nseq3 AAAAAAA\n//\n");
+        assert_eq!(parse(&text).unwrap().headers.len(), 2);
+    }
+
+    #[test]
+    fn filters_annotations_of_removed_sequences_and_columns() {
+        let annotations = parse(ALIGNMENT).unwrap().annotations.unwrap();
+        let retained: HashSet<&[u8]> = HashSet::from([&b"seq2"[..]]);
+        let kept_columns = [true, false, true, true, false, true, false];
+        let filtered = annotations.filter(&retained, &kept_columns);
+        assert!(filtered.sequence.is_empty());
+        assert!(filtered.residue.is_empty());
+        assert_eq!(filtered.column[0].data, b"HHEC");
+        assert_eq!(filtered.file.len(), 1);
+    }
+
+    #[test]
+    fn round_trips_through_the_writer() {
+        let data = parse(ALIGNMENT).unwrap();
+        let mut written = Vec::new();
+        write_stockholm(
+            &data.sequences,
+            &data.headers,
+            data.annotations.as_ref(),
+            &mut written,
+        )
+        .unwrap();
+        let reread = parse_stockholm(written.as_slice(), &[]).unwrap();
+        assert_eq!(reread.headers, data.headers);
+        assert_eq!(reread.sequences, data.sequences);
+        let (annotations, reread_annotations) =
+            (data.annotations.unwrap(), reread.annotations.unwrap());
+        assert_eq!(
+            reread_annotations.residue[0].data,
+            annotations.residue[0].data
+        );
+        assert_eq!(
+            reread_annotations.column[0].data,
+            annotations.column[0].data
+        );
+        assert_eq!(reread_annotations.sequence[0].text, b"first sequence");
+    }
+
+    #[test]
+    fn rejects_a_missing_header() {
+        assert!(matches!(
+            parse("seq1 ACGT\n//\n"),
+            Err(Error::StockholmParse(_))
+        ));
+        assert!(matches!(parse("\n\n"), Err(Error::EmptyInput)));
+        assert!(matches!(
+            parse("# STOCKHOLM 1.0\n//\n"),
+            Err(Error::EmptyInput)
+        ));
+    }
+}
@@ -0,0 +1,632 @@
+//! Sequence and column weights for the alignment area objective.
+//!
+//! By default, every retained sequence contributes one row and every gap-free
+//! column one column to the alignment area. With sequence weights, rows
+//! contribute their weight, so that excluding one of many redundant sequences
+//! costs less than excluding a unique one. With column weights, gap-free
+//! columns contribute their weight, so that important columns (or only the
+//! columns of a mask) drive the optimization. Weights are stored as integers
+//! (in units of `1 / WEIGHT_SCALE`) so that the optimization remains exact.
+
+use crate::alignment::GapChars;
+use crate::bitops::{Bitset, word_ones};
+use crate::error::{Error, Result};
+use crate::fasta::get_record_accession_string;
+use crate::stockholm::split_field;
+use log::warn;
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+/// Number of integer weight units per unit of weight.
+pub const WEIGHT_SCALE: usize = 1000;
+
+/// Method used to compute sequence weights from an alignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceWeighting {
+    /// Position-based weights (Henikoff and Henikoff, 1994).
+    Henikoff,
+    /// Inverse of the number of sequences within an identity threshold.
+    Identity,
+}
+
+impl std::fmt::Display for SequenceWeighting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Henikoff => "henikoff",
+            Self::Identity => "identity",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl std::str::FromStr for SequenceWeighting {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "henikoff" => Ok(Self::Henikoff),
+            "identity" => Ok(Self::Identity),
+            _ => Err(format!(
+                "invalid weighting method '{s}': must be henikoff or identity"
+            )),
+        }
+    }
+}
+
+/// Integer weights of sequences or columns used in the area objective.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Weights {
+    count: usize,
+    units: Option<Vec<usize>>,
+}
+
+impl Weights {
+    /// Creates uniform weights, under which every sequence or column counts
+    /// once.
+    #[must_use]
+    pub const fn uniform(count: usize) -> Self {
+        Self { count, units: None }
+    }
+
+    /// Creates weights from floating-point values. Weights are rounded to
+    /// `1 / WEIGHT_SCALE`; positive weights below that become one unit, so that
+    /// they still count, and negative weights become zero.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn new(weights: &[f64]) -> Self {
+        let units = weights
+            .iter()
+            .map(|&w| {
+                if w > 0.0 {
+                    (w * WEIGHT_SCALE as f64).round().max(1.0) as usize
+                } else {
+                    0
+                }
+            })
+            .collect();
+        Self {
+            count: weights.len(),
+            units: Some(units),
+        }
+    }
+
+    /// Returns `true` if all elements have the same weight.
+    #[must_use]
+    pub const fn is_uniform(&self) -> bool {
+        self.units.is_none()
+    }
+
+    /// Returns the number of weighted elements.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns `true` if there are no weighted elements.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the number of integer units per unit of weight.
+    #[must_use]
+    pub const fn scale(&self) -> usize {
+        if self.units.is_some() {
+            WEIGHT_SCALE
+        } else {
+            1
+        }
+    }
+
+    /// Returns the weights of the elements at the given indices, in order.
+    #[must_use]
+    pub fn select(&self, indices: &[usize]) -> Self {
+        Self {
+            count: indices.len(),
+            units: self
+                .units
+                .as_ref()
+                .map(|units| indices.iter().map(|&idx| units[idx]).collect()),
+        }
+    }
+
+    /// Returns the weight of a single element, in integer units.
+    #[must_use]
+    pub fn get(&self, idx: usize) -> usize {
+        self.units.as_ref().map_or(1, |units| units[idx])
+    }
+
+    /// Returns the total weight of all elements, in integer units.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.units
+            .as_ref()
+            .map_or(self.count, |units| units.iter().sum())
+    }
+
+    /// Returns the total weight of the elements in a set.
+    #[must_use]
+    pub fn of_set(&self, set: &Bitset) -> usize {
+        match &self.units {
+            None => set.count_ones(),
+            Some(units) => sum_set_bits(units, set.words().iter().copied()),
+        }
+    }
+
+    /// Returns the total weight of the union of two sets.
+    #[must_use]
+    pub fn of_union(&self, a: &Bitset, b: &Bitset) -> usize {
+        match &self.units {
+            None => a.count_union(b),
+            Some(units) => {
+                sum_set_bits(units, a.words().iter().zip(b.words()).map(|(&x, &y)| x | y))
+            }
+        }
+    }
+
+    /// Returns the total weight of the union of three sets.
+    #[must_use]
+    pub fn of_union_triple(&self, a: &Bitset, b: &Bitset, c: &Bitset) -> usize {
+        match &self.units {
+            None => a.count_union_triple(b, c),
+            Some(units) => sum_set_bits(
+                units,
+                a.words()
+                    .iter()
+                    .zip(b.words())
+                    .zip(c.words())
+                    .map(|((&x, &y), &z)| x | y | z),
+            ),
+        }
+    }
+}
+
+fn sum_set_bits(units: &[usize], words: impl Iterator<Item = u64>) -> usize {
+    word_ones(words)
+        .map(|idx| units.get(idx).copied().unwrap_or(0))
+        .sum()
+}
+
+/// Reads sequence weights from a tab-separated file with the accession in the
+/// first column and the weight in the second. Empty lines and lines starting
+/// with `#` are ignored. Sequences that are not listed get a weight of 1.
+pub fn read_weights(path: &Path, headers: &[Vec<u8>]) -> Result<Vec<f64>> {
+    let read_err = |e| Error::WeightsRead {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let reader = crate::compression::open_file(path).map_err(read_err)?;
+
+    let mut listed: HashMap<String, f64> = HashMap::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(read_err)?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(accession), Some(weight)) = (fields.next(), fields.next()) else {
+            return Err(Error::WeightsParse(format!(
+                "line {} must contain an accession and a weight separated by a tab",
+                line_number + 1
+            )));
+        };
+        let weight: f64 = weight.trim().parse().map_err(|_| {
+            Error::WeightsParse(format!(
+                "invalid weight '{}' on line {}",
+                weight.trim(),
+                line_number + 1
+            ))
+        })?;
+        if !weight.is_finite() || weight <= 0.0 {
+            return Err(Error::WeightsParse(format!(
+                "weight on line {} must be a positive number",
+                line_number + 1
+            )));
+        }
+        listed.insert(accession.trim().to_string(), weight);
+    }
+
+    let mut found = 0;
+    let mut missing = 0;
+    let weights = headers
+        .iter()
+        .map(|header| {
+            let accession = get_record_accession_string(header).unwrap_or_default();
+            if let Some(&weight) = listed.get(&accession) {
+                found += 1;
+                weight
+            } else {
+                missing += 1;
+                1.0
+            }
+        })
+        .collect();
+
+    if missing > 0 {
+        warn!(
+            "{missing} sequence(s) are not listed in the weights file and were given a weight of 1"
+        );
+    }
+    if found < listed.len() {
+        warn!(
+            "{} sequence(s) listed in the weights file were not found in the input",
+            listed.len() - found
+        );
+    }
+
+    Ok(weights)
+}
+
+/// Reads column weights from a file with one weight per column, separated by
+/// whitespace. Lines starting with `#` are ignored.
+pub fn read_column_weights(path: &Path, alignment_length: usize) -> Result<Vec<f64>> {
+    let read_err = |e| Error::WeightsRead {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let reader = crate::compression::open_file(path).map_err(read_err)?;
+
+    let mut weights = Vec::with_capacity(alignment_length);
+    for line in reader.lines() {
+        let line = line.map_err(read_err)?;
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        for field in line.split_whitespace() {
+            let weight: f64 = field.parse().map_err(|_| {
+                Error::ColumnWeightsParse(format!("invalid column weight '{field}'"))
+            })?;
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(Error::ColumnWeightsParse(format!(
+                    "column weight '{field}' must be a non-negative number"
+                )));
+            }
+            weights.push(weight);
+        }
+    }
+
+    check_column_count(weights.len(), alignment_length)?;
+    Ok(weights)
+}
+
+/// Reads a column mask and returns it as column weights (1 for columns in the
+/// mask, 0 otherwise). The mask is either a list of BED-like ranges (0-based,
+/// end-exclusive, optionally preceded by a sequence name as in BED files) or a
+/// `#=GC`-style string with one character per column (see
+/// [`parse_mask_string`]).
+pub fn read_column_mask(path: &Path, alignment_length: usize) -> Result<Vec<f64>> {
+    let read_err = |e| Error::WeightsRead {
+        path: path.to_path_buf(),
+        source: e,
+    };
+    let mut reader = crate::compression::open_file(path).map_err(read_err)?;
+    let mut text = String::new();
+    reader.read_to_string(&mut text).map_err(read_err)?;
+
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            !line.is_empty()
+                && !line.starts_with("track")
+                && !line.starts_with("browser")
+                && (!line.starts_with('#') || line.starts_with("#=GC"))
+        })
+        .collect();
+
+    let is_range = |line: &&str| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        fields.len() >= 2
+            && (fields[..2].iter().all(|f| f.parse::<usize>().is_ok())
+                || (fields.len() >= 3 && fields[1..3].iter().all(|f| f.parse::<usize>().is_ok())))
+    };
+
+    if !lines.is_empty() && lines.iter().all(is_range) {
+        let mut weights = vec![0.0; alignment_length];
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let range = if fields[0].parse::<usize>().is_ok() {
+                &fields[..2]
+            } else {
+                &fields[1..3]
+            };
+            let (start, end): (usize, usize) = (
+                range[0].parse().unwrap_or_default(),
+                range[1].parse().unwrap_or_default(),
+            );
+            if start >= end || end > alignment_length {
+                return Err(Error::ColumnWeightsParse(format!(
+                    "invalid range {start}-{end} for an alignment of {alignment_length} columns"
+                )));
+            }
+            weights[start..end].fill(1.0);
+        }
+        Ok(weights)
+    } else {
+        let mut mask = Vec::with_capacity(alignment_length);
+        for line in lines {
+            let data = match line.strip_prefix("#=GC") {
+                Some(rest) => split_field(rest.as_bytes()).1,
+                None => line.as_bytes(),
+            };
+            mask.extend(data.iter().filter(|b| !b.is_ascii_whitespace()));
+        }
+        let weights = parse_mask_string(&mask);
+        check_column_count(weights.len(), alignment_length)?;
+        Ok(weights)
+    }
+}
+
+/// Converts a `#=GC`-style mask string (e.g., an `RF` line) to column weights.
+/// `.`, `-`, `_`, `~` and `0` mark columns outside the mask (weight 0);
+/// any other character marks a column in the mask (weight 1).
+#[must_use]
+pub fn parse_mask_string(mask: &[u8]) -> Vec<f64> {
+    mask.iter()
+        .map(|&byte| {
+            if matches!(byte, b'.' | b'-' | b'_' | b'~' | b'0') {
+                0.0
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+fn check_column_count(count: usize, alignment_length: usize) -> Result<()> {
+    if count == alignment_length {
+        Ok(())
+    } else {
+        Err(Error::ColumnWeightsParse(format!(
+            "expected {alignment_length} column weights, found {count}"
+        )))
+    }
+}
+
+/// Computes position-based sequence weights (Henikoff and Henikoff, 1994).
+///
+/// In each column, every residue contributes `1 / (r * s)` to the weight of its
+/// sequence, where `r` is the number of distinct residues in the column and `s`
+/// is the number of times the residue occurs. Gaps are ignored. The weights are
+/// normalized to a mean of 1.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn henikoff_weights(sequences: &[Vec<u8>], gap_chars: &GapChars) -> Vec<f64> {
+    let alignment_length = sequences.iter().map(Vec::len).max().unwrap_or(0);
+    let mut weights = vec![0.0; sequences.len()];
+    let mut counts = [0usize; 256];
+
+    for col in 0..alignment_length {
+        counts.fill(0);
+        for seq in sequences {
+            if let Some(&byte) = seq.get(col)
+                && !gap_chars.contains(byte)
+            {
+                counts[usize::from(byte.to_ascii_uppercase())] += 1;
+            }
+        }
+        let distinct = counts.iter().filter(|&&c| c > 0).count();
+        if distinct == 0 {
+            continue;
+        }
+        for (weight, seq) in weights.iter_mut().zip(sequences) {
+            if let Some(&byte) = seq.get(col)
+                && !gap_chars.contains(byte)
+            {
+                let count = counts[usize::from(byte.to_ascii_uppercase())];
+                *weight += 1.0 / (distinct * count) as f64;
+            }
+        }
+    }
+
+    normalize(weights)
+}
+
+/// Computes sequence weights as the inverse of the number of sequences
+/// (including itself) that share at least `threshold` identity with each
+/// sequence. Identity is computed over the columns in which both sequences have
+/// a residue. The weights are normalized to a mean of 1.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn identity_weights(sequences: &[Vec<u8>], gap_chars: &GapChars, threshold: f64) -> Vec<f64> {
+    let mut neighbours = vec![1usize; sequences.len()];
+
+    for i in 0..sequences.len() {
+        for j in i + 1..sequences.len() {
+            let (mut identical, mut aligned) = (0usize, 0usize);
+            for (&a, &b) in sequences[i].iter().zip(&sequences[j]) {
+                if gap_chars.contains(a) || gap_chars.contains(b) {
+                    continue;
+                }
+                aligned += 1;
+                if a.eq_ignore_ascii_case(&b) {
+                    identical += 1;
+                }
+            }
+            if aligned > 0 && identical as f64 / aligned as f64 >= threshold {
+                neighbours[i] += 1;
+                neighbours[j] += 1;
+            }
+        }
+    }
+
+    normalize(neighbours.iter().map(|&n| 1.0 / n as f64).collect())
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn normalize(mut weights: Vec<f64>) -> Vec<f64> {
+    let total: f64 = weights.iter().sum();
+    if total > 0.0 {
+        let factor = weights.len() as f64 / total;
+        for weight in &mut weights {
+            *weight *= factor;
+        }
+    }
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Writes a file to the temporary directory, named after the test.
+    fn write_file(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("maxalign-rs-{}-{name}", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn read_mask(name: &str, text: &str, alignment_length: usize) -> Result<Vec<f64>> {
+        let path = write_file(name, text);
+        let mask = read_column_mask(&path, alignment_length);
+        std::fs::remove_file(&path).unwrap();
+        mask
+    }
+
+    #[test]
+    fn reads_bed_ranges() {
+        let expected = [1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0];
+        assert_eq!(
+            read_mask("mask-ranges", "0\t3\n5 7\n", 8).unwrap(),
+            expected
+        );
+        assert_eq!(
+            read_mask("mask-bed", "chr1\t0\t3\tfirst\nchr1\t5\t7\n", 8).unwrap(),
+            expected
+        );
+        assert_eq!(
+            read_mask(
+                "mask-bed-headers",
+                "track name=mask\nbrowser position chr1:1-8\n# comment\n\nchr1\t0\t3\n5\t7\n",
+                8
+            )
+            .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn rejects_ranges_beyond_the_alignment() {
+        let error = read_mask("mask-end", "0\t3\n5\t9\n", 8).unwrap_err();
+        assert!(error.to_string().contains("invalid range 5-9"), "{error}");
+        let error = read_mask("mask-empty-range", "chr1\t4\t4\n", 8).unwrap_err();
+        assert!(error.to_string().contains("invalid range 4-4"), "{error}");
+    }
+
+    #[test]
+    fn reads_mask_strings() {
+        let expected = [1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0];
+        assert_eq!(
+            read_mask("mask-gc", "# mask\n#=GC RF xx..x\n#=GC RF x.x\n", 8).unwrap(),
+            expected
+        );
+        assert_eq!(
+            read_mask("mask-string", "xx..\nxx-x\n", 8).unwrap(),
+            expected
+        );
+        let error = read_mask("mask-length", "#=GC RF xx..x\n", 8).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("expected 8 column weights, found 5"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn parses_mask_characters() {
+        assert_eq!(
+            parse_mask_string(b".-_~0xX1A"),
+            [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0]
+        );
+        assert!(parse_mask_string(b"").is_empty());
+    }
+
+    #[test]
+    fn reads_weights_by_accession() {
+        let path = write_file(
+            "weights",
+            "# accession\tweight\n\nseq1\t2.5\nseq3\t 0.5 \nmissing\t3\n",
+        );
+        let headers = [b"seq1 first".to_vec(), b"seq2".to_vec(), b"seq3".to_vec()];
+        let weights = read_weights(&path, &headers).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(weights, [2.5, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn rejects_invalid_weight_lines() {
+        for (name, text, message) in [
+            ("weights-field", "seq1\t1\nseq2 1\n", "line 2 must contain"),
+            (
+                "weights-number",
+                "seq1\tabc\n",
+                "invalid weight 'abc' on line 1",
+            ),
+            (
+                "weights-zero",
+                "# header\nseq1\t0\n",
+                "line 2 must be a positive",
+            ),
+            (
+                "weights-negative",
+                "seq1\t-1\n",
+                "line 1 must be a positive",
+            ),
+            (
+                "weights-infinite",
+                "seq1\tinf\n",
+                "line 1 must be a positive",
+            ),
+        ] {
+            let path = write_file(name, text);
+            let error = read_weights(&path, &[b"seq1".to_vec()]).unwrap_err();
+            std::fs::remove_file(&path).unwrap();
+            assert!(error.to_string().contains(message), "{error}");
+        }
+    }
+
+    #[test]
+    fn keeps_tiny_weights_positive() {
+        let weights = Weights::new(&[0.0001, 0.0004, 0.0006, 1.0, 2.5, 0.0, -1.0]);
+        assert_eq!(
+            (0..weights.len())
+                .map(|idx| weights.get(idx))
+                .collect::<Vec<_>>(),
+            [1, 1, 1, WEIGHT_SCALE, 2500, 0, 0]
+        );
+        assert_eq!(weights.total(), 3 + 3500);
+        assert_eq!(weights.scale(), WEIGHT_SCALE);
+        assert!(!weights.is_uniform());
+    }
+
+    #[test]
+    fn computes_henikoff_weights() {
+        // Column 1: each A counts 1/4 and G 1/2, and the gap nothing. Column 2: A
+        // counts 1/2 and each C 1/6. The raw weights 3/4, 5/12, 2/3 and 1/6 sum
+        // to 2.
+        let sequences = [
+            b"AA".to_vec(),
+            b"AC".to_vec(),
+            b"gC".to_vec(),
+            b"-C".to_vec(),
+        ];
+        let weights = henikoff_weights(&sequences, &GapChars::default());
+        let expected = [1.5, 5.0 / 6.0, 4.0 / 3.0, 1.0 / 3.0];
+        assert_eq!(weights.len(), expected.len());
+        for (weight, expected) in weights.iter().zip(expected) {
+            assert!((weight - expected).abs() < 1e-12, "{weights:?}");
+        }
+        assert_eq!(
+            henikoff_weights(&[b"--".to_vec()], &GapChars::default()),
+            [0.0]
+        );
+    }
+}